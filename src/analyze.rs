@@ -1,16 +1,14 @@
 use std::cell::SyncUnsafeCell;
-use std::io::{self, Read, Write};
-use std::net::TcpStream;
-use std::process::Command;
-use std::sync::{Arc, Mutex};
+use std::io::{self, Write};
+use std::sync::Mutex;
 
 use anyhow::anyhow;
 use crossterm::style::{style, Stylize};
-use ssh2::Session;
 
 use crate::cli::Analyze;
 use crate::config::Config;
-use crate::SSH_PASS;
+use crate::fleet;
+use crate::target::{Target, COMMAND_TIMEOUT, INSTALL_TIMEOUT};
 
 static MIN_KERNEL_VERSION: &str = "5.17.0";
 static mut UBUNTU_PACKAGES: [&str; 5] = [
@@ -30,6 +28,11 @@ pub async fn analyze(options: Analyze, config: Config) -> Result<bool, anyhow::E
     let mut error_messages: Vec<anyhow::Error> = Vec::new();
     let mut skip_flag_check = false;
 
+    if options.noconfirm.is_none() && fleet::is_host() {
+        return Err(anyhow!(
+            "Config and package installation can't be confirmed on inventory hosts. Run with --noconfirm"
+        ));
+    }
     if options.noconfirm.is_none() {
         println!("{}\n", "- CONFIG -".on_blue().black());
         let mut action = String::new();
@@ -50,202 +53,63 @@ pub async fn analyze(options: Analyze, config: Config) -> Result<bool, anyhow::E
         }
     }
 
-    let hostname = config.init.as_ref().unwrap().hostname.as_ref();
-    let port = config.init.as_ref().unwrap().port.as_ref();
+    let target = Target::connect(config.init.as_ref().unwrap(), "Analyze".blue().bold())?;
 
-    if hostname.is_none()
-        || *hostname.as_ref().unwrap() == "localhost"
-        || *hostname.as_ref().unwrap() == "127.0.0.1"
-    {
-        let mut output =
-            String::from_utf8(Command::new("uname").arg("-r").output().unwrap().stdout)?;
-        println!("{}", "- Kernel Version Check -".on_blue().black());
-        println!(
-            "{}: Kernel version: {}",
-            "Analyze".blue().bold(),
-            &output.trim()
-        );
-        match check_kernel_version(&output) {
-            Ok(_) => (),
-            Err(e) => {
-                total_errors += 1;
-                error_messages.push(e);
-            }
-        };
-
-        println!("{}", "- Required Packages Check -".on_blue().black());
-        output = String::from_utf8(Command::new("uname").arg("-n").output().unwrap().stdout)?;
-
-        match check_packages(options, output.trim()).await {
-            Ok(_) => (),
-            Err(e) => {
-                unsafe {
-                    let pkgs = MISSING_PACKAGES.get().as_mut().unwrap();
-                    skip_flag_check = pkgs.lock().unwrap().contains(&"ripgrep");
-                }
-                total_errors += 1;
-                error_messages.push(e);
+    println!("{}", "- Kernel Version Check -".on_blue().black());
+    match check_kernel_version_target(&target) {
+        Ok(_) => (),
+        Err(e) => {
+            total_errors += 1;
+            error_messages.push(e);
+        }
+    };
+
+    println!("{}", "- Required Packages Check -".on_blue().black());
+    match check_packages(options, &target) {
+        Ok(_) => (),
+        Err(e) => {
+            unsafe {
+                let pkgs = MISSING_PACKAGES.get().as_mut().unwrap();
+                skip_flag_check = pkgs.get_mut().unwrap().contains(&"ripgrep");
             }
-        };
-
-        println!("{}", "- Kernel Flags Check -".on_blue().black());
-
-        if !skip_flag_check {
-            output = String::from_utf8(
-                Command::new("sh")
-                    .args(["-c", "sudo bpftool feature | rg -w 'CONFIG_BPF|CONFIG_BPF_SYSCALL|CONFIG_BPF_JIT|CONFIG_BPF_EVENTS'"])
-                    .output()
-                    .unwrap()
-                .stdout,
-            )?;
-            match check_bpf_enabled(output.trim().split("\n").collect()) {
-                Ok(_) => (),
-                Err(e) => {
-                    total_errors += 1;
-                    error_messages.push(e);
-                }
-            };
-        } else {
-            println!(
-                "{}: Required kernel flags {}\n",
-                "Analyze".blue().bold(),
-                "(not ok)".red().bold()
-            );
-            error_messages.push(anyhow!(
-                "Cannot check kernel flags without {} package",
-                "ripgrep".bold()
-            ));
             total_errors += 1;
+            error_messages.push(e);
         }
+    };
 
-        println!("{}", "- Network Interface Check -".on_blue().black());
-        output = String::from_utf8(
-            Command::new("sh")
-                .args(["-c", "ip -o link show | awk -F': ' '{{print $2}}'"])
-                .output()
-                .unwrap()
-                .stdout,
-        )?;
-        match check_net_iface(
-            config.init.as_ref().unwrap().iface.as_ref().unwrap(),
-            output.split("\n").collect(),
-        ) {
+    println!("{}", "- Kernel Flags Check -".on_blue().black());
+    if !skip_flag_check {
+        match check_bpf_enabled_target(&target) {
             Ok(_) => (),
             Err(e) => {
                 total_errors += 1;
                 error_messages.push(e);
             }
         };
-    } else if hostname.is_some() {
-        let tcp =
-            TcpStream::connect(format!("{}:{}", hostname.unwrap(), port.unwrap_or(&22))).unwrap();
-        let mut session = Session::new().unwrap();
-        session.set_tcp_stream(tcp);
-        session.handshake().unwrap();
-
-        let mut username: String = String::new();
-        if config.init.as_ref().unwrap().username.is_none() {
-            print!("Username: ");
-            io::stdout().flush()?;
-            io::stdin().read_line(&mut username)?;
-        } else {
-            username = config
-                .init
-                .as_ref()
-                .unwrap()
-                .username
-                .as_ref()
-                .unwrap()
-                .to_string();
-            println!(
-                "{}: Using username \"{}\"",
-                "Analyze".blue().bold(),
-                &username
-            );
-        }
-
-        let password: String;
-        unsafe {
-            let pass = (*SSH_PASS.get()).lock().unwrap();
-            if !pass.is_empty() {
-                password = (*pass).clone();
-            } else {
-                password = rpassword::prompt_password("Password: ")?;
-            }
-        }
-
-        session.userauth_password(username.trim(), password.trim())?;
-
-        unsafe {
-            let pass = (*SSH_PASS.get()).lock().unwrap();
-            if pass.is_empty() {
-                let new = SSH_PASS.get().as_mut().unwrap();
-                *new = Mutex::new(password.clone());
-            }
-        }
-
+    } else {
         println!(
-            "{}: Connected to {}\n",
+            "{}: Required kernel flags {}\n",
             "Analyze".blue().bold(),
-            hostname.unwrap()
+            "(not ok)".red().bold()
         );
+        error_messages.push(anyhow!(
+            "Cannot check kernel flags without {} package",
+            "ripgrep".bold()
+        ));
+        total_errors += 1;
+    }
 
-        println!("{}", "- Kernel Version Check -".on_blue().black());
-        match check_kernel_version_remote(&mut session) {
-            Ok(_) => (),
-            Err(e) => {
-                total_errors += 1;
-                error_messages.push(e);
-            }
-        };
-
-        println!("{}", "- Required Packages Check -".on_blue().black());
-        match check_packages_remote(options, &mut session, &password) {
-            Ok(_) => (),
-            Err(e) => {
-                unsafe {
-                    let pkgs = MISSING_PACKAGES.get().as_mut().unwrap();
-                    skip_flag_check = pkgs.lock().unwrap().contains(&"ripgrep");
-                }
-                total_errors += 1;
-                error_messages.push(e);
-            }
-        };
-
-        println!("{}", "- Kernel Flags Check -".on_blue().black());
-        if !skip_flag_check {
-            match check_bpf_enabled_remote(&mut session, &password) {
-                Ok(_) => (),
-                Err(e) => {
-                    total_errors += 1;
-                    error_messages.push(e);
-                }
-            };
-        } else {
-            println!(
-                "{}: Required kernel flags {}\n",
-                "Analyze".blue().bold(),
-                "(not ok)".red().bold()
-            );
-            error_messages.push(anyhow!(
-                "Cannot check kernel flags without {} package",
-                "ripgrep".bold()
-            ));
+    println!("{}", "- Network Interface Check -".on_blue().black());
+    match check_net_iface_target(
+        &target,
+        config.init.as_ref().unwrap().iface.as_ref().unwrap(),
+    ) {
+        Ok(_) => (),
+        Err(e) => {
             total_errors += 1;
+            error_messages.push(e);
         }
-
-        println!("{}", "- Network Interface Check -".on_blue().black());
-        match check_net_iface_remote(
-            &mut session,
-            config.init.as_ref().unwrap().iface.as_ref().unwrap(),
-        ) {
-            Ok(_) => (),
-            Err(e) => {
-                total_errors += 1;
-                error_messages.push(e);
-            }
-        };
-    }
+    };
 
     let total_errors_display;
     if total_errors != 0 {
@@ -268,6 +132,10 @@ pub async fn analyze(options: Analyze, config: Config) -> Result<bool, anyhow::E
         "Analyze".blue().bold(),
         total_errors_display
     );
+    // Inventory rollout counts hosts with problems as failed
+    if fleet::is_host() && total_errors != 0 {
+        return Err(anyhow!("Analysis found {} problems", total_errors));
+    }
     Ok(true)
 }
 
@@ -331,176 +199,20 @@ fn check_kernel_version(version: &str) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-fn check_kernel_version_remote(session: &mut Session) -> Result<(), anyhow::Error> {
-    let mut channel = session.channel_session().unwrap();
-    channel.exec("uname -r").unwrap();
-    let mut output = String::new();
-    channel.read_to_string(&mut output).unwrap();
+fn check_kernel_version_target(target: &Target) -> Result<(), anyhow::Error> {
+    let output = target.exec("uname -r", COMMAND_TIMEOUT)?.stdout;
 
     println!(
         "{}: Kernel version: {}",
         "Analyze".blue().bold(),
-        &output.trim()
+        output.trim()
     );
     check_kernel_version(&output)?;
-    channel.wait_close()?;
     Ok(())
 }
 
-async fn check_packages(options: Analyze, nodename: &str) -> Result<(), anyhow::Error> {
-    let missing_pkgs: Arc<Mutex<Vec<&str>>> = Arc::new(Mutex::new(Vec::new()));
-
-    match nodename {
-        "ubuntu" => unsafe {
-            let tasks = UBUNTU_PACKAGES.map(|mut pkg| {
-                let missing_pkgs: Arc<Mutex<Vec<&str>>> = missing_pkgs.clone();
-                if pkg == "linux-tools-generic" {
-                    let output =
-                        String::from_utf8(Command::new("uname").arg("-r").output().unwrap().stdout)
-                            .unwrap();
-                    pkg = ("linux-tools-".to_string() + output.leak()).leak().trim();
-                }
-                tokio::spawn(async move {
-                    let output = String::from_utf8(
-                        tokio::process::Command::new("sh")
-                            .args(["-c", format!("apt -qq list {}", pkg).as_str()])
-                            .output()
-                            .await
-                            .unwrap()
-                            .stdout,
-                    )
-                    .expect("Failed to get package");
-
-                    if !output.contains("[installed]") {
-                        missing_pkgs.lock().unwrap().push(pkg);
-                    } else {
-                        println!(
-                            "{}: Package \"{}\" is installed.",
-                            "Analyze".blue().bold(),
-                            pkg.bold()
-                        );
-                    }
-                })
-            });
-            for t in tasks {
-                t.await?
-            }
-        },
-        "archlinux" => {
-            let output = String::from_utf8(
-                Command::new("sh")
-                    .args([
-                        "-c",
-                        format!("pacman -Qqen | grep -wE '{}'", ARCH_PACKAGES.join("|")).as_str(),
-                    ])
-                    .output()
-                    .unwrap()
-                    .stdout,
-            )?;
-
-            for pkg in ARCH_PACKAGES {
-                if !output.contains(pkg) {
-                    missing_pkgs.lock().unwrap().push(pkg);
-                } else {
-                    println!(
-                        "{}: Package \"{}\" is installed.",
-                        "Analyze".blue().bold(),
-                        pkg.bold()
-                    );
-                }
-            }
-        }
-        _ => return Err(anyhow!("Unsupported OS")),
-    }
-
-    if !missing_pkgs.lock().unwrap().is_empty() {
-        if options.noconfirm.is_none() {
-            let mut action = String::new();
-            println!(
-                "{}: Missing packages:\n - {}",
-                "Analyze".blue(),
-                missing_pkgs.lock().unwrap().join("\n - ")
-            );
-            print!(
-                "{}: Attempt to install missing packages? [Y/n] ",
-                "Analyze".blue().bold()
-            );
-            io::stdout().flush()?;
-            io::stdin().read_line(&mut action)?;
-
-            let action = action.trim().to_lowercase();
-
-            if action != "y" && action != "yes" && !action.is_empty() {
-                println!(
-                    "{}: Required packages {}\n",
-                    "Analyze".blue().bold(),
-                    "(not ok)".red().bold(),
-                );
-                unsafe {
-                    let pkgs = MISSING_PACKAGES.get().as_mut().unwrap();
-                    pkgs.lock()
-                        .unwrap()
-                        .append(&mut missing_pkgs.lock().unwrap().clone());
-                }
-
-                return Err(anyhow!(format!(
-                    "Missing packages:\n - {}",
-                    missing_pkgs.lock().unwrap().join("\n - ")
-                )));
-            }
-        }
-
-        println!(
-            "{}: Installing missing packages:\n - {}\n",
-            "Analyze".blue().bold(),
-            missing_pkgs.lock().unwrap().join("\n - ")
-        );
-        match nodename {
-            "ubuntu" => {
-                println!("{}: please wait...", "analyze".blue().bold(),);
-                Command::new("sh").args([
-                    "-c",
-                    format!(
-                        "sudo apt install --assume-yes {}",
-                        missing_pkgs.lock().unwrap().join(" ")
-                    )
-                    .as_str(),
-                ]);
-            }
-            "archlinux" => {
-                println!("{}: please wait...", "analyze".blue().bold(),);
-                Command::new("sudo")
-                    .arg("pacman")
-                    .arg("--noconfirm")
-                    .arg("-S")
-                    .arg(missing_pkgs.lock().unwrap().join(" "))
-                    .output()?;
-            }
-            _ => return Err(anyhow!("Unsupported OS")),
-        }
-        println!(
-            "{}: Missing packages installed succefully.\n",
-            "Analyze".blue().bold()
-        );
-    } else {
-        println!(
-            "{}: Required packages {}\n",
-            "Analyze".blue().bold(),
-            "(ok)".green().bold(),
-        );
-    }
-    Ok(())
-}
-
-fn check_packages_remote(
-    options: Analyze,
-    session: &mut Session,
-    password: &str,
-) -> Result<(), anyhow::Error> {
-    let mut channel = session.channel_session().unwrap();
-    channel.exec("uname -n").unwrap();
-    let mut output = String::new();
-    channel.read_to_string(&mut output).unwrap();
+fn check_packages(options: Analyze, target: &Target) -> Result<(), anyhow::Error> {
+    let mut output = target.exec("uname -n", COMMAND_TIMEOUT)?.stdout;
     let mut missing_pkgs: Vec<&str> = Vec::new();
 
     let nodename = output.clone().trim().to_string();
@@ -508,12 +220,9 @@ fn check_packages_remote(
     match nodename.as_str() {
         "ubuntu" => unsafe {
             for pkg in UBUNTU_PACKAGES {
-                output = String::new();
-                let mut channel = session.channel_session().unwrap();
-                channel
-                    .exec(format!("apt -qq list {}", pkg).as_str())
-                    .unwrap();
-                channel.read_to_string(&mut output).unwrap();
+                output = target
+                    .run(&format!("apt -qq list {}", pkg), COMMAND_TIMEOUT)?
+                    .stdout;
 
                 if !output.contains("[installed]") {
                     missing_pkgs.push(pkg);
@@ -524,17 +233,15 @@ fn check_packages_remote(
                         pkg.bold()
                     );
                 }
-
-                channel.wait_close()?;
             }
         },
         "archlinux" => {
-            let mut channel = session.channel_session().unwrap();
-            channel
-                .exec(format!("pacman -Qqen | grep {}", ARCH_PACKAGES.join(" ")).as_str())
-                .unwrap();
-            channel.read_to_string(&mut output).unwrap();
-            channel.wait_close()?;
+            output = target
+                .run(
+                    &format!("pacman -Qqen | grep -wE '{}'", ARCH_PACKAGES.join("|")),
+                    COMMAND_TIMEOUT,
+                )?
+                .stdout;
 
             for pkg in ARCH_PACKAGES {
                 if !output.contains(pkg) {
@@ -571,7 +278,7 @@ fn check_packages_remote(
             if action != "y" && action != "yes" && !action.is_empty() {
                 unsafe {
                     let pkgs = MISSING_PACKAGES.get().as_mut().unwrap();
-                    pkgs.lock().unwrap().append(&mut missing_pkgs.clone());
+                    pkgs.get_mut().unwrap().append(&mut missing_pkgs.clone());
                 }
                 return Err(anyhow!(format!(
                     "Missing packages:\n - {}",
@@ -582,38 +289,16 @@ fn check_packages_remote(
 
         match nodename.as_str() {
             "ubuntu" => {
-                output = String::new();
-                channel = session.channel_session().unwrap();
-                channel
-                    .exec(
-                        format!(
-                            "echo {} | sudo -S apt install --assume-yes {}",
-                            password,
-                            missing_pkgs.join(" ")
-                        )
-                        .as_str(),
-                    )
-                    .unwrap();
-                channel.read_to_string(&mut output).unwrap();
-
-                channel.wait_close()?;
+                target.exec(
+                    &format!("apt install --assume-yes {}", missing_pkgs.join(" ")),
+                    INSTALL_TIMEOUT,
+                )?;
             }
             "archlinux" => {
-                output = String::new();
-                channel = session.channel_session().unwrap();
-                channel
-                    .exec(
-                        format!(
-                            "echo {} | sudo -S pacman --noconfirm -S {}",
-                            password,
-                            missing_pkgs.join(" ")
-                        )
-                        .as_str(),
-                    )
-                    .unwrap();
-                channel.read_to_string(&mut output).unwrap();
-
-                channel.wait_close()?;
+                target.exec(
+                    &format!("pacman --noconfirm -S {}", missing_pkgs.join(" ")),
+                    INSTALL_TIMEOUT,
+                )?;
             }
             _ => return Err(anyhow!("Unsupported OS: {}", output)),
         }
@@ -628,7 +313,6 @@ fn check_packages_remote(
             "(ok)".green().bold(),
         );
     }
-    channel.wait_close()?;
     Ok(())
 }
 
@@ -656,32 +340,20 @@ fn check_bpf_enabled(flags: Vec<&str>) -> Result<(), anyhow::Error> {
 
     Ok(())
 }
-fn check_bpf_enabled_remote(session: &mut Session, password: &str) -> Result<(), anyhow::Error> {
-    let mut channel = session.channel_session().unwrap();
-    channel
-        .exec( format!(
-            "echo {} | (sudo bpftool feature | rg -w 'CONFIG_BPF|CONFIG_BPF_SYSCALL|CONFIG_BPF_JIT|CONFIG_BPF_EVENTS')",
-            password
-        )
-            .as_str(),
-        )
-        .unwrap();
-    let mut output = String::new();
-    channel.read_to_string(&mut output).unwrap();
-    channel.wait_close()?;
-
-    channel = session.channel_session().unwrap();
-    channel
-        .exec(
-            format!(
-                "echo {} | (sudo -S bpftool feature | rg -w 'CONFIG_HAVE_EBPF_JIT|CONFIG_HAVE_BPF_JIT')",
-                password
-            )
-            .as_str(),
-        )
-        .unwrap();
-    channel.read_to_string(&mut output).unwrap();
-    channel.wait_close()?;
+fn check_bpf_enabled_target(target: &Target) -> Result<(), anyhow::Error> {
+    // rg exits with 1 when no flag is found, which is reported below
+    let mut output = target
+        .run(
+            "bpftool feature | rg -w 'CONFIG_BPF|CONFIG_BPF_SYSCALL|CONFIG_BPF_JIT|CONFIG_BPF_EVENTS'",
+            COMMAND_TIMEOUT,
+        )?
+        .stdout;
+    output += &target
+        .run(
+            "bpftool feature | rg -w 'CONFIG_HAVE_EBPF_JIT|CONFIG_HAVE_BPF_JIT'",
+            COMMAND_TIMEOUT,
+        )?
+        .stdout;
 
     let flags: Vec<&str> = output.trim().split("\n").collect();
     check_bpf_enabled(flags)?;
@@ -710,21 +382,17 @@ fn check_net_iface(iface: &str, ifaces: Vec<&str>) -> Result<(), anyhow::Error>
     Ok(())
 }
 
-fn check_net_iface_remote(session: &mut Session, iface: &str) -> Result<(), anyhow::Error> {
+fn check_net_iface_target(target: &Target, iface: &str) -> Result<(), anyhow::Error> {
     println!("{}: Checking network interfaces", "Analyze".blue().bold());
-    let mut channel = session.channel_session().unwrap();
-    channel
-        .exec(
-            "ip -o link show | awk -F': ' '{print $2}'"
-                .to_string()
-                .as_str(),
-        )
-        .unwrap();
-    let mut output = String::new();
-    channel.read_to_string(&mut output).unwrap();
-    channel.wait_close()?;
-
-    let ifaces: Vec<&str> = output.split("\n").collect();
+    let output = target
+        .exec("ip -o link show | awk -F': ' '{print $2}'", COMMAND_TIMEOUT)?
+        .stdout;
+
+    // veth interfaces are listed as name@peer
+    let ifaces: Vec<&str> = output
+        .split("\n")
+        .map(|i| i.split('@').next().unwrap_or_default())
+        .collect();
     check_net_iface(iface, ifaces)?;
 
     Ok(())
@@ -0,0 +1,89 @@
+use crossterm::style::Stylize;
+use libbpf_rs::{MapCore, MapFlags, MapImpl};
+
+use crate::{
+    config::{Init, List, DEFAULT_FILL_WARNING},
+    geo,
+};
+
+/// Overflow map indexes. Same order as control map.
+pub static OVERFLOW_WHITELIST: u32 = 0;
+pub static OVERFLOW_BLACKLIST: u32 = 1;
+pub static OVERFLOW_GRAYLIST: u32 = 2;
+pub static OVERFLOW_SIZE: usize = 3;
+
+/// Max entries and eviction of enabled list.
+pub fn capacity(init: &Init, list: &str) -> Option<(u32, bool)> {
+    let list: Option<&dyn List> = match list {
+        "whitelist" => init
+            .whitelist
+            .as_ref()
+            .filter(|l| l.enabled.unwrap_or(false))
+            .map(|l| l as &dyn List),
+        "blacklist" => init
+            .blacklist
+            .as_ref()
+            .filter(|l| l.enabled.unwrap_or(false))
+            .map(|l| l as &dyn List),
+        "graylist" => init
+            .graylist
+            .as_ref()
+            .filter(|l| l.enabled.unwrap_or(false))
+            .map(|l| l as &dyn List),
+        _ => None,
+    };
+    list.map(|l| (l.get_max(), l.get_evict()))
+}
+
+/// Warning if list is filled past init.fill_warning percent.
+pub fn fill_warning(init: &Init, list: &str, used: usize) -> Option<String> {
+    let (max, evict) = capacity(init, list)?;
+    let threshold = init.fill_warning.unwrap_or(DEFAULT_FILL_WARNING);
+    let percent = used * 100 / (max.max(1) as usize);
+
+    if percent < threshold as usize {
+        return None;
+    }
+
+    Some(format!(
+        "{} is {}% full ({}/{}). {}",
+        list,
+        percent,
+        used,
+        max,
+        if evict {
+            "Oldest entries will be evicted"
+        } else {
+            "New entries will be rejected"
+        }
+    ))
+}
+
+/// Prints failed insertions and fill warning of list after its data was loaded.
+pub fn report(init: &Init, list: &str, data: &[String], failed: usize) {
+    let loaded = data.iter().filter(|e| !geo::is_geo_entry(e)).count() - failed;
+    if failed > 0 {
+        println!(
+            "{}: {} {} entries did not fit and were not loaded. Increase max",
+            "Load".red().bold(),
+            failed,
+            list
+        );
+    }
+    if let Some(w) = fill_warning(init, list, loaded) {
+        println!("{}: {}", "Load".red().bold(), w);
+    }
+}
+
+/// Insertions rejected by kernel, in overflow map index order.
+pub fn overflow_counts(map: &MapImpl) -> Result<Vec<u64>, anyhow::Error> {
+    let mut counts = Vec::new();
+    for i in 0..OVERFLOW_SIZE as u32 {
+        let count = match map.lookup(&i.to_ne_bytes(), MapFlags::ANY)? {
+            Some(v) => u64::from_ne_bytes(v[0..8].try_into()?),
+            None => 0,
+        };
+        counts.push(count);
+    }
+    Ok(counts)
+}
@@ -1,4 +1,6 @@
-use clap::{arg, Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+
+use crate::config::XdpMode;
 
 // Main command options
 #[derive(Parser, Debug)]
@@ -6,9 +8,18 @@ use clap::{arg, Args, Parser, Subcommand};
 pub struct Options {
     #[command(subcommand)]
     pub command: Commands,
-    /// Config file name in TOML/JSON format.
+    /// Config file name in TOML/JSON/YAML format. Repeat to layer files, later ones win.
     #[arg(short, long, global = true)]
-    pub config: Option<String>,
+    pub config: Vec<String>,
+    /// Override config value, f.e. --set init.iface=lo. Can be repeated.
+    #[arg(long = "set", global = true, value_name = "PATH=VALUE")]
+    pub set: Vec<String>,
+    /// Program of multi-program config to act on. Defaults to all of them.
+    #[arg(long, global = true)]
+    pub program: Option<String>,
+    /// Profile of config to apply, f.e. --profile lab
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
 }
 
 // Subcommands
@@ -29,6 +40,42 @@ pub enum Commands {
     Secret,
     /// Embed and run Lua script
     Run(Run),
+    /// Show network prefixes a country or ASN entry expands to
+    Geo(Geo),
+    /// Enforces list and action schedules of loaded eBPF program
+    Schedule(Schedule),
+    /// Configuration tools
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Creates a config by asking questions
+    Init(Init),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Check configuration and report every problem found
+    Validate,
+    /// Print merged configuration
+    Show {
+        /// Print every value with the layer it came from
+        #[arg(long)]
+        resolved: bool,
+    },
+    /// Upgrade config files to current version in place
+    Migrate {
+        /// Files to upgrade. Defaults to files given with -c
+        files: Vec<String>,
+    },
+    /// Convert config file between JSON, TOML and YAML. Format is taken from extensions
+    Convert {
+        /// Config to convert
+        input: String,
+        /// File to write, f.e. config.toml
+        output: String,
+        /// Overwrite output if it exists
+        #[arg(long)]
+        force: bool,
+    },
 }
 
 // Commands
@@ -40,40 +87,82 @@ pub struct Run {
 }
 
 #[derive(Args, Debug)]
+pub struct Init {
+    /// Where to write config. Asked for if not set.
+    #[arg(short, long)]
+    pub output: Option<String>,
+    /// Overwrite existing file without asking
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct Geo {
+    /// Country or ASN entry, f.e. country:RU or asn:AS14061
+    pub entry: String,
+    /// MMDB file to resolve from. Defaults to init.mmdb from config.
+    #[arg(short, long)]
+    pub mmdb: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct Schedule {
+    /// How often to check schedules, in seconds.
+    #[arg(short, long, default_value_t = 30)]
+    pub interval: u64,
+}
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct Fleet {
+    /// Inventory of hosts to run on in parallel, f.e. hosts.toml
+    #[arg(long)]
+    pub inventory: Option<String>,
+    /// Only hosts of this inventory group. Can be repeated.
+    #[arg(long, requires = "inventory")]
+    pub group: Vec<String>,
+}
+
+#[derive(Args, Debug, Clone)]
 pub struct Analyze {
     /// Skip confirmation of the configuration, package installation.
     #[arg(long, default_missing_value = "", num_args = 0)]
     pub noconfirm: Option<String>,
+    #[command(flatten)]
+    pub fleet: Fleet,
 }
 
-#[derive(Args, Debug)]
+#[derive(Args, Debug, Clone)]
 pub struct Generate {
     /// Skip confirmation of the configuration
     #[arg(long, default_missing_value = "", num_args = 0)]
     pub noconfirm: Option<String>,
 }
 
-#[derive(Args, Debug)]
+#[derive(Args, Debug, Clone)]
 pub struct Load {
     /// Interface name.
     #[arg(short, long, default_value = "")]
     pub iface: String,
-    /// XdpFlags to pass to XDP framework. Available options: generic, native, offloaded.
-    #[arg(long, default_value = "generic")]
-    pub xdp_flags: String,
+    /// XDP mode to attach program in. Defaults to init.xdp_mode, then generic.
+    #[arg(long, value_enum, ignore_case = true)]
+    pub xdp_flags: Option<XdpMode>,
+    #[command(flatten)]
+    pub fleet: Fleet,
 }
 
-#[derive(Args, Debug)]
+#[derive(Args, Debug, Clone)]
 pub struct Unload {
     /// Interface name.
     #[arg(short, long, default_value = "")]
     pub iface: String,
-    /// XdpFlags to pass to XDP framework. Available options: generic, native, offloaded.
-    #[arg(long, default_value = "generic")]
-    pub xdp_flags: String,
+    /// XDP mode the program was attached in. Defaults to init.xdp_mode, then generic.
+    #[arg(long, value_enum, ignore_case = true)]
+    pub xdp_flags: Option<XdpMode>,
     /// Program ID.
     #[arg(short, long, default_value = "")]
     pub pid: String,
+    #[command(flatten)]
+    pub fleet: Fleet,
 }
 
 #[derive(Subcommand, Debug)]
@@ -84,6 +173,8 @@ pub enum Get {
     ExampleConfig(ConfOutputType),
     /// Get starter configuration
     BaseConfig(ConfOutputType),
+    /// Get JSON Schema of configuration
+    Schema,
     /// Get available Lua API
     LuaApi,
     #[command(subcommand, subcommand_help_heading = "Functions", subcommand_value_name = "FUNCTION")]
@@ -101,6 +192,12 @@ pub struct ConfOutputType {
     /// Visualize configuration
     #[arg(short, long, default_missing_value = "", num_args = 0)]
     pub formatted: Option<String>,
+    /// Format to TOML format
+    #[arg(short, long, default_missing_value = "", num_args = 0)]
+    pub toml: Option<String>,
+    /// Format to YAML format
+    #[arg(short, long, default_missing_value = "", num_args = 0)]
+    pub yaml: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
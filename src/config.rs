@@ -1,7 +1,17 @@
-use std::fmt::Display;
+use std::{borrow::Cow, collections::BTreeMap, fmt::Display, fs, path::Path, str::FromStr};
 
+use anyhow::{anyhow, Context};
+use clap::{builder::PossibleValue, ValueEnum};
 use crossterm::style::Stylize;
-use serde::{Deserialize, Serialize};
+use schemars::{JsonSchema, Schema, SchemaGenerator};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::{
+    interpolate, layers,
+    migrate::{self, CURRENT_VERSION},
+    target::TargetKind,
+};
 
 pub static DEFAULT_NET_IFACE: &str = "eth0";
 pub static DEFAULT_FREQUENCY: u32 = 1000;
@@ -9,74 +19,821 @@ pub static DEFAULT_FAST_PACKETS: u32 = 100;
 
 pub static DEFAULT_NAME: &str = "ExampleProgram";
 static DEFAULT_MAX_IPS: u32 = 32;
-static DEFAULT_WHITELIST_ACTION: &str = "allow";
-static DEFAULT_BLACKLIST_ACTION: &str = "deny";
-static DEFAULT_GRAYLIST_ACTION: &str = "investigate";
+static DEFAULT_PROG_TYPE: ProgType = ProgType::Ip;
+static DEFAULT_XDP_ACTION: XdpAction = XdpAction::Pass;
+pub static DEFAULT_XDP_MODE: XdpMode = XdpMode::Generic;
+static DEFAULT_WHITELIST_ACTION: ListAction = ListAction::Allow;
+static DEFAULT_BLACKLIST_ACTION: ListAction = ListAction::Deny;
+static DEFAULT_GRAYLIST_ACTION: ListAction = ListAction::Investigate;
+pub static DEFAULT_BRUTEFORCE_ATTEMPTS: u32 = 5;
+pub static DEFAULT_BRUTEFORCE_WINDOW: u32 = 60000;
+pub static DEFAULT_BRUTEFORCE_MAX: u32 = 1024;
+pub static DEFAULT_FILL_WARNING: u32 = 80;
+/// DNS, NTP, memcached, SSDP, CLDAP
+pub static DEFAULT_AMPLIFICATION_PORTS: [u16; 5] = [53, 123, 11211, 1900, 389];
+pub static DEFAULT_AMPLIFICATION_REQUESTS: u32 = 100;
+pub static DEFAULT_AMPLIFICATION_WINDOW: u32 = 1000;
+pub static DEFAULT_AMPLIFICATION_RATIO: u32 = 10;
+pub static DEFAULT_AMPLIFICATION_BYTES: u32 = 65536;
+pub static DEFAULT_AMPLIFICATION_MAX: u32 = 4096;
+static DEFAULT_AMPLIFICATION_ACTION: ListAction = ListAction::Investigate;
+static DEFAULT_HOST_KEY_CHECK: HostKeyCheck = HostKeyCheck::Ask;
+static DEFAULT_BUILD: BuildMode = BuildMode::Local;
+static DEFAULT_SSH_AUTH: [SshAuth; 3] = [SshAuth::Agent, SshAuth::Key, SshAuth::Password];
+
+// Defaults shown in JSON Schema. Same values getters fall back to.
+fn default_version() -> Option<u32> {
+    Some(CURRENT_VERSION)
+}
+fn default_name() -> Option<String> {
+    Some(DEFAULT_NAME.to_string())
+}
+fn default_port() -> Option<u16> {
+    Some(22)
+}
+
+fn default_ssh_auth() -> Option<Vec<SshAuth>> {
+    Some(DEFAULT_SSH_AUTH.to_vec())
+}
+
+fn default_host_key_check() -> Option<HostKeyCheck> {
+    Some(DEFAULT_HOST_KEY_CHECK)
+}
+fn default_build() -> Option<BuildMode> {
+    Some(DEFAULT_BUILD)
+}
+fn default_iface() -> Option<String> {
+    Some(DEFAULT_NET_IFACE.to_string())
+}
+fn default_prog_type() -> Option<ProgType> {
+    Some(DEFAULT_PROG_TYPE)
+}
+fn default_xdp_action() -> Option<XdpAction> {
+    Some(DEFAULT_XDP_ACTION)
+}
+fn default_xdp_mode() -> Option<XdpMode> {
+    Some(DEFAULT_XDP_MODE)
+}
+fn default_fill_warning() -> Option<u32> {
+    Some(DEFAULT_FILL_WARNING)
+}
+fn default_max() -> Option<u32> {
+    Some(DEFAULT_MAX_IPS)
+}
+fn default_true() -> Option<bool> {
+    Some(true)
+}
+fn default_false() -> Option<bool> {
+    Some(false)
+}
+fn default_whitelist_action() -> Option<ListAction> {
+    Some(DEFAULT_WHITELIST_ACTION)
+}
+fn default_blacklist_action() -> Option<ListAction> {
+    Some(DEFAULT_BLACKLIST_ACTION)
+}
+fn default_graylist_action() -> Option<ListAction> {
+    Some(DEFAULT_GRAYLIST_ACTION)
+}
+/// Action of whitelist and blacklist.
+fn allow_deny_schema(_: &mut SchemaGenerator) -> Schema {
+    optional(ListAction::schema_of(&[
+        ListAction::Allow,
+        ListAction::Deny,
+    ]))
+}
+/// Action of amplification.
+fn deny_investigate_schema(_: &mut SchemaGenerator) -> Schema {
+    optional(ListAction::schema_of(&[
+        ListAction::Deny,
+        ListAction::Investigate,
+    ]))
+}
+/// Schema of Option field.
+fn optional(schema: Schema) -> Schema {
+    schemars::json_schema!({ "anyOf": [schema, { "type": "null" }] })
+}
+fn default_frequency() -> Option<u32> {
+    Some(DEFAULT_FREQUENCY)
+}
+fn default_fast_packets() -> Option<u32> {
+    Some(DEFAULT_FAST_PACKETS)
+}
+fn default_bruteforce_attempts() -> Option<u32> {
+    Some(DEFAULT_BRUTEFORCE_ATTEMPTS)
+}
+fn default_bruteforce_window() -> Option<u32> {
+    Some(DEFAULT_BRUTEFORCE_WINDOW)
+}
+fn default_amplification_ports() -> Option<Vec<u16>> {
+    Some(DEFAULT_AMPLIFICATION_PORTS.to_vec())
+}
+fn default_amplification_requests() -> Option<u32> {
+    Some(DEFAULT_AMPLIFICATION_REQUESTS)
+}
+fn default_amplification_window() -> Option<u32> {
+    Some(DEFAULT_AMPLIFICATION_WINDOW)
+}
+fn default_amplification_ratio() -> Option<u32> {
+    Some(DEFAULT_AMPLIFICATION_RATIO)
+}
+fn default_amplification_bytes() -> Option<u32> {
+    Some(DEFAULT_AMPLIFICATION_BYTES)
+}
+fn default_amplification_action() -> Option<ListAction> {
+    Some(DEFAULT_AMPLIFICATION_ACTION)
+}
+
+/// Enum written as a lowercase name. Names are read ignoring case, the same way by config files,
+/// CLI arguments and Lua, so f.e. PASS, Pass and pass are all XdpAction::Pass.
+macro_rules! named_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident ($kind:literal) {
+            $($(#[$variant_meta:meta])* $variant:ident = $value:literal,)+
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)+
+        }
+
+        impl $name {
+            pub const ALL: &[$name] = &[$($name::$variant,)+];
+
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $($name::$variant => $value,)+
+                }
+            }
+
+            /// Schema taking only given variants. Lowercase and uppercase names, for editors.
+            /// hpx accepts any case.
+            pub fn schema_of(variants: &[$name]) -> Schema {
+                let names = variants
+                    .iter()
+                    .flat_map(|v| [v.as_str().to_string(), v.as_str().to_uppercase()])
+                    .collect::<Vec<String>>();
+                schemars::json_schema!({ "type": "string", "enum": names })
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.as_str())
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = anyhow::Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                $name::ALL
+                    .iter()
+                    .find(|v| v.as_str().eq_ignore_ascii_case(s.trim()))
+                    .copied()
+                    .with_context(|| {
+                        format!(
+                            "unsupported {} \"{}\". Expected one of: {}",
+                            $kind,
+                            s,
+                            $name::ALL
+                                .iter()
+                                .map(|v| v.as_str())
+                                .collect::<Vec<&str>>()
+                                .join(", ")
+                        )
+                    })
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer)?
+                    .parse()
+                    .map_err(serde::de::Error::custom)
+            }
+        }
+
+        impl JsonSchema for $name {
+            fn schema_name() -> Cow<'static, str> {
+                stringify!($name).into()
+            }
+
+            fn json_schema(_: &mut SchemaGenerator) -> Schema {
+                $name::schema_of($name::ALL)
+            }
+        }
+
+        impl ValueEnum for $name {
+            fn value_variants<'a>() -> &'a [Self] {
+                $name::ALL
+            }
+
+            fn to_possible_value(&self) -> Option<PossibleValue> {
+                Some(PossibleValue::new(self.as_str()))
+            }
+        }
+    };
+}
+
+named_enum! {
+    /// Type of generated eBPF program.
+    pub enum ProgType ("program type") {
+        Ip = "ip",
+        Dns = "dns",
+        Udp = "udp",
+    }
+}
+
+named_enum! {
+    /// Verdict returned by XDP program.
+    pub enum XdpAction ("XDP action") {
+        Pass = "pass",
+        Drop = "drop",
+    }
+}
+
+named_enum! {
+    /// What to do with packets of listed addresses.
+    pub enum ListAction ("action") {
+        Allow = "allow",
+        Deny = "deny",
+        /// Rate limit, escalating to blacklist. Graylist and amplification only.
+        Investigate = "investigate",
+    }
+}
+
+named_enum! {
+    /// Format of list file.
+    pub enum ListFormat ("list file format") {
+        /// One entry per line.
+        Plain = "plain",
+        /// Column by header name or index.
+        Csv = "csv",
+        /// Array of strings, objects or arrays.
+        Json = "json",
+    }
+}
+
+named_enum! {
+    /// XDP attach mode.
+    pub enum XdpMode ("XDP mode") {
+        Generic = "generic",
+        Native = "native",
+        Offloaded = "offloaded",
+    }
+}
+
+impl XdpMode {
+    /// Mode name used by bpftool net attach/detach.
+    pub fn bpftool_flag(&self) -> &'static str {
+        match self {
+            XdpMode::Generic => "xdpgeneric",
+            XdpMode::Native => "xdpdrv",
+            XdpMode::Offloaded => "xdpoffload",
+        }
+    }
+}
+
+named_enum! {
+    /// SSH login method.
+    pub enum SshAuth ("SSH auth method") {
+        /// Keys of running ssh-agent.
+        Agent = "agent",
+        /// identity_file or default keys in ~/.ssh.
+        Key = "key",
+        Password = "password",
+    }
+}
+
+named_enum! {
+    /// What to do with hosts missing from ~/.ssh/known_hosts.
+    pub enum HostKeyCheck ("host key check") {
+        /// Show fingerprint and ask to trust host. Trusted hosts are added to known_hosts.
+        Ask = "ask",
+        /// Refuse to connect.
+        Strict = "strict",
+    }
+}
+
+named_enum! {
+    /// Where program loaded on SSH host is compiled and against which kernel types (BTF).
+    pub enum BuildMode ("build mode") {
+        /// On this machine against its own kernel.
+        Local = "local",
+        /// On this machine against kernel of the host.
+        TargetBtf = "target-btf",
+        /// On the host from uploaded source. Host needs clang and libbpf headers.
+        Target = "target",
+    }
+}
+
+/// Where SSH and sudo password comes from.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Default)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum PasswordSource {
+    /// Ask on terminal when needed.
+    #[default]
+    Prompt,
+    /// Environment variable holding password.
+    Env(String),
+    /// File holding password on its first line.
+    File(String),
+    /// Command printing password on its first line, f.e. "pass show hosts/edge1".
+    Command(String),
+}
+
+impl Display for PasswordSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordSource::Prompt => write!(f, "prompt"),
+            PasswordSource::Env(v) => write!(f, "environment variable {}", v),
+            PasswordSource::File(p) => write!(f, "file {}", p),
+            PasswordSource::Command(c) => write!(f, "command \"{}\"", c),
+        }
+    }
+}
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    /// JSON Schema of config, for editors. Ignored by hpx.
+    #[serde(rename = "$schema", skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    /// Config format version. Older configs are upgraded when loaded, see hpx config migrate.
+    #[schemars(default = "default_version")]
+    pub version: Option<u32>,
+    /// Program settings.
     pub init: Option<Init>,
-    pub data: Option<Data>
+    /// Addresses loaded into lists.
+    pub data: Option<Data>,
+    /// Several programs in one config. Top-level init and data are shared by all of them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub programs: Option<Vec<Program>>,
+    /// Named sets of init and data overrides, f.e. lab or prod. Chosen with --profile.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profiles: Option<BTreeMap<String, Profile>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Single program of multi-program config. Its values override top-level ones.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Program {
+    /// Program settings. Override top-level init.
+    pub init: Option<Init>,
+    /// Addresses loaded into lists. Override top-level data.
+    pub data: Option<Data>,
+}
+
+/// Overrides applied over config when profile is chosen with --profile.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Overrides init.
+    pub init: Option<Init>,
+    /// Overrides data.
+    pub data: Option<Data>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Init {
+    /// Name of eBPF program. Letters, digits and _, up to 15 characters.
+    #[schemars(default = "default_name")]
     pub name: Option<String>,
+    /// Where program is loaded: "local", "ssh" (hostname) or "netns:<name>" (network namespace
+    /// on local machine). Defaults to ssh when hostname is set, local otherwise.
+    pub target: Option<TargetKind>,
+    /// Host to load program on over SSH. Defaults to local machine.
     pub hostname: Option<String>,
+    /// SSH port.
+    #[schemars(default = "default_port")]
     pub port: Option<u16>,
+    /// Jump hosts hostname is reached through, in order (like ssh -J).
+    pub proxy_jump: Option<Vec<ProxyJump>>,
+    /// SSH user (sudoer). Asked for if not set.
     pub username: Option<String>,
+    /// Private key for SSH login. Defaults to ~/.ssh/id_ed25519, id_ecdsa or id_rsa.
+    pub identity_file: Option<String>,
+    /// SSH login methods, tried in given order.
+    #[schemars(default = "default_ssh_auth")]
+    pub ssh_auth: Option<Vec<SshAuth>>,
+    /// Where SSH and sudo password comes from: "prompt" (default), { env = "VAR" },
+    /// { file = "path" } or { command = "pass show host" }.
+    pub password_source: Option<PasswordSource>,
+    /// Expected SHA256 fingerprint of host key, as printed by ssh-keygen -lf. Used instead of
+    /// ~/.ssh/known_hosts.
+    pub host_key_fingerprint: Option<String>,
+    /// What to do with hosts missing from ~/.ssh/known_hosts.
+    #[schemars(default = "default_host_key_check")]
+    pub host_key_check: Option<HostKeyCheck>,
+    /// How program is compiled for SSH hosts whose kernel differs from local one.
+    #[schemars(default = "default_build")]
+    pub build: Option<BuildMode>,
+    /// Network interface to attach program to.
+    #[schemars(default = "default_iface")]
     pub iface: Option<String>,
-    pub prog_type: Option<String>,
-    pub xdp_action: Option<String>,
+    /// Type of eBPF program to generate.
+    #[schemars(default = "default_prog_type")]
+    pub prog_type: Option<ProgType>,
+    /// Action for packets not matched by any list.
+    #[schemars(default = "default_xdp_action")]
+    pub xdp_action: Option<XdpAction>,
+    /// XDP mode to attach program in. --xdp-flags wins over it.
+    #[schemars(default = "default_xdp_mode")]
+    pub xdp_mode: Option<XdpMode>,
+    /// MaxMind-format databases used to resolve country:/asn: list entries.
+    pub mmdb: Option<Vec<String>>,
+    /// Addresses always allowed.
     pub whitelist: Option<Whitelist>,
+    /// Addresses always denied.
     pub blacklist: Option<Blacklist>,
+    /// Addresses with rate limited packets.
     pub graylist: Option<Graylist>,
+    /// Brute-force detection per service port. Used by ip program type.
+    pub bruteforce: Option<Vec<Bruteforce>>,
+    /// UDP reflection/amplification detection. Used by udp program type.
+    pub amplification: Option<Amplification>,
+    /// Default action used instead of xdp_action while its schedule is active.
+    pub scheduled_action: Option<ScheduledAction>,
+    /// Percentage of list capacity after which loader warns about it.
+    #[schemars(default = "default_fill_warning", range(min = 1, max = 100))]
+    pub fill_warning: Option<u32>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Data {
+    /// IPv4 addresses, country:XX or asn:ASXXXX entries.
     pub whitelist: Option<Vec<String>>,
+    /// IPv4 addresses, country:XX or asn:ASXXXX entries.
     pub blacklist: Option<Vec<String>>,
+    /// IPv4 addresses.
     pub graylist: Option<Vec<String>>,
+    /// Files with more entries. Merged into the list when config is loaded.
+    pub whitelist_files: Option<Vec<ListSource>>,
+    pub blacklist_files: Option<Vec<ListSource>>,
+    pub graylist_files: Option<Vec<ListSource>>,
+}
+
+/// Jump host given either as [user@]host[:port] or with its own login settings.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(untagged)]
+pub enum ProxyJump {
+    Address(String),
+    Host(JumpHost),
+}
+
+/// Jump host login settings. Same as the ones of init, not inherited from it.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct JumpHost {
+    pub hostname: String,
+    #[schemars(default = "default_port")]
+    pub port: Option<u16>,
+    /// Asked for if not set.
+    pub username: Option<String>,
+    pub identity_file: Option<String>,
+    #[schemars(default = "default_ssh_auth")]
+    pub ssh_auth: Option<Vec<SshAuth>>,
+    pub password_source: Option<PasswordSource>,
+    pub host_key_fingerprint: Option<String>,
+    #[schemars(default = "default_host_key_check")]
+    pub host_key_check: Option<HostKeyCheck>,
+}
+
+impl ProxyJump {
+    /// Login settings of jump host. Address is parsed as [user@]host[:port].
+    pub fn host(&self) -> Result<JumpHost, anyhow::Error> {
+        let address = match self {
+            ProxyJump::Host(h) => return Ok(h.clone()),
+            ProxyJump::Address(a) => a.trim(),
+        };
+        let (username, rest) = match address.split_once('@') {
+            Some((u, r)) => (Some(u.to_string()), r),
+            None => (None, address),
+        };
+        let (hostname, port) = match rest.rsplit_once(':') {
+            Some((h, p)) => (
+                h,
+                Some(
+                    p.parse::<u16>()
+                        .map_err(|_| anyhow!("Invalid port in jump host \"{}\"", address))?,
+                ),
+            ),
+            None => (rest, None),
+        };
+        if hostname.is_empty() || username.as_ref().is_some_and(|u| u.is_empty()) {
+            return Err(anyhow!(
+                "Invalid jump host \"{}\". Use [user@]host[:port]",
+                address
+            ));
+        }
+
+        Ok(JumpHost {
+            hostname: hostname.to_string(),
+            port,
+            username,
+            identity_file: None,
+            ssh_auth: None,
+            password_source: None,
+            host_key_fingerprint: None,
+            host_key_check: None,
+        })
+    }
+}
+
+/// List file given either as a path or with explicit format.
+#[derive(Debug, Serialize, Clone, JsonSchema)]
+#[serde(untagged)]
+pub enum ListSource {
+    Path(String),
+    File(ListFile),
+}
+
+// Not derived, untagged enums hide errors of ListFile fields
+impl<'de> Deserialize<'de> for ListSource {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::String(path) => Ok(ListSource::Path(path)),
+            file => ListFile::deserialize(file)
+                .map(ListSource::File)
+                .map_err(serde::de::Error::custom),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ListFile {
+    /// Relative paths are resolved from config file directory.
+    pub path: String,
+    /// Defaults to file extension, plain otherwise.
+    pub format: Option<ListFormat>,
+    /// CSV column or JSON object key holding addresses.
+    pub column: Option<Column>,
+}
+
+/// Column by header name or 0-based index.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(untagged)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Whitelist {
+    /// Generate and load the list.
+    #[schemars(default = "default_false")]
     pub enabled: Option<bool>,
+    /// Maximum number of addresses.
+    #[schemars(default = "default_max", range(min = 1))]
     pub max: Option<u32>,
-    pub action: Option<String>,
+    /// Let kernel drop least recently used entries when list is full.
+    #[schemars(default = "default_false")]
+    pub evict: Option<bool>,
+    /// Action for matched packets.
+    #[schemars(
+        default = "default_whitelist_action",
+        schema_with = "allow_deny_schema"
+    )]
+    pub action: Option<ListAction>,
+    /// Time windows in which list is active. Always active if not set.
+    pub schedule: Option<Schedule>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Blacklist {
+    /// Generate and load the list.
+    #[schemars(default = "default_false")]
     pub enabled: Option<bool>,
+    /// Maximum number of addresses.
+    #[schemars(default = "default_max", range(min = 1))]
     pub max: Option<u32>,
-    pub action: Option<String>,
+    /// Let kernel drop least recently used entries when list is full.
+    #[schemars(default = "default_true")]
+    pub evict: Option<bool>,
+    /// Action for matched packets.
+    #[schemars(
+        default = "default_blacklist_action",
+        schema_with = "allow_deny_schema"
+    )]
+    pub action: Option<ListAction>,
+    /// Time windows in which list is active. Always active if not set.
+    pub schedule: Option<Schedule>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Graylist {
+    /// Generate and load the list.
+    #[schemars(default = "default_false")]
     pub enabled: Option<bool>,
+    /// Maximum number of addresses.
+    #[schemars(default = "default_max", range(min = 1))]
     pub max: Option<u32>,
-    pub action: Option<String>,
+    /// Let kernel drop least recently used entries when list is full.
+    #[schemars(default = "default_true")]
+    pub evict: Option<bool>,
+    /// Action for matched packets.
+    #[schemars(default = "default_graylist_action")]
+    pub action: Option<ListAction>,
+    /// How fast packets can be sent, in ms.
+    #[schemars(default = "default_frequency", range(min = 1))]
     pub frequency: Option<u32>,
+    /// Packets within frequency after which source is escalated.
+    #[schemars(default = "default_fast_packets")]
     pub fast_packet_count: Option<u32>,
+    /// Time windows in which list is active. Always active if not set.
+    pub schedule: Option<Schedule>,
+}
+
+/// Counts new connection attempts (SYNs) per source to a single service port.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Bruteforce {
+    /// Service port, f.e. 22.
+    #[schemars(range(min = 1))]
+    pub port: u16,
+    /// Attempts per window after which source is blacklisted.
+    #[schemars(default = "default_bruteforce_attempts")]
+    pub max_attempts: Option<u32>,
+    /// Window length in ms.
+    #[schemars(default = "default_bruteforce_window", range(min = 1))]
+    pub window: Option<u32>,
+}
+
+/// Tracks UDP traffic to/from amplification services per requesting address and port.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Amplification {
+    /// Ports of amplification services.
+    #[schemars(default = "default_amplification_ports")]
+    pub ports: Option<Vec<u16>>,
+    /// Requests per window after which source is flagged.
+    #[schemars(default = "default_amplification_requests")]
+    pub max_requests: Option<u32>,
+    /// Window length in ms.
+    #[schemars(default = "default_amplification_window", range(min = 1))]
+    pub window: Option<u32>,
+    /// Response bytes per request byte after which requesting address is flagged.
+    #[schemars(default = "default_amplification_ratio")]
+    pub max_ratio: Option<u32>,
+    /// Response bytes per window before ratio is checked.
+    #[schemars(default = "default_amplification_bytes")]
+    pub min_bytes: Option<u32>,
+    /// deny (blacklist) or investigate (graylist).
+    #[schemars(
+        default = "default_amplification_action",
+        schema_with = "deny_investigate_schema"
+    )]
+    pub action: Option<ListAction>,
+}
+
+impl Amplification {
+    pub fn get_ports(&self) -> Vec<u16> {
+        self.ports
+            .clone()
+            .unwrap_or(DEFAULT_AMPLIFICATION_PORTS.to_vec())
+    }
+
+    pub fn get_action(&self) -> ListAction {
+        self.action.unwrap_or(DEFAULT_AMPLIFICATION_ACTION)
+    }
+}
+
+/// Weekday and time windows in which list or action applies.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    /// IANA timezone name, f.e. Europe/Vilnius. Defaults to UTC.
+    pub timezone: Option<String>,
+    /// Schedule is active while any of the windows is.
+    pub windows: Vec<Window>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Window {
+    /// Weekdays (mon, tue, ...) or ranges (mon-fri). Defaults to every day.
+    pub days: Option<Vec<String>>,
+    /// Start time, HH:MM.
+    pub start: String,
+    /// End time, HH:MM. Window crosses midnight if end is before start.
+    pub end: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduledAction {
+    /// Action used while schedule is active.
+    pub action: XdpAction,
+    /// When action applies.
+    pub schedule: Schedule,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            schema: None,
+            version: Some(CURRENT_VERSION),
             init: Some(Init::default()),
-            data: None
+            data: None,
+            programs: None,
+            profiles: None,
         }
     }
 }
 
+impl Config {
+    /// Every program described by config, with top-level values merged in. Config without
+    /// programs describes a single one.
+    pub fn programs(&self) -> Result<Vec<Config>, anyhow::Error> {
+        let programs = match self.programs.as_ref() {
+            Some(p) if !p.is_empty() => p,
+            _ => {
+                return Ok(vec![Config {
+                    programs: None,
+                    ..self.clone()
+                }])
+            }
+        };
+
+        let base = serde_json::to_value(Config {
+            programs: None,
+            ..self.clone()
+        })?;
+        let mut ret = Vec::new();
+        for (i, p) in programs.iter().enumerate() {
+            let mut value = base.clone();
+            layers::overlay(&mut value, serde_json::to_value(p)?)?;
+            ret.push(
+                serde_json::from_value(value)
+                    .with_context(|| format!("Failed to merge programs[{}]", i))?,
+            );
+        }
+        Ok(ret)
+    }
+
+    /// Programs to act on. All of them or the one named by --program.
+    pub fn select(&self, program: Option<&str>) -> Result<Vec<Config>, anyhow::Error> {
+        let programs = self.programs()?;
+        let program = match program {
+            Some(p) => p,
+            None => return Ok(programs),
+        };
+
+        let names = programs.iter().map(|p| p.name()).collect::<Vec<&str>>();
+        if !names.contains(&program) {
+            return Err(anyhow!(
+                "Program \"{}\" not found in config. Available: {}",
+                program,
+                names.join(", ")
+            ));
+        }
+        Ok(programs
+            .iter()
+            .filter(|p| p.name() == program)
+            .cloned()
+            .collect())
+    }
+
+    /// Same as select, but for commands that work with a single program.
+    pub fn select_one(&self, program: Option<&str>) -> Result<Config, anyhow::Error> {
+        let mut programs = self.select(program)?;
+        if programs.len() > 1 {
+            return Err(anyhow!(
+                "Config defines {} programs. Choose one with --program",
+                programs.len()
+            ));
+        }
+        Ok(programs.remove(0))
+    }
+
+    pub fn name(&self) -> &str {
+        self.init
+            .as_ref()
+            .and_then(|i| i.name.as_deref())
+            .unwrap_or(DEFAULT_NAME)
+    }
+}
+
 impl Display for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "├─ Hostname: {}\n├─ Port: {}\n├─ Username: {}\n├─ Network Interface: {}\n├─ eBPF Program Type: {}\n├─ eBPF Program Type: {} \n├─ eBPF Program Name: {}\n├─ Maps:\n├─── {}: \n{}├─── {}: \n{}└─── {}: \n{}",
+            "├─ Hostname: {}\n├─ Port: {}\n├─ Username: {}\n├─ Network Interface: {}\n├─ eBPF Program Type: {}\n├─ eBPF Program Type: {} \n├─ eBPF Program Name: {}\n├─ Maps:\n├─── {}: \n{}├─── {}: \n{}├─── {}: \n{}├─── {}: \n{}\n└─── {}: \n{}",
             self.init
                 .as_ref()
                 .unwrap()
@@ -116,19 +873,15 @@ impl Display for Config {
             self.init
                 .as_ref()
                 .unwrap()
-                .prog_type
-                .as_ref()
-                .unwrap_or(&"ip".to_string())
-                .to_uppercase()
+                .get_prog_type()
                 .as_str()
+                .to_uppercase()
                 .green()
                 .bold(),
             self.init
                 .as_ref()
                 .unwrap()
-                .xdp_action
-                .as_ref()
-                .unwrap_or(&"pass".to_string())
+                .get_xdp_action()
                 .as_str()
                 .green()
                 .bold(),
@@ -137,7 +890,7 @@ impl Display for Config {
                 .unwrap()
                 .name
                 .as_ref()
-                .unwrap()
+                .unwrap_or(&DEFAULT_NAME.to_string())
                 .as_str()
                 .green()
                 .bold(),
@@ -162,15 +915,89 @@ impl Display for Config {
                 .graylist
                 .as_ref()
                 .unwrap_or(&Graylist::default()),
+            "Brute-force".on_dark_red().white(),
+            format_bruteforce(self.init.as_ref().unwrap().bruteforce.as_ref()),
+            "Amplification".on_dark_blue().white(),
+            format_amplification(self.init.as_ref().unwrap()),
         )
     }
 }
 
+fn format_bruteforce(services: Option<&Vec<Bruteforce>>) -> String {
+    let services = match services {
+        Some(s) if !s.is_empty() => s,
+        _ => return format!("│       └─ Enabled: {}", "false".green().bold()),
+    };
+
+    let mut out = String::new();
+    for (i, s) in services.iter().enumerate() {
+        let branch = if i == services.len() - 1 { "└─" } else { "├─" };
+        out.push_str(&format!(
+            "│       {} Port {}: {} attempts per {} ms",
+            branch,
+            s.port.to_string().green().bold(),
+            s.max_attempts
+                .unwrap_or(DEFAULT_BRUTEFORCE_ATTEMPTS)
+                .to_string()
+                .green()
+                .bold(),
+            s.window
+                .unwrap_or(DEFAULT_BRUTEFORCE_WINDOW)
+                .to_string()
+                .green()
+                .bold(),
+        ));
+        if i != services.len() - 1 {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn format_amplification(init: &Init) -> String {
+    if init.get_prog_type() != ProgType::Udp {
+        return format!("        └─ Enabled: {}", "false".green().bold());
+    }
+    let a = init.amplification.clone().unwrap_or_default();
+
+    format!(
+        "        ├─ Ports: {}\n        ├─ Max Requests: {} per {} ms\n        ├─ Max Ratio: {} (after {} bytes)\n        └─ Action: {}",
+        a.get_ports()
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+            .green()
+            .bold(),
+        a.max_requests
+            .unwrap_or(DEFAULT_AMPLIFICATION_REQUESTS)
+            .to_string()
+            .green()
+            .bold(),
+        a.window
+            .unwrap_or(DEFAULT_AMPLIFICATION_WINDOW)
+            .to_string()
+            .green()
+            .bold(),
+        a.max_ratio
+            .unwrap_or(DEFAULT_AMPLIFICATION_RATIO)
+            .to_string()
+            .green()
+            .bold(),
+        a.min_bytes
+            .unwrap_or(DEFAULT_AMPLIFICATION_BYTES)
+            .to_string()
+            .green()
+            .bold(),
+        a.get_action().as_str().green().bold(),
+    )
+}
+
 impl Display for Blacklist {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "│       ├─ Enabled: {}\n│       ├─ Max IPs: {}\n│       └─ Action: {}\n",
+            "│       ├─ Enabled: {}\n│       ├─ Max IPs: {}\n│       ├─ Evict: {}\n│       └─ Action: {}\n",
             self.enabled
                 .as_ref()
                 .unwrap_or(&false)
@@ -183,12 +1010,8 @@ impl Display for Blacklist {
                 .to_string()
                 .green()
                 .bold(),
-            self.action
-                .as_ref()
-                .unwrap_or(&DEFAULT_BLACKLIST_ACTION.to_string())
-                .as_str()
-                .green()
-                .bold(),
+            self.get_evict().to_string().green().bold(),
+            self.get_action().as_str().green().bold(),
         )
     }
 }
@@ -197,7 +1020,7 @@ impl Display for Whitelist {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "│       ├─ Enabled: {}\n│       ├─ Max IPs: {}\n│       └─ Action: {}\n",
+            "│       ├─ Enabled: {}\n│       ├─ Max IPs: {}\n│       ├─ Evict: {}\n│       └─ Action: {}\n",
             self.enabled
                 .as_ref()
                 .unwrap_or(&false)
@@ -210,12 +1033,8 @@ impl Display for Whitelist {
                 .to_string()
                 .green()
                 .bold(),
-            self.action
-                .as_ref()
-                .unwrap_or(&DEFAULT_WHITELIST_ACTION.to_string())
-                .as_str()
-                .green()
-                .bold(),
+            self.get_evict().to_string().green().bold(),
+            self.get_action().as_str().green().bold(),
         )
     }
 }
@@ -224,7 +1043,7 @@ impl Display for Graylist {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "        ├─ Enabled: {}\n        ├─ Max IPs: {}\n        ├─ Action: {}\n        ├─ Frequency: {}\n        └─ Allowed Fast Packet Count: {}",
+            "│       ├─ Enabled: {}\n│       ├─ Max IPs: {}\n│       ├─ Evict: {}\n│       ├─ Action: {}\n│       ├─ Frequency: {}\n│       └─ Allowed Fast Packet Count: {}\n",
             self.enabled
                 .as_ref()
                 .unwrap_or(&false)
@@ -237,12 +1056,8 @@ impl Display for Graylist {
                 .to_string()
                 .green()
                 .bold(),
-            self.action
-                .as_ref()
-                .unwrap_or(&DEFAULT_GRAYLIST_ACTION.to_string())
-                .as_str()
-                .green()
-                .bold(),
+            self.get_evict().to_string().green().bold(),
+            self.get_action().as_str().green().bold(),
             self.frequency
                 .as_ref()
                 .unwrap_or(&DEFAULT_FREQUENCY)
@@ -263,25 +1078,83 @@ impl Default for Init {
     fn default() -> Self {
         Self {
             name: Some(DEFAULT_NAME.to_string()),
+            target: None,
             hostname: None,
             port: None,
+            proxy_jump: None,
             iface: Some(DEFAULT_NET_IFACE.to_string()),
             username: None,
-            prog_type: Some("ip".to_string()),
-            xdp_action: Some("PASS".to_string()),
+            identity_file: None,
+            ssh_auth: None,
+            password_source: None,
+            host_key_fingerprint: None,
+            host_key_check: None,
+            build: None,
+            prog_type: Some(DEFAULT_PROG_TYPE),
+            xdp_action: Some(DEFAULT_XDP_ACTION),
+            xdp_mode: None,
+            mmdb: None,
             whitelist: Some(Whitelist::default()),
             blacklist: Some(Blacklist::default()),
             graylist: Some(Graylist::default()),
+            bruteforce: None,
+            amplification: None,
+            scheduled_action: None,
+            fill_warning: None,
         }
     }
 }
 
+impl Init {
+    pub fn get_prog_type(&self) -> ProgType {
+        self.prog_type.unwrap_or(DEFAULT_PROG_TYPE)
+    }
+
+    /// Whether blacklist map is generated, so program can add addresses to it.
+    pub fn blacklist_enabled(&self) -> bool {
+        self.blacklist
+            .as_ref()
+            .is_some_and(|b| b.enabled.unwrap_or(false))
+    }
+
+    /// Whether graylist map is generated, so program can add addresses to it.
+    pub fn graylist_enabled(&self) -> bool {
+        self.graylist
+            .as_ref()
+            .is_some_and(|g| g.enabled.unwrap_or(false))
+    }
+
+    pub fn get_xdp_action(&self) -> XdpAction {
+        self.xdp_action.unwrap_or(DEFAULT_XDP_ACTION)
+    }
+
+    pub fn get_xdp_mode(&self) -> XdpMode {
+        self.xdp_mode.unwrap_or(DEFAULT_XDP_MODE)
+    }
+
+    pub fn get_host_key_check(&self) -> HostKeyCheck {
+        self.host_key_check.unwrap_or(DEFAULT_HOST_KEY_CHECK)
+    }
+
+    pub fn get_build(&self) -> BuildMode {
+        self.build.unwrap_or(DEFAULT_BUILD)
+    }
+
+    pub fn get_ssh_auth(&self) -> Vec<SshAuth> {
+        self.ssh_auth
+            .clone()
+            .unwrap_or(DEFAULT_SSH_AUTH.to_vec())
+    }
+}
+
 impl Default for Whitelist {
     fn default() -> Self {
         Self {
             enabled: Some(false),
             max: Some(DEFAULT_MAX_IPS),
-            action: Some(DEFAULT_WHITELIST_ACTION.to_string()),
+            evict: None,
+            action: Some(DEFAULT_WHITELIST_ACTION),
+            schedule: None,
         }
     }
 }
@@ -291,7 +1164,9 @@ impl Default for Blacklist {
         Self {
             enabled: Some(false),
             max: Some(DEFAULT_MAX_IPS),
-            action: Some(DEFAULT_BLACKLIST_ACTION.to_string()),
+            evict: None,
+            action: Some(DEFAULT_BLACKLIST_ACTION),
+            schedule: None,
         }
     }
 }
@@ -301,16 +1176,20 @@ impl Default for Graylist {
         Self {
             enabled: Some(true),
             max: Some(DEFAULT_MAX_IPS),
-            action: Some(DEFAULT_GRAYLIST_ACTION.to_string()),
+            evict: None,
+            action: Some(DEFAULT_GRAYLIST_ACTION),
             frequency: Some(DEFAULT_FREQUENCY),
-            fast_packet_count: Some(DEFAULT_FAST_PACKETS)
+            fast_packet_count: Some(DEFAULT_FAST_PACKETS),
+            schedule: None,
         }
     }
 }
 
 pub trait List {
     fn get_max(&self) -> u32;
-    fn get_action(&self) -> &str;
+    fn get_action(&self) -> ListAction;
+    fn get_schedule(&self) -> Option<&Schedule>;
+    fn get_evict(&self) -> bool;
 }
 
 impl List for Whitelist {
@@ -318,12 +1197,17 @@ impl List for Whitelist {
         self.max.unwrap_or(DEFAULT_MAX_IPS)
     }
 
-    fn get_action(&self) -> &str {
-        if let Some(action) = self.action.as_ref() {
-            action
-        } else {
-            DEFAULT_WHITELIST_ACTION
-        }
+    fn get_action(&self) -> ListAction {
+        self.action.unwrap_or(DEFAULT_WHITELIST_ACTION)
+    }
+
+    fn get_schedule(&self) -> Option<&Schedule> {
+        self.schedule.as_ref()
+    }
+
+    // Evicting whitelist entries could lock out management addresses
+    fn get_evict(&self) -> bool {
+        self.evict.unwrap_or(false)
     }
 }
 
@@ -332,12 +1216,16 @@ impl List for Blacklist {
         self.max.unwrap_or(DEFAULT_MAX_IPS)
     }
 
-    fn get_action(&self) -> &str {
-        if let Some(action) = self.action.as_ref() {
-            action
-        } else {
-            DEFAULT_BLACKLIST_ACTION
-        }
+    fn get_action(&self) -> ListAction {
+        self.action.unwrap_or(DEFAULT_BLACKLIST_ACTION)
+    }
+
+    fn get_schedule(&self) -> Option<&Schedule> {
+        self.schedule.as_ref()
+    }
+
+    fn get_evict(&self) -> bool {
+        self.evict.unwrap_or(true)
     }
 }
 
@@ -346,11 +1234,130 @@ impl List for Graylist {
         self.max.unwrap_or(DEFAULT_MAX_IPS)
     }
 
-    fn get_action(&self) -> &str {
-        if let Some(action) = self.action.as_ref() {
-            action
-        } else {
-            DEFAULT_GRAYLIST_ACTION
+    fn get_action(&self) -> ListAction {
+        self.action.unwrap_or(DEFAULT_GRAYLIST_ACTION)
+    }
+
+    fn get_schedule(&self) -> Option<&Schedule> {
+        self.schedule.as_ref()
+    }
+
+    fn get_evict(&self) -> bool {
+        self.evict.unwrap_or(true)
+    }
+}
+
+/// Supported config file formats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Display for ConfigFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigFormat::Json => write!(f, "JSON"),
+            ConfigFormat::Toml => write!(f, "TOML"),
+            ConfigFormat::Yaml => write!(f, "YAML"),
+        }
+    }
+}
+
+impl ConfigFormat {
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "json" => Some(ConfigFormat::Json),
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            _ => None,
         }
     }
+
+    /// Guesses format from first meaningful line. JSON starts with an object, TOML has
+    /// tables or key = value pairs, anything else is treated as YAML.
+    pub fn detect(content: &str) -> Self {
+        let line = content
+            .lines()
+            .map(|l| l.trim())
+            .find(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with("---"))
+            .unwrap_or_default();
+
+        if line.starts_with('{') {
+            return ConfigFormat::Json;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            return ConfigFormat::Toml;
+        }
+        match (line.find('='), line.find(':')) {
+            (Some(e), Some(c)) if e < c => ConfigFormat::Toml,
+            (Some(_), None) => ConfigFormat::Toml,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+}
+
+pub fn parse_config(content: &str, format: ConfigFormat) -> Result<Config, anyhow::Error> {
+    let config = match format {
+        ConfigFormat::Json => serde_json::from_str(content).map_err(|e| anyhow!(e)),
+        ConfigFormat::Toml => toml::from_str(content).map_err(|e| anyhow!(e)),
+        ConfigFormat::Yaml => serde_yaml::from_str(content).map_err(|e| anyhow!(e)),
+    };
+    config.with_context(|| format!("Failed to parse {} config", format))
+}
+
+/// Checks that config parses as hpx would read it, with environment variables filled in.
+pub fn check_config(content: &str, format: ConfigFormat) -> Result<(), anyhow::Error> {
+    let mut value = parse_value(content, format)?;
+    // Checked as written if possible, so errors have line numbers
+    if !interpolate::interpolate(&mut value)? {
+        return parse_config(content, format).map(|_| ());
+    }
+    check_value(value, format)
+}
+
+fn check_value(value: Value, format: ConfigFormat) -> Result<(), anyhow::Error> {
+    serde_json::from_value::<Config>(value)
+        .map(|_| ())
+        .with_context(|| format!("Failed to parse {} config", format))
+}
+
+fn parse_value(content: &str, format: ConfigFormat) -> Result<Value, anyhow::Error> {
+    let value = match format {
+        ConfigFormat::Json => serde_json::from_str(content).map_err(|e| anyhow!(e)),
+        ConfigFormat::Toml => toml::from_str(content).map_err(|e| anyhow!(e)),
+        ConfigFormat::Yaml => serde_yaml::from_str(content).map_err(|e| anyhow!(e)),
+    };
+    value.with_context(|| format!("Failed to parse {} config", format))
+}
+
+/// Reads config file as a layer, upgraded to current version. Format comes from extension or,
+/// if it is unknown, from contents. Unknown keys are rejected here so errors point to the right
+/// file. ${VAR} values are checked, but left as they are. Returns version file had.
+pub fn read_layer(path: &Path) -> Result<(Value, u32), anyhow::Error> {
+    if !path.exists() {
+        return Err(anyhow!(
+            "File does not exist!\nSearched locations: {}",
+            path.display()
+        ));
+    }
+
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config {}", path.display()))?;
+    let format = ConfigFormat::from_extension(path).unwrap_or(ConfigFormat::detect(&content));
+
+    let mut value =
+        parse_value(&content, format).with_context(|| format!("In {}", path.display()))?;
+    let version = migrate::migrate(&mut value).with_context(|| format!("In {}", path.display()))?;
+
+    if version == CURRENT_VERSION {
+        check_config(&content, format)
+    } else {
+        let mut check = value.clone();
+        interpolate::interpolate(&mut check).and_then(|_| check_value(check, format))
+    }
+    .with_context(|| format!("In {}", path.display()))?;
+
+    Ok((value, version))
 }
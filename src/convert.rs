@@ -0,0 +1,335 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{anyhow, Context};
+use crossterm::style::Stylize;
+use toml_edit::{DocumentMut, Item, Table, TableLike};
+
+use crate::{
+    config::{check_config, read_layer, ConfigFormat},
+    layers,
+    migrate::{self, CURRENT_VERSION},
+    sources::LIST_FILES,
+};
+
+/// Comments of a single TOML key or table.
+#[derive(Default)]
+struct Comment {
+    /// Comment lines above key
+    above: Vec<String>,
+    /// Comment after value on the same line
+    trailing: Option<String>,
+}
+
+/// hpx config convert
+pub fn config_convert(input: &str, output: &str, force: bool) -> Result<(), anyhow::Error> {
+    let input = Path::new(input);
+    let output = Path::new(output);
+    let out_format = ConfigFormat::from_extension(output).with_context(|| {
+        format!(
+            "Unknown format of {}. Use .json, .toml, .yaml or .yml",
+            output.display()
+        )
+    })?;
+    if output.exists() && !force {
+        return Err(anyhow!(
+            "{} already exists. Use --force to overwrite it",
+            output.display()
+        ));
+    }
+
+    let content = fs::read_to_string(input)
+        .with_context(|| format!("Failed to read config {}", input.display()))?;
+    let in_format = ConfigFormat::from_extension(input).unwrap_or(ConfigFormat::detect(&content));
+    // Upgraded and checked the same way as when config is loaded
+    let (mut value, version) = read_layer(input)?;
+    layers::strip_nulls(&mut value);
+
+    // Relative list files must point to the same files from new location
+    let in_dir = fs::canonicalize(input)?
+        .parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_default();
+    let out_dir = output
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .map(fs::canonicalize)
+        .transpose()
+        .with_context(|| format!("Directory of {} does not exist", output.display()))?;
+    let out_dir = match out_dir {
+        Some(d) => d,
+        None => fs::canonicalize(std::env::current_dir()?)?,
+    };
+    let mut rebase = false;
+    if out_dir != in_dir {
+        let before = value.clone();
+        layers::rebase_list_files(&mut value, &in_dir);
+        rebase = before != value;
+    }
+
+    let toml_doc = match in_format {
+        ConfigFormat::Toml => {
+            let mut doc = content.parse::<DocumentMut>()?;
+            if version < CURRENT_VERSION {
+                migrate::migrate_toml_doc(&mut doc, version);
+            }
+            Some(doc)
+        }
+        _ => None,
+    };
+    let has_comments = match toml_doc.as_ref() {
+        Some(doc) => !toml_comments(doc).is_empty(),
+        None => content.lines().any(|l| l.trim_start().starts_with('#')),
+    };
+    let value = migrate::version_first(value);
+
+    let converted = match (out_format, toml_doc) {
+        // Same document, so every comment and the layout stay as they were
+        (ConfigFormat::Toml, Some(mut doc)) => {
+            if rebase {
+                rebase_toml(&mut doc, &in_dir);
+            }
+            doc.to_string()
+        }
+        (ConfigFormat::Yaml, Some(doc)) => {
+            with_comments(&serde_yaml::to_string(&value)?, &toml_comments(&doc))
+        }
+        (ConfigFormat::Toml, None) => toml::to_string_pretty(&value)?,
+        (ConfigFormat::Yaml, None) => serde_yaml::to_string(&value)?,
+        (ConfigFormat::Json, _) => format!("{}\n", serde_json::to_string_pretty(&value)?),
+    };
+    let comments_kept =
+        !has_comments || (in_format == ConfigFormat::Toml && out_format != ConfigFormat::Json);
+
+    // Never write a file hpx can't read back
+    check_config(&converted, out_format).with_context(|| {
+        format!(
+            "Converted {} is not valid. Nothing was written",
+            input.display()
+        )
+    })?;
+    fs::write(output, converted)
+        .with_context(|| format!("Failed to write config {}", output.display()))?;
+
+    println!(
+        "{}: {} ({}) converted to {} ({})",
+        "Convert".yellow().bold(),
+        input.display(),
+        in_format,
+        output.display(),
+        out_format
+    );
+    if !comments_kept {
+        println!(
+            "{}: Comments were not kept. Only TOML comments are carried over and JSON has none",
+            "Convert".yellow().bold()
+        );
+    }
+    if rebase {
+        println!(
+            "{}: Relative list file paths were made absolute",
+            "Convert".yellow().bold()
+        );
+    }
+
+    Ok(())
+}
+
+/// Comments of TOML document by value path, f.e. init.whitelist or programs[0].init.name.
+/// Comments at the end of document are under empty path.
+fn toml_comments(doc: &DocumentMut) -> HashMap<String, Comment> {
+    let mut comments = HashMap::new();
+    table_comments(doc.as_table(), "", &mut comments);
+
+    let trailing = comment_lines(doc.trailing().as_str().unwrap_or_default());
+    if !trailing.is_empty() {
+        comments.entry(String::new()).or_default().above = trailing;
+    }
+    comments
+}
+
+fn table_comments(table: &Table, path: &str, comments: &mut HashMap<String, Comment>) {
+    for (key, item) in table.iter() {
+        let p = join(path, key);
+        let mut comment = Comment::default();
+
+        match item {
+            Item::Value(v) => {
+                if let Some(k) = table.key(key) {
+                    comment.above = decor_lines(k.leaf_decor().prefix());
+                }
+                comment.trailing = decor_lines(v.decor().suffix()).into_iter().next();
+            }
+            Item::Table(t) => {
+                comment.above = decor_lines(t.decor().prefix());
+                table_comments(t, &p, comments);
+            }
+            Item::ArrayOfTables(a) => {
+                for (i, t) in a.iter().enumerate() {
+                    let p = format!("{}[{}]", p, i);
+                    let above = decor_lines(t.decor().prefix());
+                    if !above.is_empty() {
+                        comments.insert(
+                            p.clone(),
+                            Comment {
+                                above,
+                                trailing: None,
+                            },
+                        );
+                    }
+                    table_comments(t, &p, comments);
+                }
+            }
+            Item::None => (),
+        }
+
+        if !comment.above.is_empty() || comment.trailing.is_some() {
+            comments.insert(p, comment);
+        }
+    }
+}
+
+fn decor_lines(raw: Option<&toml_edit::RawString>) -> Vec<String> {
+    comment_lines(raw.and_then(|r| r.as_str()).unwrap_or_default())
+}
+
+fn comment_lines(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|l| l.trim())
+        .filter(|l| l.starts_with('#'))
+        .map(|l| l.to_string())
+        .collect()
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Puts comments into YAML written by serde_yaml, above the key they belong to. Keys are
+/// found by indentation, which serde_yaml keeps at two spaces per level.
+fn with_comments(yaml: &str, comments: &HashMap<String, Comment>) -> String {
+    // Indentation and path of keys and sequence items the current line is nested in
+    let mut parents: Vec<(usize, String)> = Vec::new();
+    let mut items: HashMap<String, usize> = HashMap::new();
+    let mut out = String::new();
+
+    for line in yaml.lines() {
+        let mut indent = line.len() - line.trim_start().len();
+        let mut rest = line.trim_start();
+        let mut paths = Vec::new();
+
+        if let Some(item) = rest.strip_prefix("- ").or((rest == "-").then_some("")) {
+            while parents.last().is_some_and(|(i, _)| *i > indent) {
+                parents.pop();
+            }
+            let parent = parents.last().map(|(_, p)| p.clone()).unwrap_or_default();
+            let index = items.entry(parent.clone()).or_default();
+            let path = format!("{}[{}]", parent, index);
+            *index += 1;
+
+            paths.push(path.clone());
+            parents.push((indent + 1, path));
+            indent += 2;
+            rest = item;
+        }
+
+        if let Some(key) = yaml_key(rest) {
+            while parents.last().is_some_and(|(i, _)| *i >= indent) {
+                parents.pop();
+            }
+            let parent = parents.last().map(|(_, p)| p.as_str()).unwrap_or_default();
+            let path = join(parent, &key);
+            paths.push(path.clone());
+            parents.push((indent, path));
+        }
+
+        let pad = " ".repeat(line.len() - line.trim_start().len());
+        let mut trailing = None;
+        for p in &paths {
+            if let Some(c) = comments.get(p) {
+                for l in &c.above {
+                    out.push_str(&format!("{}{}\n", pad, l));
+                }
+                trailing = trailing.or(c.trailing.as_ref());
+            }
+        }
+        match trailing {
+            // Containers continue on next lines, so comment goes above them
+            Some(t) if line.ends_with(':') => out.push_str(&format!("{}{}\n{}\n", pad, t, line)),
+            Some(t) => out.push_str(&format!("{} {}\n", line, t)),
+            None => out.push_str(&format!("{}\n", line)),
+        }
+    }
+
+    if let Some(c) = comments.get("") {
+        out.push('\n');
+        for l in &c.above {
+            out.push_str(&format!("{}\n", l));
+        }
+    }
+    out
+}
+
+/// Key of "key: value" or "key:" line.
+fn yaml_key(line: &str) -> Option<String> {
+    let (key, _) = line
+        .split_once(':')
+        .filter(|(_, v)| v.is_empty() || v.starts_with(' '))?;
+    let key = key.trim_matches(|c| c == '\'' || c == '"');
+    if key.is_empty() || key.contains(' ') || key.starts_with('{') {
+        return None;
+    }
+    Some(key.to_string())
+}
+
+/// Same as layers::rebase_list_files, but for TOML document, so comments stay.
+fn rebase_toml(doc: &mut DocumentMut, base: &Path) {
+    if let Some(d) = doc.get_mut("data").and_then(|d| d.as_table_like_mut()) {
+        rebase_toml_data(d, base);
+    }
+    match doc.get_mut("programs") {
+        Some(Item::ArrayOfTables(programs)) => {
+            for p in programs.iter_mut() {
+                if let Some(d) = p.get_mut("data").and_then(|d| d.as_table_like_mut()) {
+                    rebase_toml_data(d, base);
+                }
+            }
+        }
+        Some(Item::Value(toml_edit::Value::Array(programs))) => {
+            for p in programs.iter_mut().filter_map(|p| p.as_inline_table_mut()) {
+                if let Some(d) = p.get_mut("data").and_then(|d| d.as_inline_table_mut()) {
+                    rebase_toml_data(d, base);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+fn rebase_toml_data(data: &mut dyn TableLike, base: &Path) {
+    for list in LIST_FILES {
+        let files = match data.get_mut(list).and_then(|l| l.as_array_mut()) {
+            Some(f) => f,
+            None => continue,
+        };
+
+        for f in files.iter_mut() {
+            let path = match f {
+                toml_edit::Value::String(_) => f,
+                toml_edit::Value::InlineTable(t) => match t.get_mut("path") {
+                    Some(p) => p,
+                    None => continue,
+                },
+                _ => continue,
+            };
+            if let Some(p) = path.as_str() {
+                let decor = path.decor().clone();
+                *path = toml_edit::Value::from(base.join(p).to_string_lossy().to_string());
+                *path.decor_mut() = decor;
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    env, fmt, fs,
+    process::Command,
+    ptr,
+    sync::atomic::{compiler_fence, Ordering},
+};
+
+use anyhow::{anyhow, Context};
+
+use crate::{config::PasswordSource, fleet, ssh};
+
+/// String that is overwritten with zeros when dropped, so passwords don't linger in memory.
+/// Debug output never shows it.
+#[derive(Clone, Default)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // Volatile writes can't be optimized away even though the string is freed right after
+        unsafe {
+            for b in self.0.as_bytes_mut() {
+                ptr::write_volatile(b, 0);
+            }
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+impl PasswordSource {
+    /// Reads password. Prompt is shown only by prompt source.
+    pub fn read(&self, prompt: &str) -> Result<Secret, anyhow::Error> {
+        let password = match self {
+            PasswordSource::Prompt if fleet::is_host() => {
+                return Err(anyhow!(
+                    "Password can't be asked for on inventory hosts. Set password_source"
+                ))
+            }
+            PasswordSource::Prompt => Secret::new(rpassword::prompt_password(prompt)?),
+            PasswordSource::Env(var) => Secret::new(
+                env::var(var)
+                    .with_context(|| format!("Environment variable {} is not set", var))?,
+            ),
+            PasswordSource::File(path) => {
+                let path = ssh::expand_home(path);
+                let content = Secret::new(
+                    fs::read_to_string(&path)
+                        .with_context(|| format!("Failed to read password file {}", path.display()))?,
+                );
+                first_line(&content)
+            }
+            PasswordSource::Command(command) => {
+                let mut output = Command::new("sh")
+                    .args(["-c", command])
+                    .output()
+                    .with_context(|| format!("Failed to run password command \"{}\"", command))?;
+                let stdout = Secret::new(String::from_utf8_lossy(&output.stdout).to_string());
+                output.stdout.fill(0);
+                if !output.status.success() {
+                    return Err(anyhow!(
+                        "Password command \"{}\" failed: {}",
+                        command,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+                first_line(&stdout)
+            }
+        };
+
+        if password.is_empty() && *self != PasswordSource::Prompt {
+            return Err(anyhow!("Password from {} is empty", self));
+        }
+        Ok(password)
+    }
+}
+
+/// Password files and commands (f.e. pass) keep other data after the first line.
+fn first_line(content: &Secret) -> Secret {
+    Secret::new(
+        content
+            .expose()
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    )
+}
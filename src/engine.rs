@@ -1,6 +1,6 @@
-use core::panic;
 use std::{
-    fs::File,
+    fs::{self, File},
+    hash::{DefaultHasher, Hash, Hasher},
     io::{self, Write},
     path::Path,
     process::Command,
@@ -10,19 +10,44 @@ use anyhow::{anyhow, Context};
 use crossterm::style::Stylize;
 
 use crate::{
+    capacity::OVERFLOW_SIZE,
     cli::Generate,
-    config::{Config, Init, List, DEFAULT_FAST_PACKETS, DEFAULT_FREQUENCY, DEFAULT_NAME},
-    snippets::{ACTION, BASE_DNS, BASE_IP, GET_DATA_DNS, GET_DATA_IP, GRAYLIST, MAP},
+    config::{
+        Bruteforce, Config, Init, List, ListAction, ProgType, XdpAction,
+        DEFAULT_AMPLIFICATION_BYTES, DEFAULT_AMPLIFICATION_MAX, DEFAULT_AMPLIFICATION_RATIO,
+        DEFAULT_AMPLIFICATION_REQUESTS, DEFAULT_AMPLIFICATION_WINDOW, DEFAULT_BRUTEFORCE_ATTEMPTS,
+        DEFAULT_BRUTEFORCE_MAX, DEFAULT_BRUTEFORCE_WINDOW, DEFAULT_FAST_PACKETS, DEFAULT_FREQUENCY,
+        DEFAULT_NAME,
+    },
+    geo, schedule,
+    snippets::{
+        ACTION, AMPLIFICATION, AMPLIFICATION_DENY, AMPLIFICATION_INVESTIGATE, AMPLIFICATION_MAP,
+        AMPLIFICATION_PORT, BASE_DNS, BASE_IP, BASE_UDP, BRUTEFORCE, BRUTEFORCE_MAP, CONTROL_MAP,
+        GET_DATA_DNS, GET_DATA_IP, GET_DATA_TCP, GET_NET_DNS, GET_NET_IP, GRAYLIST, MAP, NET_MAP,
+        OVERFLOW_MAP, SCHEDULED,
+    },
     WORKING_DIR,
 };
 
-pub fn generator(options: Generate, config: Config) -> Result<(bool, String), anyhow::Error> {
-    let out = format!(
-        "{}/out/generated.c",
+/// Dumps kernel types (BTF) of running kernel as vmlinux.h.
+pub static BTF_DUMP: &str = "bpftool btf dump file /sys/kernel/btf/vmlinux format c";
+/// clang arguments for compiling generated program.
+pub static CLANG_ARGS: &str = "-O2 -g -target bpf";
+
+/// Generated source (c) or compiled object (o) of program, f.e. ~/.hpx/out/Example.o
+pub fn out_path(config: &Config, ext: &str) -> Result<String, anyhow::Error> {
+    Ok(format!(
+        "{}/out/{}.{}",
         WORKING_DIR
             .to_str()
-            .with_context(|| "Failed to parse HOME directory".to_string())?
-    );
+            .with_context(|| "Failed to parse HOME directory".to_string())?,
+        config.name(),
+        ext
+    ))
+}
+
+pub fn generator(options: Generate, config: Config) -> Result<(bool, String), anyhow::Error> {
+    let out = out_path(&config, "c")?;
     let path = Path::new(&out);
     let out_file = File::create(path)?;
 
@@ -47,6 +72,7 @@ pub fn generator(options: Generate, config: Config) -> Result<(bool, String), an
     }
 
     println!("{}: Generating eBPF program...", "Generate".yellow().bold(),);
+    let object = out_path(&config, "o")?;
     generate(config, out_file)?;
     println!(
         "{}: Generated eBPF program at: {}",
@@ -54,31 +80,73 @@ pub fn generator(options: Generate, config: Config) -> Result<(bool, String), an
         path.display()
     );
 
-    let out = format!(
-        "{}/out/generated.o",
-        WORKING_DIR
-            .to_str()
-            .with_context(|| "Failed to parse HOME directory".to_string())?
-    );
-    let compile_out = Path::new(&out);
+    let compile_out = Path::new(&object);
     println!("{}: Compiling eBPF program...", "Generate".yellow().bold(),);
-    Command::new("clang")
-        .arg("-O2")
-        .arg("-g")
-        .arg("-target")
-        .arg("bpf")
-        .arg("-c")
-        .arg(path)
-        .arg("-o")
-        .arg(compile_out)
-        .output()?;
+    compile(path, compile_out)?;
     println!(
         "{}: Compiled eBPF program at: {}",
         "Generate".yellow().bold(),
         compile_out.display()
     );
 
-    Ok((true, out))
+    Ok((true, object))
+}
+
+/// Compiles generated source. vmlinux.h next to source decides which kernel it is built for.
+fn compile(source: &Path, object: &Path) -> Result<(), anyhow::Error> {
+    let output = Command::new("clang")
+        .args(CLANG_ARGS.split(' '))
+        .arg("-c")
+        .arg(source)
+        .arg("-o")
+        .arg(object)
+        .output()
+        .with_context(|| "Failed to run clang".to_string())?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to compile {}: {}",
+            source.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Compiles generated source against vmlinux.h of another kernel, in its own directory under
+/// ~/.hpx/out, so the local vmlinux.h next to source is not picked up. Directory is named after
+/// host and hash of its kernel types, so a new kernel of the same host gets a fresh one.
+/// Returns object path.
+pub fn compile_with_btf(
+    config: &Config,
+    host: &str,
+    vmlinux: &str,
+) -> Result<String, anyhow::Error> {
+    let host: String = host
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    let mut hasher = DefaultHasher::new();
+    vmlinux.hash(&mut hasher);
+
+    let source = out_path(config, "c")?;
+    let out = Path::new(&source)
+        .parent()
+        .with_context(|| format!("Invalid program path {}", source))?
+        .join(format!("{}-{:016x}", host, hasher.finish()));
+    fs::create_dir_all(&out)?;
+
+    fs::write(out.join("vmlinux.h"), vmlinux)?;
+    let copy = out.join(format!("{}.c", config.name()));
+    fs::copy(&source, &copy)
+        .with_context(|| format!("{} was not generated. Run hpx generate first", source))?;
+    let object = out.join(format!("{}.o", config.name()));
+    compile(&copy, &object)?;
+
+    Ok(object.to_string_lossy().to_string())
 }
 
 // should have written a library to do most of this stuff... like finding patters and changing
@@ -87,23 +155,40 @@ pub fn generator(options: Generate, config: Config) -> Result<(bool, String), an
 // seperate generator functions, f.e. frequency, dns(? wip), other that would allow more
 // features/configuration
 pub fn generate(config: Config, out_file: File) -> Result<(), anyhow::Error> {
-    match config.init.as_ref().unwrap().prog_type.as_ref() {
-        Some(t) => match t.as_str() {
-            "ip" => {
-                generate_program(config, out_file, BASE_IP)?;
-            }
-            "dns" => {
-                generate_program(config, out_file, BASE_DNS)?;
-            }
-            _ => return Err(anyhow!("Unknown program type")),
-        },
-        None => generate_program(config, out_file, BASE_IP)?,
-    }
-    Ok(())
+    let base = match config.init.as_ref().unwrap().get_prog_type() {
+        ProgType::Ip => BASE_IP,
+        ProgType::Dns => BASE_DNS,
+        ProgType::Udp => BASE_UDP,
+    };
+    generate_program(config, out_file, base)
 }
 
 fn generate_program(config: Config, out: File, prog_base: &str) -> Result<(), anyhow::Error> {
     let mut writer = io::BufWriter::new(out);
+    let init = config.init.as_ref().unwrap();
+    let data = config.data.as_ref();
+
+    // Country/ASN entries are resolved now only to size the prefix maps. Loader fills them.
+    let whitelist_nets = match init.whitelist.as_ref() {
+        Some(w) if w.enabled.unwrap_or(false) => {
+            geo::expand_list(data.and_then(|d| d.whitelist.as_ref()), init.mmdb.as_ref())?.len()
+        }
+        _ => 0,
+    };
+    let blacklist_nets = match init.blacklist.as_ref() {
+        Some(b) if b.enabled.unwrap_or(false) => {
+            geo::expand_list(data.and_then(|d| d.blacklist.as_ref()), init.mmdb.as_ref())?.len()
+        }
+        _ => 0,
+    };
+    if data
+        .and_then(|d| d.graylist.as_ref())
+        .is_some_and(|g| g.iter().any(|e| geo::is_geo_entry(e)))
+    {
+        return Err(anyhow!(
+            "Graylist does not support country/ASN entries. Use whitelist or blacklist"
+        ));
+    }
 
     for line in prog_base.lines() {
         match line.find("{{") {
@@ -122,7 +207,7 @@ fn generate_program(config: Config, out: File, prog_base: &str) -> Result<(), an
                                     .as_ref()
                                     .unwrap()
                                     .enabled
-                                    .unwrap()
+                                    .unwrap_or(false)
                             {
                                 continue;
                             }
@@ -144,7 +229,7 @@ fn generate_program(config: Config, out: File, prog_base: &str) -> Result<(), an
                                     .as_ref()
                                     .unwrap()
                                     .enabled
-                                    .unwrap()
+                                    .unwrap_or(false)
                             {
                                 continue;
                             }
@@ -166,7 +251,7 @@ fn generate_program(config: Config, out: File, prog_base: &str) -> Result<(), an
                                     .as_ref()
                                     .unwrap()
                                     .enabled
-                                    .unwrap()
+                                    .unwrap_or(false)
                             {
                                 continue;
                             }
@@ -188,23 +273,18 @@ fn generate_program(config: Config, out: File, prog_base: &str) -> Result<(), an
                                     .as_ref()
                                     .unwrap()
                                     .enabled
-                                    .unwrap()
+                                    .unwrap_or(false)
                             {
                                 continue;
                             }
                             replace_wb_action(
-                                config
-                                    .init
-                                    .as_ref()
-                                    .unwrap()
-                                    .prog_type
-                                    .as_ref()
-                                    .unwrap_or(&"ip".to_string()),
+                                config.init.as_ref().unwrap().get_prog_type(),
                                 config.init.as_ref().unwrap().whitelist.as_ref().unwrap(),
                                 start,
                                 end,
                                 line,
                                 "whitelist",
+                                false,
                             )
                         }
                         "blacklist_action" => {
@@ -217,23 +297,18 @@ fn generate_program(config: Config, out: File, prog_base: &str) -> Result<(), an
                                     .as_ref()
                                     .unwrap()
                                     .enabled
-                                    .unwrap()
+                                    .unwrap_or(false)
                             {
                                 continue;
                             }
                             replace_wb_action(
-                                config
-                                    .init
-                                    .as_ref()
-                                    .unwrap()
-                                    .prog_type
-                                    .as_ref()
-                                    .unwrap_or(&"ip".to_string()),
+                                config.init.as_ref().unwrap().get_prog_type(),
                                 config.init.as_ref().unwrap().blacklist.as_ref().unwrap(),
                                 start,
                                 end,
                                 line,
                                 "blacklist",
+                                false,
                             )
                         }
                         "graylist_action" => {
@@ -246,7 +321,7 @@ fn generate_program(config: Config, out: File, prog_base: &str) -> Result<(), an
                                     .as_ref()
                                     .unwrap()
                                     .enabled
-                                    .unwrap()
+                                    .unwrap_or(false)
                             {
                                 continue;
                             }
@@ -258,13 +333,149 @@ fn generate_program(config: Config, out: File, prog_base: &str) -> Result<(), an
                                 "graylist",
                             )
                         }
-                        "default_action" => replace_default_action(&config, start, end, line),
+                        "whitelist_net_map" => {
+                            if whitelist_nets == 0 {
+                                continue;
+                            }
+                            replace_net_map(whitelist_nets, start, end, line, "whitelist_net")
+                        }
+                        "blacklist_net_map" => {
+                            if blacklist_nets == 0 {
+                                continue;
+                            }
+                            replace_net_map(blacklist_nets, start, end, line, "blacklist_net")
+                        }
+                        "whitelist_net_action" => {
+                            if whitelist_nets == 0 {
+                                continue;
+                            }
+                            replace_wb_action(
+                                init.get_prog_type(),
+                                init.whitelist.as_ref().unwrap(),
+                                start,
+                                end,
+                                line,
+                                "whitelist_net",
+                                true,
+                            )
+                        }
+                        "blacklist_net_action" => {
+                            if blacklist_nets == 0 {
+                                continue;
+                            }
+                            replace_wb_action(
+                                init.get_prog_type(),
+                                init.blacklist.as_ref().unwrap(),
+                                start,
+                                end,
+                                line,
+                                "blacklist_net",
+                                true,
+                            )
+                        }
+                        "bruteforce_map" => {
+                            match config.init.as_ref().unwrap().bruteforce.as_ref() {
+                                Some(b) if !b.is_empty() => {
+                                    replace_bruteforce_map(start, end, line)
+                                }
+                                _ => continue,
+                            }
+                        }
+                        "bruteforce_action" => {
+                            match config.init.as_ref().unwrap().bruteforce.as_ref() {
+                                Some(b) if !b.is_empty() => {
+                                    replace_bruteforce_action(b, start, end, line)
+                                }
+                                _ => continue,
+                            }
+                        }
+                        "amplification_map" => {
+                            replace_amplification_map(start, end, line)
+                        }
+                        "amplification_action" => replace_amplification_action(
+                            init,
+                            start,
+                            end,
+                            line,
+                        )?,
+                        "default_action" => replace_default_action(
+                            init.get_xdp_action(),
+                            start,
+                            end,
+                            line,
+                        ),
+                        "control_map" => {
+                            if !schedule::has_schedules(init) {
+                                continue;
+                            }
+                            CONTROL_MAP.replace("{{max}}", &schedule::CONTROL_SIZE.to_string())
+                        }
+                        "overflow_map" => {
+                            // Only blacklist and graylist are written to from kernel
+                            if !init.blacklist.as_ref().is_some_and(|b| b.enabled.unwrap_or(false))
+                                && !init.graylist.as_ref().is_some_and(|g| g.enabled.unwrap_or(false))
+                            {
+                                continue;
+                            }
+                            OVERFLOW_MAP.replace("{{max}}", &OVERFLOW_SIZE.to_string())
+                        }
+                        "default_action_schedule" => match init.scheduled_action.as_ref() {
+                            Some(a) => {
+                                let ret = "return {{default_action}};";
+                                replace_scheduled(
+                                    &replace_default_action(
+                                        a.action,
+                                        ret.find("{{").unwrap(),
+                                        ret.find("}}").unwrap(),
+                                        ret,
+                                    ),
+                                    "default",
+                                    schedule::CONTROL_DEFAULT,
+                                )
+                            }
+                            None => continue,
+                        },
                         _ => {
                             writer.write_all((line.to_string() + "\n").as_bytes())?;
                             continue;
                         }
                     };
 
+                    // Lists with schedule only apply while control map allows it
+                    let parsed_line = match block {
+                        "whitelist_action" | "whitelist_net_action" => {
+                            match init.whitelist.as_ref().and_then(|l| l.get_schedule()) {
+                                Some(_) => replace_scheduled(
+                                    &parsed_line,
+                                    &block.replace("_action", ""),
+                                    schedule::CONTROL_WHITELIST,
+                                ),
+                                None => parsed_line,
+                            }
+                        }
+                        "blacklist_action" | "blacklist_net_action" => {
+                            match init.blacklist.as_ref().and_then(|l| l.get_schedule()) {
+                                Some(_) => replace_scheduled(
+                                    &parsed_line,
+                                    &block.replace("_action", ""),
+                                    schedule::CONTROL_BLACKLIST,
+                                ),
+                                None => parsed_line,
+                            }
+                        }
+                        "graylist_action" => {
+                            match init.graylist.as_ref().and_then(|l| l.get_schedule()) {
+                                Some(_) => replace_scheduled(
+                                    &parsed_line,
+                                    "graylist",
+                                    schedule::CONTROL_GRAYLIST,
+                                ),
+                                None => parsed_line,
+                            }
+                        }
+                        _ => parsed_line,
+                    };
+
                     writer.write((parsed_line + "\n").as_bytes())?
                 }
                 None => continue,
@@ -301,6 +512,15 @@ fn replace_map(config: &impl List, start: usize, end: usize, line: &str, name: &
                             let s = l.replace(&l[start..end + 2], &config.get_max().to_string());
                             parsed.push(s + "\n");
                         }
+                        "map_type" => {
+                            let map_type = if config.get_evict() {
+                                "BPF_MAP_TYPE_LRU_HASH"
+                            } else {
+                                "BPF_MAP_TYPE_HASH"
+                            };
+                            let s = l.replace(&l[start..end + 2], map_type);
+                            parsed.push(s + "\n");
+                        }
                         "name" => {
                             let s = l.replace(&l[start..end + 2], name);
                             parsed.push(s + "\n");
@@ -317,18 +537,20 @@ fn replace_map(config: &impl List, start: usize, end: usize, line: &str, name: &
 }
 
 fn replace_wb_action(
-    prog_type: &str,
+    prog_type: ProgType,
     config: &impl List,
     start: usize,
     end: usize,
     line: &str,
     list: &str,
+    net: bool,
 ) -> String {
     let mut parsed: Vec<String> = Vec::new();
-    let actions: &str = match prog_type.to_lowercase().as_str() {
-        "ip" => &(GET_DATA_IP.to_owned() + ACTION),
-        "dns" => &(GET_DATA_DNS.to_owned() + ACTION),
-        _ => panic!("Generate: Unsupported program type"),
+    let actions: &str = match (prog_type, net) {
+        (ProgType::Ip | ProgType::Udp, false) => &(GET_DATA_IP.to_owned() + ACTION),
+        (ProgType::Dns, false) => &(GET_DATA_DNS.to_owned() + ACTION),
+        (ProgType::Ip | ProgType::Udp, true) => &(GET_NET_IP.to_owned() + "\n" + ACTION),
+        (ProgType::Dns, true) => &(GET_NET_DNS.to_owned() + "\n" + ACTION),
     };
 
     for l in actions.lines() {
@@ -348,7 +570,7 @@ fn replace_wb_action(
                         let block = &curr_line[start + 2..end];
                         match block {
                             "action" => match config.get_action() {
-                                "allow" => {
+                                ListAction::Allow => {
                                     s = l.replace(
                                         &curr_line[start..end + 2],
                                         &curr_line[start..end + 2]
@@ -356,15 +578,8 @@ fn replace_wb_action(
                                     );
                                     l = s.clone();
                                 }
-                                "deny" => {
-                                    s = l.replace(
-                                        &curr_line[start..end + 2],
-                                        &curr_line[start..end + 2]
-                                            .replace(&curr_line[start..end + 2], "XDP_DROP"),
-                                    );
-                                    l = s.clone();
-                                }
-                                _ => {
+                                // Graylist investigate is generated by replace_g_action
+                                ListAction::Deny | ListAction::Investigate => {
                                     s = l.replace(
                                         &curr_line[start..end + 2],
                                         &curr_line[start..end + 2]
@@ -401,17 +616,18 @@ fn replace_wb_action(
 fn replace_g_action(config: &Init, start: usize, end: usize, line: &str, list: &str) -> String {
     let mut parsed: Vec<String> = Vec::new();
     let action = match config.graylist.as_ref().unwrap().get_action() {
-        "allow" | "deny" => {
+        ListAction::Allow | ListAction::Deny => {
             return replace_wb_action(
-                "ip",
+                ProgType::Ip,
                 config.graylist.as_ref().unwrap(),
                 start,
                 end,
                 line,
                 list,
+                false,
             );
         }
-        _ => GRAYLIST,
+        ListAction::Investigate => GRAYLIST,
     };
     let actions: &str = &(GET_DATA_IP.to_owned() + action);
 
@@ -488,27 +704,129 @@ fn replace_g_action(config: &Init, start: usize, end: usize, line: &str, list: &
     line.replace(&line[start..end + 2], &parsed.concat())
 }
 
-fn replace_default_action(config: &Config, start: usize, end: usize, line: &str) -> String {
-    let default_action = config
-        .init
-        .as_ref()
-        .unwrap()
-        .xdp_action
-        .as_ref()
-        .unwrap_or(&"PASS".to_string())
-        .as_str()
-        .replace(" ", "");
-    match default_action.to_uppercase().as_str() {
-        "PASS" | "DROP" => line.replace(
-            &line[start..end + 2],
-            &("XDP_".to_string() + &default_action.to_uppercase()),
-        ),
-        _ => {
-            println!(
-                "INFO: Unsupported XDP action: {}. Using default: PASS",
-                &default_action
-            );
-            line.replace(&line[start..end + 2], &("XDP_".to_string() + "PASS"))
+fn replace_scheduled(action: &str, list: &str, index: u32) -> String {
+    SCHEDULED
+        .replace("{{list}}", list)
+        .replace("{{index}}", &index.to_string())
+        .replace("{{action}}", action)
+}
+
+fn replace_net_map(max: usize, start: usize, end: usize, line: &str, name: &str) -> String {
+    let map = NET_MAP
+        .replace("{{name}}", name)
+        .replace("{{max}}", &max.to_string());
+    line.replace(&line[start..end + 2], &map)
+}
+
+fn replace_bruteforce_map(start: usize, end: usize, line: &str) -> String {
+    let map = BRUTEFORCE_MAP.replace("{{max}}", &DEFAULT_BRUTEFORCE_MAX.to_string());
+    line.replace(&line[start..end + 2], &map)
+}
+
+fn replace_bruteforce_action(
+    services: &[Bruteforce],
+    start: usize,
+    end: usize,
+    line: &str,
+) -> String {
+    let mut parsed: Vec<String> = vec![GET_DATA_TCP.to_string()];
+
+    for s in services {
+        parsed.push(
+            BRUTEFORCE
+                .replace("{{port}}", &s.port.to_string())
+                .replace(
+                    "{{max_attempts}}",
+                    &s.max_attempts
+                        .unwrap_or(DEFAULT_BRUTEFORCE_ATTEMPTS)
+                        .to_string(),
+                )
+                .replace(
+                    "{{window}}",
+                    &s.window.unwrap_or(DEFAULT_BRUTEFORCE_WINDOW).to_string(),
+                ),
+        );
+    }
+    line.replace(&line[start..end + 2], &parsed.concat())
+}
+
+fn replace_amplification_map(start: usize, end: usize, line: &str) -> String {
+    let map = AMPLIFICATION_MAP.replace("{{max}}", &DEFAULT_AMPLIFICATION_MAX.to_string());
+    line.replace(&line[start..end + 2], &map)
+}
+
+fn replace_amplification_action(
+    init: &Init,
+    start: usize,
+    end: usize,
+    line: &str,
+) -> Result<String, anyhow::Error> {
+    let amplification = init.amplification.clone().unwrap_or_default();
+    // Flagged addresses would be dropped silently without the list they go to
+    let flag = match amplification.get_action() {
+        ListAction::Deny if init.blacklist_enabled() => AMPLIFICATION_DENY,
+        ListAction::Investigate if init.graylist_enabled() => AMPLIFICATION_INVESTIGATE,
+        ListAction::Deny => {
+            return Err(anyhow!(
+                "Amplification action deny needs init.blacklist to be enabled"
+            ))
         }
+        ListAction::Investigate => {
+            return Err(anyhow!(
+                "Amplification action investigate needs init.graylist to be enabled"
+            ))
+        }
+        ListAction::Allow => {
+            return Err(anyhow!(
+                "Unsupported amplification action: allow. Use deny or investigate"
+            ))
+        }
+    };
+
+    let mut parsed: Vec<String> =
+        vec!["__u16 amp_port = 0;\n__u32 amp_ip = 0;\n__u8 amp_response = 0;\n".to_string()];
+    for p in amplification.get_ports() {
+        parsed.push(AMPLIFICATION_PORT.replace("{{port}}", &p.to_string()));
     }
+    parsed.push(
+        AMPLIFICATION
+            .replace(
+                "{{window}}",
+                &amplification
+                    .window
+                    .unwrap_or(DEFAULT_AMPLIFICATION_WINDOW)
+                    .to_string(),
+            )
+            .replace(
+                "{{max_requests}}",
+                &amplification
+                    .max_requests
+                    .unwrap_or(DEFAULT_AMPLIFICATION_REQUESTS)
+                    .to_string(),
+            )
+            .replace(
+                "{{min_bytes}}",
+                &amplification
+                    .min_bytes
+                    .unwrap_or(DEFAULT_AMPLIFICATION_BYTES)
+                    .to_string(),
+            )
+            .replace(
+                "{{max_ratio}}",
+                &amplification
+                    .max_ratio
+                    .unwrap_or(DEFAULT_AMPLIFICATION_RATIO)
+                    .to_string(),
+            )
+            .replace("{{flag}}", flag),
+    );
+    Ok(line.replace(&line[start..end + 2], &parsed.concat()))
+}
+
+fn replace_default_action(action: XdpAction, start: usize, end: usize, line: &str) -> String {
+    let verdict = match action {
+        XdpAction::Pass => "XDP_PASS",
+        XdpAction::Drop => "XDP_DROP",
+    };
+    line.replace(&line[start..end + 2], verdict)
 }
@@ -0,0 +1,630 @@
+use std::{
+    collections::HashSet,
+    env,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
+use crossterm::{
+    cursor, queue,
+    style::Stylize,
+    terminal::{self, ClearType},
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{cli::Fleet, config::ConfigFormat, WORKING_DIR};
+
+/// Set for hpx processes started for one inventory host, name of the host is the value
+static HOST_ENV: &str = "HPX_FLEET_HOST";
+static DEFAULT_CONCURRENCY: usize = 10;
+/// How often finished hosts are checked for and live view is redrawn
+static REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+/// Directory of per-host logs in working directory
+static LOG_DIR: &str = "logs";
+
+/// Hosts commands run on with --inventory.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Inventory {
+    /// Hosts run at once. Defaults to 10.
+    pub concurrency: Option<usize>,
+    /// Share of hosts (0 to 1) allowed to fail. Once more fail, no new hosts are started.
+    pub max_failure_ratio: Option<f64>,
+    pub hosts: Vec<InventoryHost>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InventoryHost {
+    /// Shown in progress and summary. Defaults to hostname.
+    pub name: Option<String>,
+    pub hostname: String,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// init values of this host, f.e. iface or username. They win over config files.
+    #[serde(default)]
+    pub init: Map<String, Value>,
+}
+
+impl InventoryHost {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.hostname)
+    }
+}
+
+impl Inventory {
+    /// Reads inventory. Format comes from extension or, if it is unknown, from contents.
+    pub fn read(path: &str) -> Result<Inventory, anyhow::Error> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read inventory {}", path))?;
+        let format =
+            ConfigFormat::from_extension(Path::new(path)).unwrap_or(ConfigFormat::detect(&content));
+        let inventory: Inventory = match format {
+            ConfigFormat::Json => serde_json::from_str(&content).map_err(|e| anyhow!(e)),
+            ConfigFormat::Toml => toml::from_str(&content).map_err(|e| anyhow!(e)),
+            ConfigFormat::Yaml => serde_yaml::from_str(&content).map_err(|e| anyhow!(e)),
+        }
+        .with_context(|| format!("Failed to parse {} inventory {}", format, path))?;
+
+        inventory
+            .check()
+            .with_context(|| format!("In inventory {}", path))?;
+        Ok(inventory)
+    }
+
+    fn check(&self) -> Result<(), anyhow::Error> {
+        if self.concurrency == Some(0) {
+            return Err(anyhow!("concurrency must be at least 1"));
+        }
+        if self
+            .max_failure_ratio
+            .is_some_and(|r| !(0.0..=1.0).contains(&r))
+        {
+            return Err(anyhow!("max_failure_ratio must be between 0 and 1"));
+        }
+
+        let mut names = HashSet::new();
+        for h in &self.hosts {
+            if h.hostname.trim().is_empty() {
+                return Err(anyhow!("Host without hostname"));
+            }
+            if !names.insert(h.name()) {
+                return Err(anyhow!("Host \"{}\" is listed twice", h.name()));
+            }
+            if h.init.contains_key("hostname") {
+                return Err(anyhow!(
+                    "Host \"{}\" sets init.hostname. Use hostname of host instead",
+                    h.name()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Hosts in any of groups, all of them if no group is given.
+    pub fn select(&self, groups: &[String]) -> Result<Vec<&InventoryHost>, anyhow::Error> {
+        let hosts: Vec<&InventoryHost> = self
+            .hosts
+            .iter()
+            .filter(|h| groups.is_empty() || h.groups.iter().any(|g| groups.contains(g)))
+            .collect();
+        if hosts.is_empty() {
+            return Err(match groups.is_empty() {
+                true => anyhow!("Inventory has no hosts"),
+                false => anyhow!("No hosts in group {} of inventory", groups.join(", ")),
+            });
+        }
+        Ok(hosts)
+    }
+}
+
+/// Whether this process was started for one inventory host. Such processes can't ask questions.
+pub fn is_host() -> bool {
+    env::var_os(HOST_ENV).is_some()
+}
+
+enum State {
+    Waiting,
+    Running(Child, Instant),
+    Done(bool, Duration),
+    Skipped,
+}
+
+struct Run<'a> {
+    host: &'a InventoryHost,
+    state: State,
+    /// Last line host printed, updated by threads reading its output
+    last: Arc<Mutex<String>>,
+    readers: Vec<JoinHandle<()>>,
+    log: PathBuf,
+}
+
+/// Runs this hpx command on inventory hosts in parallel. Every host is run by hpx started with
+/// the same arguments, its hostname and init values, so hosts don't share state or output.
+pub fn rollout(fleet: &Fleet, command: &str, prefix: impl Display) -> Result<(), anyhow::Error> {
+    let inventory = Inventory::read(fleet.inventory.as_deref().unwrap_or_default())?;
+    let hosts = inventory.select(&fleet.group)?;
+    let concurrency = inventory.concurrency.unwrap_or(DEFAULT_CONCURRENCY);
+    let max_failed = max_failed(inventory.max_failure_ratio, hosts.len());
+
+    let log_dir = WORKING_DIR.join(LOG_DIR);
+    fs::create_dir_all(&log_dir)?;
+    let mut runs: Vec<Run> = hosts
+        .into_iter()
+        .map(|host| Run {
+            host,
+            state: State::Waiting,
+            last: Default::default(),
+            readers: Vec::new(),
+            log: log_dir.join(format!("{}-{}.log", command, host.name().replace('/', "_"))),
+        })
+        .collect();
+    println!(
+        "{}: Running on {} hosts, {} at a time\n",
+        prefix,
+        runs.len(),
+        concurrency
+    );
+
+    let live = io::stdout().is_terminal();
+    let mut drawn = 0;
+    loop {
+        let mut finished = Vec::new();
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let State::Running(child, started) = &mut run.state {
+                if let Some(status) = child.try_wait()? {
+                    let elapsed = started.elapsed();
+                    for r in run.readers.drain(..) {
+                        let _ = r.join();
+                    }
+                    run.state = State::Done(status.success(), elapsed);
+                    finished.push(i);
+                }
+            }
+        }
+
+        let mut lines: Vec<String> = finished
+            .into_iter()
+            .filter_map(|i| finished_line(&runs[i], &prefix))
+            .collect();
+
+        let failed = count(&runs, |s| matches!(s, State::Done(false, _)));
+        let waiting = count(&runs, |s| matches!(s, State::Waiting));
+        if waiting > 0 && max_failed.is_some_and(|m| failed > m) {
+            lines.push(format!(
+                "{}: {} of {} hosts failed, not starting the rest",
+                prefix,
+                failed,
+                runs.len()
+            ));
+            for run in runs
+                .iter_mut()
+                .filter(|r| matches!(r.state, State::Waiting))
+            {
+                run.state = State::Skipped;
+            }
+        }
+
+        let mut running = count(&runs, |s| matches!(s, State::Running(..)));
+        for run in runs
+            .iter_mut()
+            .filter(|r| matches!(r.state, State::Waiting))
+        {
+            if running >= concurrency {
+                break;
+            }
+            match spawn(run.host, &run.log, run.last.clone()) {
+                Ok((child, readers)) => {
+                    run.state = State::Running(child, Instant::now());
+                    run.readers = readers;
+                    running += 1;
+                    if !live {
+                        lines.push(format!("{}: Starting {}", prefix, run.host.name().bold()));
+                    }
+                }
+                Err(e) => {
+                    *run.last.lock().unwrap() = format!("{:#}", e);
+                    run.state = State::Done(false, Duration::ZERO);
+                    lines.extend(finished_line(run, &prefix));
+                    // Failure ratio is checked again before more hosts are started
+                    break;
+                }
+            }
+        }
+
+        if live {
+            draw(&runs, &lines, &mut drawn)?;
+        } else {
+            lines.iter().for_each(|l| println!("{}", l));
+        }
+        if running == 0 && count(&runs, |s| matches!(s, State::Waiting)) == 0 {
+            break;
+        }
+        thread::sleep(REFRESH_INTERVAL);
+    }
+
+    summary(&runs, &log_dir, &prefix);
+    let failed = count(&runs, |s| matches!(s, State::Done(false, _)));
+    let skipped = count(&runs, |s| matches!(s, State::Skipped));
+    if skipped > 0 {
+        return Err(anyhow!(
+            "{} of {} hosts failed, {} skipped",
+            failed,
+            runs.len(),
+            skipped
+        ));
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} of {} hosts failed", failed, runs.len()));
+    }
+    Ok(())
+}
+
+/// Hosts allowed to fail with max_failure_ratio. Once more fail, no new hosts are started.
+fn max_failed(ratio: Option<f64>, hosts: usize) -> Option<usize> {
+    ratio.map(|r| (r * hosts as f64).floor() as usize)
+}
+
+/// Line telling host is done, None if it isn't.
+fn finished_line(run: &Run, prefix: impl Display) -> Option<String> {
+    let State::Done(ok, elapsed) = run.state else {
+        return None;
+    };
+    Some(match ok {
+        true => format!(
+            "{}: {} {} in {}s",
+            prefix,
+            run.host.name().bold(),
+            "ok".green().bold(),
+            elapsed.as_secs()
+        ),
+        false => format!(
+            "{}: {} {} in {}s: {}",
+            prefix,
+            run.host.name().bold(),
+            "failed".red().bold(),
+            elapsed.as_secs(),
+            run.last.lock().unwrap()
+        ),
+    })
+}
+
+/// Starts hpx for host. Its output goes to log and last line to last.
+fn spawn(
+    host: &InventoryHost,
+    log: &Path,
+    last: Arc<Mutex<String>>,
+) -> Result<(Child, Vec<JoinHandle<()>>), anyhow::Error> {
+    let mut cmd = Command::new(env::current_exe()?);
+    // Global options go before the command, so --set given by user still wins
+    cmd.args(["--set", "init.target=ssh", "--set"])
+        .arg(format!("init.hostname={}", host.hostname));
+    for (key, value) in &host.init {
+        // --set reads strings as they are and everything else as JSON
+        let value = match value {
+            Value::String(s) => s.clone(),
+            v => v.to_string(),
+        };
+        cmd.arg("--set").arg(format!("init.{}={}", key, value));
+    }
+    cmd.args(env::args_os().skip(1))
+        .env(HOST_ENV, host.name())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to start hpx for {}", host.name()))?;
+    let log =
+        Arc::new(Mutex::new(File::create(log).with_context(|| {
+            format!("Failed to create {}", log.display())
+        })?));
+    let stdout = child
+        .stdout
+        .take()
+        .map(|s| read_output(s, log.clone(), last.clone()));
+    let stderr = child.stderr.take().map(|s| read_output(s, log, last));
+    Ok((child, stdout.into_iter().chain(stderr).collect()))
+}
+
+/// Writes lines of host output to log without colors, keeping the last one. Error hpx ended
+/// with is kept whole, with its causes on one line.
+fn read_output(
+    pipe: impl Read + Send + 'static,
+    log: Arc<Mutex<File>>,
+    last: Arc<Mutex<String>>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut buf = Vec::new();
+        let mut error = false;
+        while let Ok(n) = reader.read_until(b'\n', &mut buf) {
+            if n == 0 {
+                break;
+            }
+            let line = strip_colors(&String::from_utf8_lossy(&buf));
+            let _ = write!(log.lock().unwrap(), "{}", line);
+            buf.clear();
+
+            keep_last(&mut last.lock().unwrap(), &mut error, line.trim());
+        }
+    })
+}
+
+/// Updates last line with line of output. Once hpx printed its error, the causes that follow
+/// are appended to it.
+fn keep_last(last: &mut String, error: &mut bool, text: &str) {
+    if let Some(message) = text.strip_prefix("Error: ") {
+        *error = true;
+        *last = message.to_string();
+    } else if *error && !text.is_empty() && text != "Caused by:" {
+        // Several causes are numbered, f.e. "0: ..."
+        let cause = text
+            .split_once(": ")
+            .filter(|(n, _)| n.parse::<usize>().is_ok())
+            .map_or(text, |(_, c)| c);
+        last.push_str(": ");
+        last.push_str(cause);
+    } else if !*error && !text.is_empty() {
+        *last = text.to_string();
+    }
+}
+
+/// Removes terminal escape sequences (colors) from line.
+fn strip_colors(line: &str) -> String {
+    let mut stripped = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end with a letter
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+fn count(runs: &[Run], f: impl Fn(&State) -> bool) -> usize {
+    runs.iter().filter(|r| f(&r.state)).count()
+}
+
+/// Replaces live view with lines, which stay, and redraws it below them. Live view has a line
+/// per running host with what it printed last and a line of totals.
+fn draw(runs: &[Run], lines: &[String], drawn: &mut u16) -> Result<(), anyhow::Error> {
+    let mut stdout = io::stdout();
+    if *drawn > 0 {
+        queue!(
+            stdout,
+            cursor::MoveUp(*drawn),
+            terminal::Clear(ClearType::FromCursorDown)
+        )?;
+    }
+    for l in lines {
+        writeln!(stdout, "{}", l)?;
+    }
+
+    // Longer lines would wrap and break moving back up
+    let width = match terminal::size() {
+        Ok((w, _)) if w > 0 => w as usize - 1,
+        _ => 79,
+    };
+    let name_width = runs.iter().map(|r| r.host.name().len()).max().unwrap_or(0);
+    *drawn = 0;
+    for run in runs {
+        if let State::Running(_, started) = &run.state {
+            let line = format!(
+                "  {:<name_width$}  {:>4}s  {}",
+                run.host.name(),
+                started.elapsed().as_secs(),
+                run.last.lock().unwrap()
+            );
+            writeln!(stdout, "{}", line.chars().take(width).collect::<String>())?;
+            *drawn += 1;
+        }
+    }
+    writeln!(
+        stdout,
+        "  {} ok, {} failed, {} running, {} waiting",
+        count(runs, |s| matches!(s, State::Done(true, _))),
+        count(runs, |s| matches!(s, State::Done(false, _))),
+        count(runs, |s| matches!(s, State::Running(..))),
+        count(runs, |s| matches!(s, State::Waiting)),
+    )?;
+    *drawn += 1;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn summary(runs: &[Run], log_dir: &Path, prefix: impl Display) {
+    let name_width = runs
+        .iter()
+        .map(|r| r.host.name().len())
+        .chain([4])
+        .max()
+        .unwrap_or_default();
+
+    println!("\n{}: Summary", prefix);
+    println!(
+        "  {}",
+        format!(
+            "{:<name_width$}  {:<7}  {:>5}  DETAIL",
+            "HOST", "RESULT", "TIME"
+        )
+        .bold()
+    );
+    for run in runs {
+        let (result, time) = match run.state {
+            State::Done(true, t) => ("ok".green(), format!("{}s", t.as_secs())),
+            State::Done(false, t) => ("failed".red(), format!("{}s", t.as_secs())),
+            _ => ("skipped".yellow(), String::new()),
+        };
+        let detail = match run.state {
+            State::Done(..) => run.last.lock().unwrap().clone(),
+            _ => String::new(),
+        };
+        let line = format!(
+            "  {:<name_width$}  {}{}  {:>5}  {}",
+            run.host.name(),
+            result,
+            " ".repeat(7 - result.content().len()),
+            time,
+            detail
+        );
+        println!("{}", line.trim_end());
+    }
+
+    println!(
+        "\n{}: {} ok, {} failed, {} skipped. Logs are in {}",
+        prefix,
+        count(runs, |s| matches!(s, State::Done(true, _))),
+        count(runs, |s| matches!(s, State::Done(false, _))),
+        count(runs, |s| matches!(s, State::Skipped)),
+        log_dir.display()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inventory(toml: &str) -> Inventory {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn check_error(toml: &str) -> String {
+        inventory(toml).check().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn checks_inventory() {
+        assert!(inventory(
+            r#"
+            concurrency = 2
+            max_failure_ratio = 0.5
+            [[hosts]]
+            hostname = "a"
+            [[hosts]]
+            name = "b"
+            hostname = "a"
+            "#
+        )
+        .check()
+        .is_ok());
+
+        assert_eq!(
+            check_error("concurrency = 0\nhosts = []"),
+            "concurrency must be at least 1"
+        );
+        assert_eq!(
+            check_error("max_failure_ratio = 1.5\nhosts = []"),
+            "max_failure_ratio must be between 0 and 1"
+        );
+        assert_eq!(
+            check_error("[[hosts]]\nhostname = \" \""),
+            "Host without hostname"
+        );
+        assert_eq!(
+            check_error("[[hosts]]\nhostname = \"a\"\n[[hosts]]\nhostname = \"a\""),
+            "Host \"a\" is listed twice"
+        );
+        assert_eq!(
+            check_error("[[hosts]]\nhostname = \"a\"\ninit.hostname = \"b\""),
+            "Host \"a\" sets init.hostname. Use hostname of host instead"
+        );
+    }
+
+    #[test]
+    fn selects_hosts_by_group() {
+        let edge = inventory(
+            r#"
+            [[hosts]]
+            hostname = "a"
+            groups = ["edge"]
+            [[hosts]]
+            hostname = "b"
+            groups = ["core", "edge"]
+            [[hosts]]
+            hostname = "c"
+            "#,
+        );
+        let names = |groups: &[&str]| {
+            let groups: Vec<String> = groups.iter().map(|g| g.to_string()).collect();
+            edge.select(&groups)
+                .map(|hosts| hosts.iter().map(|h| h.name()).collect::<Vec<_>>())
+                .map_err(|e| e.to_string())
+        };
+
+        assert_eq!(names(&[]), Ok(vec!["a", "b", "c"]));
+        assert_eq!(names(&["edge"]), Ok(vec!["a", "b"]));
+        assert_eq!(names(&["core", "other"]), Ok(vec!["b"]));
+        assert_eq!(
+            names(&["other"]),
+            Err("No hosts in group other of inventory".to_string())
+        );
+    }
+
+    #[test]
+    fn selects_from_empty_inventory() {
+        let error = inventory("hosts = []").select(&[]).unwrap_err();
+        assert_eq!(error.to_string(), "Inventory has no hosts");
+    }
+
+    #[test]
+    fn strips_colors() {
+        assert_eq!(
+            strip_colors("\x1b[1m\x1b[31mError\x1b[0m: plain"),
+            "Error: plain"
+        );
+        assert_eq!(strip_colors("no colors"), "no colors");
+    }
+
+    #[test]
+    fn keeps_error_with_causes() {
+        let mut last = String::new();
+        let mut error = false;
+        for text in [
+            "Loading program",
+            "Error: Failed to load",
+            "",
+            "Caused by:",
+            "0: Failed to attach",
+            "1: Device busy",
+        ] {
+            keep_last(&mut last, &mut error, text);
+        }
+        assert_eq!(last, "Failed to load: Failed to attach: Device busy");
+
+        let mut last = String::new();
+        let mut error = false;
+        for text in ["Error: Failed to load", "Caused by:", "    Device busy"] {
+            keep_last(&mut last, &mut error, text.trim());
+        }
+        assert_eq!(last, "Failed to load: Device busy");
+
+        let mut last = String::new();
+        let mut error = false;
+        for text in ["Loading program", "", "Loaded"] {
+            keep_last(&mut last, &mut error, text);
+        }
+        assert_eq!(last, "Loaded");
+    }
+
+    #[test]
+    fn counts_allowed_failures() {
+        assert_eq!(max_failed(None, 10), None);
+        assert_eq!(max_failed(Some(0.0), 10), Some(0));
+        assert_eq!(max_failed(Some(0.25), 10), Some(2));
+        assert_eq!(max_failed(Some(0.5), 3), Some(1));
+        assert_eq!(max_failed(Some(1.0), 3), Some(3));
+    }
+}
@@ -0,0 +1,169 @@
+use std::{collections::BTreeSet, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use crossterm::style::Stylize;
+use ipnetwork::{IpNetwork, Ipv4Network};
+use maxminddb::Reader;
+use serde::Deserialize;
+
+use crate::{cli::Geo, config::Config};
+
+/// List entry that resolves to network prefixes instead of a single address.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoEntry {
+    Country(String),
+    Asn(u32),
+}
+
+// Only fields needed for matching. Works with both Country and ASN databases.
+#[derive(Deserialize)]
+struct Record<'a> {
+    #[serde(borrow)]
+    country: Option<Country<'a>>,
+    autonomous_system_number: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct Country<'a> {
+    iso_code: Option<&'a str>,
+}
+
+impl FromStr for GeoEntry {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s
+            .trim()
+            .split_once(':')
+            .with_context(|| format!("Invalid entry \"{}\"", s))?;
+
+        match kind.to_lowercase().as_str() {
+            "country" => {
+                let code = value.trim().to_uppercase();
+                if code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+                    return Err(anyhow!(
+                        "Invalid country code \"{}\". Expected ISO 3166-1 alpha-2 code, f.e. country:RU",
+                        value
+                    ));
+                }
+                Ok(GeoEntry::Country(code))
+            }
+            "asn" => {
+                let value = value.trim();
+                let number = value
+                    .strip_prefix("AS")
+                    .or_else(|| value.strip_prefix("as"))
+                    .unwrap_or(value);
+                Ok(GeoEntry::Asn(number.parse().with_context(|| {
+                    format!("Invalid ASN \"{}\". Expected f.e. asn:AS14061", value)
+                })?))
+            }
+            _ => Err(anyhow!(
+                "Unknown entry type \"{}\". Supported: country, asn",
+                kind
+            )),
+        }
+    }
+}
+
+/// Checks if list entry should be resolved from MMDB file.
+pub fn is_geo_entry(entry: &str) -> bool {
+    let entry = entry.trim().to_lowercase();
+    entry.starts_with("country:") || entry.starts_with("asn:")
+}
+
+/// Resolves all country/ASN entries of a list into IPv4 prefixes. Plain addresses are ignored.
+pub fn expand_list(
+    entries: Option<&Vec<String>>,
+    mmdb: Option<&Vec<String>>,
+) -> Result<Vec<Ipv4Network>, anyhow::Error> {
+    let entries: Vec<GeoEntry> = entries
+        .map(|e| e.iter().filter(|e| is_geo_entry(e)).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|e| e.parse())
+        .collect::<Result<_, _>>()?;
+
+    if entries.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mmdb = match mmdb {
+        Some(m) if !m.is_empty() => m,
+        _ => {
+            return Err(anyhow!(
+                "Country/ASN entries require MMDB file. Set it with init.mmdb"
+            ))
+        }
+    };
+
+    expand(&entries, mmdb)
+}
+
+/// Walks IPv4 part of every database and collects prefixes matching any of the entries.
+pub fn expand(entries: &[GeoEntry], mmdb: &[String]) -> Result<Vec<Ipv4Network>, anyhow::Error> {
+    let mut nets: BTreeSet<Ipv4Network> = BTreeSet::new();
+
+    for path in mmdb {
+        let reader = Reader::open_readfile(path)
+            .with_context(|| format!("Failed to open MMDB file {}", path))?;
+        let all: IpNetwork = "0.0.0.0/0".parse()?;
+
+        for item in reader
+            .within::<Record>(all)
+            .with_context(|| format!("Failed to read MMDB file {}", path))?
+        {
+            let item = item.with_context(|| format!("Failed to read MMDB file {}", path))?;
+            let matches = entries.iter().any(|e| match e {
+                GeoEntry::Country(code) => item
+                    .info
+                    .country
+                    .as_ref()
+                    .and_then(|c| c.iso_code)
+                    .is_some_and(|c| c == code),
+                GeoEntry::Asn(asn) => item.info.autonomous_system_number == Some(*asn),
+            });
+
+            if !matches {
+                continue;
+            }
+            if let IpNetwork::V4(net) = item.ip_net {
+                nets.insert(net);
+            }
+        }
+    }
+
+    Ok(nets.into_iter().collect())
+}
+
+pub fn geo(options: Geo, config: Config) -> Result<(), anyhow::Error> {
+    let entry: GeoEntry = options.entry.parse()?;
+    let mmdb = if !options.mmdb.is_empty() {
+        options.mmdb
+    } else {
+        config
+            .init
+            .as_ref()
+            .and_then(|i| i.mmdb.clone())
+            .unwrap_or_default()
+    };
+
+    if mmdb.is_empty() {
+        return Err(anyhow!(
+            "No MMDB file provided. Use --mmdb or set init.mmdb in config"
+        ));
+    }
+
+    let nets = expand(&[entry], &mmdb)?;
+    for n in &nets {
+        println!("{}", n);
+    }
+    eprintln!(
+        "{}: {} expands to {} prefixes",
+        "Geo".magenta().bold(),
+        options.entry.trim().bold(),
+        nets.len().to_string().bold()
+    );
+
+    Ok(())
+}
@@ -2,110 +2,177 @@ use crossterm::style::Stylize;
 
 use crate::{
     cli::{ConfOutputType, LuaFunc},
-    config::{Blacklist, Config, Data, Graylist, Init, Whitelist},
+    config::{
+        Blacklist, Bruteforce, Config, Data, Graylist, Init, ListAction, ProgType, Whitelist,
+        XdpAction,
+    },
+    layers,
+    migrate::CURRENT_VERSION,
 };
 
-pub fn get_default_config(o: ConfOutputType) -> Result<(), anyhow::Error> {
+/// Prints config in format chosen by options.
+fn print_config(config: &Config, o: &ConfOutputType) -> Result<(), anyhow::Error> {
     if o.formatted.is_some() {
-        println!("{}", Config::default());
-    } else if o.pretty.is_some() {
-        println!("{}", serde_json::to_string_pretty(&Config::default())?);
+        println!("{}", config);
     } else if o.json.is_some() {
-        println!("{}", serde_json::to_string(&Config::default())?);
+        println!("{}", serde_json::to_string(config)?);
+    } else if o.toml.is_some() {
+        print!("{}", toml::to_string_pretty(config)?);
+    } else if o.yaml.is_some() {
+        // Unset values are left out, same as in TOML
+        let mut value = serde_json::to_value(config)?;
+        layers::strip_nulls(&mut value);
+        print!("{}", serde_yaml::to_string(&value)?);
     } else {
-        println!("{}", Config::default());
+        println!("{}", serde_json::to_string_pretty(config)?);
     }
 
     Ok(())
 }
 
+pub fn get_default_config(o: ConfOutputType) -> Result<(), anyhow::Error> {
+    // Visualized by default
+    if o.pretty.is_none() && o.json.is_none() && o.toml.is_none() && o.yaml.is_none() {
+        println!("{}", Config::default());
+        return Ok(());
+    }
+    print_config(&Config::default(), &o)
+}
+
 pub fn get_example_config(o: ConfOutputType) -> Result<(), anyhow::Error> {
     let config = Config {
+        schema: None,
+        version: Some(CURRENT_VERSION),
         init: Some(Init {
             name: Some(String::from("Example")),
+            target: None,
             hostname: Some(String::from("100.0.0.10")),
             port: Some(22),
+            proxy_jump: None,
             username: Some(String::from("bobthebuilder")),
+            identity_file: Some(String::from("~/.ssh/id_ed25519")),
+            ssh_auth: None,
+            password_source: None,
+            host_key_fingerprint: None,
+            host_key_check: None,
+            build: None,
             iface: Some(String::from("eth0")),
-            prog_type: Some(String::from("ip")),
-            xdp_action: Some(String::from("PASS")),
+            prog_type: Some(ProgType::Ip),
+            xdp_action: Some(XdpAction::Pass),
+            xdp_mode: None,
+            mmdb: None,
             whitelist: Some(Whitelist {
                 enabled: Some(true),
                 max: Some(32),
-                action: Some(String::from("allow")),
+                evict: None,
+                action: Some(ListAction::Allow),
+                schedule: None,
             }),
             blacklist: Some(Blacklist {
                 enabled: Some(true),
                 max: Some(32),
-                action: Some(String::from("deny")),
+                evict: None,
+                action: Some(ListAction::Deny),
+                schedule: None,
             }),
             graylist: Some(Graylist {
                 enabled: Some(true),
                 max: Some(32),
-                action: Some(String::from("investigate")),
+                evict: None,
+                action: Some(ListAction::Investigate),
                 frequency: Some(1000),
                 fast_packet_count: Some(10),
+                schedule: None,
             }),
+            scheduled_action: None,
+            fill_warning: None,
+            bruteforce: Some(vec![Bruteforce {
+                port: 22,
+                max_attempts: Some(5),
+                window: Some(60000),
+            }]),
+            amplification: None,
         }),
         data: Some(Data {
             whitelist: Some(vec![String::from("192.168.1.103")]),
             blacklist: Some(vec![String::from("192.168.1.203")]),
             graylist: Some(Vec::new()),
+            whitelist_files: None,
+            blacklist_files: None,
+            graylist_files: None,
         }),
+        programs: None,
+        profiles: None,
     };
 
-    if o.pretty.is_some() {
-        println!("{}", serde_json::to_string_pretty(&config)?);
-    } else if o.json.is_some() {
-        println!("{}", serde_json::to_string(&config)?);
-    } else if o.formatted.is_some() {
-        println!("{}", &config);
-    } else {
-        println!("{}", serde_json::to_string_pretty(&config)?);
-    }
-
-    Ok(())
+    print_config(&config, &o)
 }
 
-pub fn get_base_config(o: ConfOutputType) -> Result<(), anyhow::Error> {
-    let config = Config {
+/// Starter configuration. Also used as the base of hpx init.
+pub fn base_config() -> Config {
+    Config {
+        schema: None,
+        version: Some(CURRENT_VERSION),
         init: Some(Init {
             name: Some(String::from("MyFirstProgram")),
+            target: None,
             hostname: None,
             port: None,
+            proxy_jump: None,
             username: None,
+            identity_file: None,
+            ssh_auth: None,
+            password_source: None,
+            host_key_fingerprint: None,
+            host_key_check: None,
+            build: None,
             iface: Some(String::from("lo")),
-            prog_type: Some(String::from("ip")),
-            xdp_action: Some(String::from("PASS")),
+            prog_type: Some(ProgType::Ip),
+            xdp_action: Some(XdpAction::Pass),
+            xdp_mode: None,
+            mmdb: None,
             whitelist: Some(Whitelist {
                 enabled: Some(true),
                 max: Some(32),
-                action: Some(String::from("allow")),
+                evict: None,
+                action: Some(ListAction::Allow),
+                schedule: None,
             }),
             blacklist: Some(Blacklist {
                 enabled: Some(true),
                 max: Some(32),
-                action: Some(String::from("deny")),
+                evict: None,
+                action: Some(ListAction::Deny),
+                schedule: None,
             }),
             graylist: None,
+            bruteforce: None,
+            amplification: None,
+            scheduled_action: None,
+            fill_warning: None,
         }),
         data: Some(Data {
             whitelist: Some(vec![]),
             blacklist: Some(vec![]),
             graylist: None,
+            whitelist_files: None,
+            blacklist_files: None,
+            graylist_files: None,
         }),
-    };
-
-    if o.pretty.is_some() {
-        println!("{}", serde_json::to_string_pretty(&config)?);
-    } else if o.json.is_some() {
-        println!("{}", serde_json::to_string(&config)?);
-    } else if o.formatted.is_some() {
-        println!("{}", &config);
-    } else {
-        println!("{}", serde_json::to_string_pretty(&config)?);
+        programs: None,
+        profiles: None,
     }
+}
+
+pub fn get_base_config(o: ConfOutputType) -> Result<(), anyhow::Error> {
+    let config = base_config();
 
+    print_config(&config, &o)
+}
+
+pub fn get_schema() -> Result<(), anyhow::Error> {
+    let schema = schemars::schema_for!(Config);
+    println!("{}", serde_json::to_string_pretty(&schema)?);
     Ok(())
 }
 
@@ -134,12 +201,16 @@ pub fn get_lua_api() {
 
 pub fn get_lua_func_opts(o: LuaFunc) {
     match o {
-        LuaFunc::Analyze => println!("opts = {{\n    config\n}}"),
-        LuaFunc::Generate => println!("opts = {{\n    config\n}}"),
-        LuaFunc::PLoad => println!("opts = {{\n    config,\n    iface,\n    xdp_flags\n}}"),
+        LuaFunc::Analyze => println!("opts = {{\n    config,\n    program (optional)\n}}"),
+        LuaFunc::Generate => println!("opts = {{\n    config,\n    program (optional)\n}}"),
+        LuaFunc::PLoad => println!(
+            "opts = {{\n    config,\n    iface,\n    xdp_flags,\n    program (optional)\n}}"
+        ),
         LuaFunc::PUnload => {
-            println!("opts = {{\n    config,\n    iface,\n    xdp_flags,\n    prog_id\n}}")
+            println!("opts = {{\n    config,\n    iface,\n    xdp_flags,\n    prog_id,\n    program (optional)\n}}")
+        }
+        LuaFunc::Get_map_data => {
+            println!("opts = {{\n    config,\n    map_name,\n    program (optional)\n}}")
         }
-        LuaFunc::Get_map_data => println!("opts = {{\n    config,\n    map_name\n}}"),
     }
 }
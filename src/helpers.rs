@@ -2,6 +2,8 @@ use anyhow::anyhow;
 use libbpf_rs::XdpFlags;
 use pnet::datalink::{self, NetworkInterface};
 
+use crate::config::XdpMode;
+
 /// Turns interface name into corresponding index number.
 pub fn iface_to_idx(iface: &str) -> Result<i32, anyhow::Error> {
     let interfaces: Vec<NetworkInterface> = datalink::interfaces();
@@ -13,10 +15,11 @@ pub fn iface_to_idx(iface: &str) -> Result<i32, anyhow::Error> {
     Err(anyhow!("Interface not found"))
 }
 
-/// Turns XDP flag into corresponding type.
-pub fn get_xdp_flags(flags: &str) -> Result<XdpFlags, anyhow::Error> {
-    match flags {
-        "generic" => Ok(XdpFlags::SKB_MODE),
-        _ => Err(anyhow!("Flag not found!")),
+/// Turns XDP mode into corresponding flags.
+pub fn get_xdp_flags(mode: XdpMode) -> XdpFlags {
+    match mode {
+        XdpMode::Generic => XdpFlags::SKB_MODE,
+        XdpMode::Native => XdpFlags::DRV_MODE,
+        XdpMode::Offloaded => XdpFlags::HW_MODE,
     }
 }
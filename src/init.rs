@@ -0,0 +1,380 @@
+use std::{
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use crossterm::style::Stylize;
+use pnet::datalink;
+
+use crate::{
+    cli,
+    config::{
+        Blacklist, Config, ConfigFormat, Graylist, Init, ListAction, ProgType, Whitelist,
+        XdpAction, DEFAULT_FAST_PACKETS, DEFAULT_FREQUENCY,
+    },
+    get, layers, ssh, validate, WORKING_DIR,
+};
+
+static SSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// hpx init
+pub fn init(options: cli::Init) -> Result<(), anyhow::Error> {
+    println!(
+        "{}: Answer the questions to create a config. Press enter to keep the value in brackets\n",
+        "Init".magenta().bold()
+    );
+
+    let mut config = get::base_config();
+    let init = config.init.as_mut().unwrap();
+
+    println!("{}", "- Program -".on_magenta().black());
+    init.name = Some(ask(
+        "Program name",
+        init.name.as_deref().unwrap_or_default(),
+    )?);
+    init.prog_type = Some(choose("Program type", ProgType::ALL, init.get_prog_type())?);
+    init.xdp_action = Some(choose(
+        "Action for packets not in any list",
+        XdpAction::ALL,
+        init.get_xdp_action(),
+    )?);
+
+    println!("\n{}", "- Host -".on_magenta().black());
+    let mut remote_ifaces = None;
+    if confirm("Load program on a remote host over SSH?", false)? {
+        init.hostname = Some(ask_required("Hostname")?);
+        init.port = Some(ask_parse("SSH port", 22)?);
+        let username = ask("Username (sudoer)", "")?;
+        init.username = (!username.is_empty()).then_some(username);
+        let identity_file = ask("Private key file (empty for ssh-agent or ~/.ssh keys)", "")?;
+        init.identity_file = (!identity_file.is_empty()).then_some(identity_file);
+
+        if confirm("Test SSH connection now?", true)? {
+            match test_ssh(init) {
+                Ok(ifaces) => {
+                    println!(
+                        "{}: Connected to {}",
+                        "Init".magenta().bold(),
+                        init.hostname.as_ref().unwrap()
+                    );
+                    remote_ifaces = Some(ifaces);
+                }
+                Err(e) => {
+                    println!("{}: {:#}", "Init".magenta().bold(), e);
+                    if !confirm("Keep this host anyway?", true)? {
+                        return Err(anyhow!("Cancelled"));
+                    }
+                }
+            }
+        }
+    }
+
+    println!("\n{}", "- Network Interface -".on_magenta().black());
+    init.iface = Some(match (init.hostname.is_some(), remote_ifaces) {
+        (true, Some(ifaces)) if !ifaces.is_empty() => {
+            let default = ifaces[0].clone();
+            choose("Interface to attach program to", &ifaces, default)?
+        }
+        (true, _) => ask("Interface to attach program to", "eth0")?,
+        (false, _) => {
+            let ifaces = local_ifaces();
+            let default = ifaces
+                .iter()
+                .find(|i| i.as_str() != "lo")
+                .or(ifaces.first())
+                .cloned()
+                .with_context(|| "No network interfaces found".to_string())?;
+            choose("Interface to attach program to", &ifaces, default)?
+        }
+    });
+
+    println!("\n{}", "- Lists -".on_magenta().black());
+    init.whitelist = Some(ask_whitelist()?);
+    init.blacklist = Some(ask_blacklist()?);
+    init.graylist = Some(ask_graylist()?);
+
+    println!("\n{}", "- Output -".on_magenta().black());
+    let (format, path) = match options.output {
+        Some(p) => match ConfigFormat::from_extension(Path::new(&p)) {
+            Some(f) => (f, p),
+            None => (ask_format()?, p),
+        },
+        None => {
+            let format = ask_format()?;
+            let default_path = format!(
+                "{}/config.{}",
+                WORKING_DIR.display(),
+                format.to_string().to_lowercase()
+            );
+            (format, ask("Write config to", &default_path)?)
+        }
+    };
+
+    println!("\n{}\n", "- CONFIG -".on_blue().black());
+    println!("{}\n", config);
+
+    let issues = validate::validate(&config);
+    for i in &issues {
+        println!("{}: {}", "Validate".blue().bold(), i);
+    }
+    if !issues.is_empty() && !confirm("Config has problems. Write it anyway?", false)? {
+        return Err(anyhow!("Cancelled"));
+    }
+
+    write_config(&config, format, Path::new(&path), options.force)
+}
+
+fn ask_format() -> Result<ConfigFormat, anyhow::Error> {
+    choose(
+        "Config format",
+        &[ConfigFormat::Toml, ConfigFormat::Json],
+        ConfigFormat::Toml,
+    )
+}
+
+fn ask_whitelist() -> Result<Whitelist, anyhow::Error> {
+    let default = get::base_config().init.unwrap().whitelist.unwrap();
+    if !confirm("Enable whitelist?", true)? {
+        return Ok(Whitelist {
+            enabled: Some(false),
+            max: None,
+            evict: None,
+            action: None,
+            schedule: None,
+        });
+    }
+
+    Ok(Whitelist {
+        enabled: Some(true),
+        max: Some(ask_parse("  Max addresses", default.max.unwrap())?),
+        action: Some(choose(
+            "  Action",
+            &[ListAction::Allow, ListAction::Deny],
+            default.action.unwrap(),
+        )?),
+        ..default
+    })
+}
+
+fn ask_blacklist() -> Result<Blacklist, anyhow::Error> {
+    let default = get::base_config().init.unwrap().blacklist.unwrap();
+    if !confirm("Enable blacklist?", true)? {
+        return Ok(Blacklist {
+            enabled: Some(false),
+            max: None,
+            evict: None,
+            action: None,
+            schedule: None,
+        });
+    }
+
+    Ok(Blacklist {
+        enabled: Some(true),
+        max: Some(ask_parse("  Max addresses", default.max.unwrap())?),
+        action: Some(choose(
+            "  Action",
+            &[ListAction::Allow, ListAction::Deny],
+            default.action.unwrap(),
+        )?),
+        ..default
+    })
+}
+
+fn ask_graylist() -> Result<Graylist, anyhow::Error> {
+    if !confirm("Enable graylist (rate limiting)?", false)? {
+        return Ok(Graylist {
+            enabled: Some(false),
+            max: None,
+            evict: None,
+            action: None,
+            frequency: None,
+            fast_packet_count: None,
+            schedule: None,
+        });
+    }
+
+    let default = Graylist::default();
+    Ok(Graylist {
+        enabled: Some(true),
+        max: Some(ask_parse("  Max addresses", default.max.unwrap())?),
+        action: Some(choose(
+            "  Action",
+            ListAction::ALL,
+            default.action.unwrap(),
+        )?),
+        frequency: Some(ask_parse("  Frequency in ms", DEFAULT_FREQUENCY)?),
+        fast_packet_count: Some(ask_parse(
+            "  Fast packets allowed within frequency",
+            DEFAULT_FAST_PACKETS,
+        )?),
+        ..default
+    })
+}
+
+/// Names of local network interfaces that are up.
+fn local_ifaces() -> Vec<String> {
+    datalink::interfaces()
+        .into_iter()
+        .filter(|i| i.is_up())
+        .map(|i| i.name)
+        .collect()
+}
+
+/// Connects and authenticates like load does. Returns network interfaces of host.
+fn test_ssh(init: &Init) -> Result<Vec<String>, anyhow::Error> {
+    let host = ssh::login(init, "Init".magenta().bold())?;
+    host.session.set_timeout(SSH_TIMEOUT.as_millis() as u32);
+
+    let output = host
+        .run("ip -o link show up | awk -F': ' '{print $2}'", SSH_TIMEOUT)?
+        .with_context(|| format!("Listing network interfaces of {} timed out", host.hostname))?;
+    if output.status != 0 {
+        return Err(anyhow!(
+            "Failed to list network interfaces of {}: {}",
+            host.hostname,
+            output.stderr.trim()
+        ));
+    }
+
+    Ok(output
+        .stdout
+        .lines()
+        .map(|l| l.split('@').next().unwrap_or(l).trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect())
+}
+
+fn write_config(
+    config: &Config,
+    format: ConfigFormat,
+    path: &Path,
+    force: bool,
+) -> Result<(), anyhow::Error> {
+    if path.exists()
+        && !force
+        && !confirm(&format!("{} exists. Overwrite?", path.display()), false)?
+    {
+        return Err(anyhow!("Cancelled"));
+    }
+
+    let content = match format {
+        ConfigFormat::Toml => toml::to_string_pretty(config)?,
+        ConfigFormat::Json => {
+            // Unset values are left out, same as in TOML
+            let mut value = serde_json::to_value(config)?;
+            layers::strip_nulls(&mut value);
+            format!("{}\n", serde_json::to_string_pretty(&value)?)
+        }
+        ConfigFormat::Yaml => serde_yaml::to_string(config)?,
+    };
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    println!(
+        "{}: Config written to {}. Check it with \"hpx -c {} config validate\"",
+        "Init".magenta().bold(),
+        path.display(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn read_answer() -> Result<String, anyhow::Error> {
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        return Err(anyhow!("Cancelled"));
+    }
+    Ok(answer.trim().to_string())
+}
+
+fn ask(question: &str, default: &str) -> Result<String, anyhow::Error> {
+    if default.is_empty() {
+        print!("{}: {}: ", "Init".magenta().bold(), question);
+    } else {
+        print!("{}: {} [{}]: ", "Init".magenta().bold(), question, default);
+    }
+    let answer = read_answer()?;
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer
+    })
+}
+
+/// Asks until answer is not empty.
+fn ask_required(question: &str) -> Result<String, anyhow::Error> {
+    loop {
+        match ask(question, "")? {
+            a if a.is_empty() => println!("{}: {} is required", "Init".magenta().bold(), question),
+            a => return Ok(a),
+        }
+    }
+}
+
+/// Asks until answer parses.
+fn ask_parse<T>(question: &str, default: T) -> Result<T, anyhow::Error>
+where
+    T: FromStr + Display,
+    T::Err: Display,
+{
+    loop {
+        match ask(question, &default.to_string())?.parse() {
+            Ok(v) => return Ok(v),
+            Err(e) => println!("{}: {}", "Init".magenta().bold(), e),
+        }
+    }
+}
+
+fn confirm(question: &str, default: bool) -> Result<bool, anyhow::Error> {
+    loop {
+        print!(
+            "{}: {} {} ",
+            "Init".magenta().bold(),
+            question,
+            if default { "[Y/n]" } else { "[y/N]" }
+        );
+        match read_answer()?.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => continue,
+        }
+    }
+}
+
+/// Lists options and asks for one by number or name.
+fn choose<T: Display + Clone + PartialEq>(
+    question: &str,
+    options: &[T],
+    default: T,
+) -> Result<T, anyhow::Error> {
+    for (i, o) in options.iter().enumerate() {
+        println!("  {} {}", format!("{})", i + 1).bold(), o);
+    }
+
+    loop {
+        let answer = ask(question, &default.to_string())?;
+        let found = match answer.parse::<usize>() {
+            Ok(n) if n >= 1 => options.get(n - 1),
+            _ => options
+                .iter()
+                .find(|o| o.to_string().eq_ignore_ascii_case(&answer)),
+        };
+        match found {
+            Some(o) => return Ok(o.clone()),
+            None => println!(
+                "{}: Choose a number between 1 and {}",
+                "Init".magenta().bold(),
+                options.len()
+            ),
+        }
+    }
+}
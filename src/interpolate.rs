@@ -0,0 +1,210 @@
+use std::env;
+
+use anyhow::{anyhow, Context};
+use serde_json::Value;
+
+use crate::layers;
+
+/// Replaces ${VAR} and ${VAR:-default} in string values with environment variables. Default is
+/// used when variable is unset or empty. Value that is a single ${...} is read like --set values,
+/// so it becomes a number or boolean only for fields that take no strings. $${VAR} is left as
+/// ${VAR}. Returns true if anything was replaced.
+pub fn interpolate(value: &mut Value) -> Result<bool, anyhow::Error> {
+    let mut changed = false;
+    interpolate_value(value, "", &mut changed)?;
+    Ok(changed)
+}
+
+fn interpolate_value(
+    value: &mut Value,
+    path: &str,
+    changed: &mut bool,
+) -> Result<(), anyhow::Error> {
+    match value {
+        Value::String(s) => {
+            let expanded = match expand(s).with_context(|| format!("In {}", path))? {
+                Some(e) => e,
+                None => return Ok(()),
+            };
+            *changed = true;
+            *value = if is_single(s) {
+                layers::parse_value(path, &expanded)
+            } else {
+                Value::String(expanded)
+            };
+        }
+        Value::Array(a) => {
+            for (i, v) in a.iter_mut().enumerate() {
+                interpolate_value(v, &format!("{}[{}]", path, i), changed)?;
+            }
+        }
+        Value::Object(o) => {
+            for (k, v) in o.iter_mut() {
+                let p = if path.is_empty() {
+                    k.to_string()
+                } else {
+                    format!("{}.{}", path, k)
+                };
+                interpolate_value(v, &p, changed)?;
+            }
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Expanded string, None if it has nothing to expand.
+fn expand(s: &str) -> Result<Option<String>, anyhow::Error> {
+    let mut out = String::new();
+    let mut rest = s;
+    let mut changed = false;
+
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+
+        if let Some(a) = after.strip_prefix("${") {
+            out.push_str("${");
+            rest = a;
+            changed = true;
+            continue;
+        }
+        let expr = match after.strip_prefix('{') {
+            Some(e) => e,
+            None => {
+                out.push('$');
+                rest = after;
+                continue;
+            }
+        };
+
+        let end = expr
+            .find('}')
+            .with_context(|| format!("Missing }} in \"{}\"", s))?;
+        let (name, default) = match expr[..end].split_once(":-") {
+            Some((n, d)) => (n, Some(d)),
+            None => (&expr[..end], None),
+        };
+        if name.is_empty() {
+            return Err(anyhow!("Missing variable name in \"{}\"", s));
+        }
+
+        let v = match (env::var(name), default) {
+            (Ok(v), Some(d)) if v.is_empty() => d.to_string(),
+            (Ok(v), _) => v,
+            (Err(_), Some(d)) => d.to_string(),
+            (Err(_), None) => {
+                return Err(anyhow!(
+                    "Environment variable {} is not set. Set it or give a default with ${{{}:-default}}",
+                    name,
+                    name
+                ))
+            }
+        };
+        out.push_str(&v);
+        rest = &expr[end + 1..];
+        changed = true;
+    }
+    out.push_str(rest);
+
+    Ok(changed.then_some(out))
+}
+
+/// Whole value is a single ${...}
+fn is_single(s: &str) -> bool {
+    s.starts_with("${") && s.ends_with('}') && s[2..].find('}') == Some(s.len() - 3)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn expands_variables_and_defaults() {
+        env::set_var("HPX_TEST_EXPAND", "eth1");
+        env::set_var("HPX_TEST_EXPAND_EMPTY", "");
+        env::remove_var("HPX_TEST_EXPAND_UNSET");
+
+        let expand = |s| expand(s).unwrap();
+        assert_eq!(expand("${HPX_TEST_EXPAND}"), Some("eth1".to_string()));
+        assert_eq!(
+            expand("if-${HPX_TEST_EXPAND}.0"),
+            Some("if-eth1.0".to_string())
+        );
+        assert_eq!(
+            expand("${HPX_TEST_EXPAND_UNSET:-lo}"),
+            Some("lo".to_string())
+        );
+        assert_eq!(
+            expand("${HPX_TEST_EXPAND_EMPTY:-lo}"),
+            Some("lo".to_string())
+        );
+        assert_eq!(expand("${HPX_TEST_EXPAND:-lo}"), Some("eth1".to_string()));
+        assert_eq!(expand("${HPX_TEST_EXPAND_UNSET:-}"), Some(String::new()));
+        assert_eq!(expand("plain $ value"), None);
+    }
+
+    #[test]
+    fn keeps_escaped_variables() {
+        assert_eq!(
+            expand("$${HPX_TEST_EXPAND_UNSET}").unwrap(),
+            Some("${HPX_TEST_EXPAND_UNSET}".to_string())
+        );
+        assert_eq!(expand("a$$b").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_unset_variable_and_missing_brace() {
+        env::remove_var("HPX_TEST_EXPAND_UNSET");
+
+        let unset = expand("${HPX_TEST_EXPAND_UNSET}").unwrap_err().to_string();
+        assert!(
+            unset.contains("HPX_TEST_EXPAND_UNSET is not set"),
+            "{}",
+            unset
+        );
+        let brace = expand("${HPX_TEST_EXPAND").unwrap_err().to_string();
+        assert!(brace.contains("Missing }"), "{}", brace);
+        assert!(expand("${}").is_err());
+    }
+
+    #[test]
+    fn single_is_whole_value() {
+        assert!(is_single("${A}"));
+        assert!(is_single("${A:-1}"));
+        assert!(!is_single("${A}${B}"));
+        assert!(!is_single("x${A}"));
+        assert!(!is_single("${A}x"));
+        assert!(!is_single("$${A}"));
+    }
+
+    #[test]
+    fn single_values_take_type_of_field() {
+        env::set_var("HPX_TEST_TYPED_NUMBER", "123456");
+        env::set_var("HPX_TEST_TYPED_NULL", "null");
+
+        let mut value = json!({
+            "init": {
+                "username": "${HPX_TEST_TYPED_NUMBER}",
+                "hostname": "${HPX_TEST_TYPED_NULL}",
+                "port": "${HPX_TEST_TYPED_PORT:-2222}",
+                "blacklist": { "enabled": "${HPX_TEST_TYPED_BOOL:-true}" },
+            }
+        });
+        assert!(interpolate(&mut value).unwrap());
+        assert_eq!(
+            value,
+            json!({
+                "init": {
+                    "username": "123456",
+                    "hostname": "null",
+                    "port": 2222,
+                    "blacklist": { "enabled": true },
+                }
+            })
+        );
+    }
+}
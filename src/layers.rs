@@ -0,0 +1,621 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use anyhow::{anyhow, Context};
+use crossterm::style::Stylize;
+use serde_json::{Map, Value};
+
+use crate::{
+    config::{read_layer, Config, Init},
+    interpolate,
+    migrate::CURRENT_VERSION,
+    sources::{self, LIST_FILES},
+    WORKING_DIR,
+};
+
+static SYSTEM_DIR: &str = "/etc/hpx";
+static CONFIG_NAMES: [&str; 4] = ["config.json", "config.toml", "config.yaml", "config.yml"];
+// Only these prefixes are read so internal variables (HPX_ANALYZED etc.) are left alone
+static ENV_PREFIXES: [&str; 2] = ["HPX_INIT__", "HPX_DATA__"];
+/// Sections a profile can override.
+static PROFILE_KEYS: [&str; 2] = ["init", "data"];
+/// Config schema, tells which type text values of overrides are read as.
+static SCHEMA: LazyLock<Value> =
+    LazyLock::new(|| serde_json::to_value(schemars::schema_for!(Config)).unwrap_or_default());
+
+/// One source of configuration values.
+pub struct Layer {
+    pub source: String,
+    pub value: Value,
+}
+
+/// Merged configuration and where each value came from.
+pub struct Resolved {
+    pub config: Config,
+    pub value: Value,
+    /// Value path (f.e. init.iface) to its source.
+    pub origins: BTreeMap<String, String>,
+}
+
+/// Merges defaults, /etc/hpx/config.*, ~/.hpx/config.*, -c files, --profile, HPX_* environment
+/// variables and --set overrides, later ones winning.
+pub fn resolve(
+    files: &[String],
+    sets: &[String],
+    profile: Option<&str>,
+) -> Result<Resolved, anyhow::Error> {
+    let mut file_layers = Vec::new();
+    for dir in [PathBuf::from(SYSTEM_DIR), WORKING_DIR.to_path_buf()] {
+        if let Some(path) = find_config(&dir) {
+            file_layers.push(file_layer(&path)?);
+        }
+    }
+    for f in files.iter().filter(|f| !f.is_empty()) {
+        file_layers.push(file_layer(Path::new(f))?);
+    }
+
+    let mut layers = vec![defaults(file_layers.is_empty())?];
+    layers.extend(file_layers);
+
+    let mut value = Value::Object(Map::new());
+    let mut origins = BTreeMap::new();
+    for l in &layers {
+        merge(&mut value, &l.value, "", &l.source, &mut origins)?;
+    }
+
+    // Profile can be defined in any file, so it is picked after files are merged
+    let mut overrides = Vec::new();
+    if let Some(name) = profile {
+        overrides.push(profile_layer(&value, name)?);
+    }
+    overrides.extend(env_layers()?);
+    for s in sets {
+        overrides.push(set_layer(s)?);
+    }
+    for l in &overrides {
+        merge(&mut value, &l.value, "", &l.source, &mut origins)
+            .with_context(|| format!("In {}", l.source))?;
+    }
+
+    let mut config: Config = serde_json::from_value(value.clone())
+        .with_context(|| "Failed to apply configuration overrides".to_string())?;
+    // List file paths are already made absolute per layer
+    sources::resolve(&mut config, Path::new(""))?;
+
+    Ok(Resolved {
+        config,
+        value,
+        origins,
+    })
+}
+
+/// Built-in defaults. List sections are only used when there is no config file at all,
+/// otherwise omitted list would silently be enabled by defaults.
+fn defaults(with_lists: bool) -> Result<Layer, anyhow::Error> {
+    let mut value = serde_json::to_value(Config::default())?;
+
+    if !with_lists {
+        let init = Init::default();
+        value = serde_json::json!({
+            "init": {
+                "name": init.name,
+                "iface": init.iface,
+                "prog_type": init.prog_type,
+                "xdp_action": init.xdp_action,
+            }
+        });
+    }
+    strip_nulls(&mut value);
+
+    Ok(Layer {
+        source: "default".to_string(),
+        value,
+    })
+}
+
+/// init and data of profile chosen with --profile.
+fn profile_layer(value: &Value, name: &str) -> Result<Layer, anyhow::Error> {
+    let profiles = value.get("profiles").and_then(|p| p.as_object());
+
+    match profiles.and_then(|p| p.get(name)) {
+        Some(p) => {
+            if let Some(key) = p
+                .as_object()
+                .into_iter()
+                .flat_map(|p| p.keys())
+                .find(|k| !PROFILE_KEYS.contains(&k.as_str()))
+            {
+                return Err(anyhow!(
+                    "Profile \"{}\" can only override init and data, not {}",
+                    name,
+                    key
+                ));
+            }
+            Ok(Layer {
+                source: format!("profile {}", name),
+                value: p.clone(),
+            })
+        }
+        None => {
+            let available = profiles
+                .map(|p| p.keys().cloned().collect::<Vec<_>>().join(", "))
+                .filter(|a| !a.is_empty())
+                .unwrap_or("none".to_string());
+            Err(anyhow!(
+                "Profile \"{}\" not found in config. Available: {}",
+                name,
+                available
+            ))
+        }
+    }
+}
+
+/// Applies profile to config that is not read from files, f.e. one built by Lua script.
+pub fn apply_profile(value: &mut Value, name: &str) -> Result<(), anyhow::Error> {
+    let layer = profile_layer(value, name)?;
+    overlay(value, layer.value).with_context(|| format!("In {}", layer.source))
+}
+
+fn find_config(dir: &Path) -> Option<PathBuf> {
+    CONFIG_NAMES
+        .iter()
+        .map(|n| dir.join(n))
+        .find(|p| p.exists())
+}
+
+fn file_layer(path: &Path) -> Result<Layer, anyhow::Error> {
+    let (mut value, version) = read_layer(path)?;
+    interpolate::interpolate(&mut value).with_context(|| format!("In {}", path.display()))?;
+    strip_nulls(&mut value);
+    if version < CURRENT_VERSION {
+        eprintln!(
+            "{}: {} uses config version {}. Run \"hpx config migrate -c {}\" to upgrade it",
+            "Config".yellow().bold(),
+            path.display(),
+            version,
+            path.display()
+        );
+    }
+
+    // List files are relative to the config file that mentions them
+    rebase_list_files(&mut value, path.parent().unwrap_or(Path::new("")));
+
+    Ok(Layer {
+        source: path.display().to_string(),
+        value,
+    })
+}
+
+/// Joins list file paths of config, its programs and profiles to base. Absolute paths are kept.
+pub fn rebase_list_files(value: &mut Value, base: &Path) {
+    rebase_data(value.get_mut("data"), base);
+    for p in value
+        .get_mut("programs")
+        .and_then(|p| p.as_array_mut())
+        .into_iter()
+        .flatten()
+    {
+        rebase_data(p.get_mut("data"), base);
+    }
+    for p in value
+        .get_mut("profiles")
+        .and_then(|p| p.as_object_mut())
+        .into_iter()
+        .flat_map(|p| p.values_mut())
+    {
+        rebase_data(p.get_mut("data"), base);
+    }
+}
+
+fn rebase_data(data: Option<&mut Value>, base: &Path) {
+    let data = match data.and_then(|d| d.as_object_mut()) {
+        Some(d) => d,
+        None => return,
+    };
+
+    for list in LIST_FILES {
+        for f in data
+            .get_mut(list)
+            .and_then(|l| l.as_array_mut())
+            .into_iter()
+            .flatten()
+        {
+            let p = match f {
+                Value::String(p) => p,
+                Value::Object(o) => match o.get_mut("path") {
+                    Some(Value::String(p)) => p,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            *p = base.join(&p).to_string_lossy().to_string();
+        }
+    }
+}
+
+/// HPX_INIT__IFACE=lo sets init.iface, HPX_INIT__BLACKLIST__MAX=64 sets init.blacklist.max.
+fn env_layers() -> Result<Vec<Layer>, anyhow::Error> {
+    let mut vars: Vec<(String, String)> = env::vars()
+        .filter(|(k, _)| ENV_PREFIXES.iter().any(|p| k.starts_with(p)))
+        .collect();
+    vars.sort();
+
+    vars.into_iter()
+        .map(|(k, v)| {
+            let path = k
+                .trim_start_matches("HPX_")
+                .split("__")
+                .map(|s| s.to_lowercase())
+                .collect::<Vec<String>>()
+                .join(".");
+            Ok(Layer {
+                value: nested(&path, parse_value(&path, &v))?,
+                source: format!("env {}", k),
+            })
+        })
+        .collect()
+}
+
+/// --set init.iface=lo
+fn set_layer(set: &str) -> Result<Layer, anyhow::Error> {
+    let (path, value) = set
+        .split_once('=')
+        .with_context(|| format!("Invalid override \"{}\". Expected path=value", set))?;
+
+    Ok(Layer {
+        value: nested(path.trim(), parse_value(path.trim(), value.trim()))?,
+        source: format!("--set {}", set),
+    })
+}
+
+/// Value given as text for config path. Arrays and objects are read as JSON where field takes
+/// them. Numbers and booleans are only read for fields that don't take strings, so
+/// init.username=1234 stays a string while init.port=2222 is a number.
+pub fn parse_value(path: &str, value: &str) -> Value {
+    let types = field_types(path);
+    let takes_string = types.iter().any(|t| t == "string");
+    match serde_json::from_str(value) {
+        Ok(v @ (Value::Array(_) | Value::Object(_))) if takes(&types, &v) => v,
+        Ok(v @ (Value::Number(_) | Value::Bool(_))) if takes(&types, &v) && !takes_string => v,
+        _ => Value::String(value.to_string()),
+    }
+}
+
+/// Whether one of schema types is the type of value.
+fn takes(types: &[String], value: &Value) -> bool {
+    types.iter().any(|t| match (t.as_str(), value) {
+        ("array", Value::Array(_))
+        | ("object", Value::Object(_))
+        | ("boolean", Value::Bool(_))
+        | ("number", Value::Number(_)) => true,
+        ("integer", Value::Number(n)) => n.is_i64() || n.is_u64(),
+        _ => false,
+    })
+}
+
+/// JSON types config schema allows at path, f.e. init.port or init.bruteforce[0].port. Empty if
+/// path is not in schema.
+fn field_types(path: &str) -> Vec<String> {
+    let mut nodes: Vec<&Value> = vec![&SCHEMA];
+    for s in path.split('.') {
+        let (key, indexes) = match segment(s) {
+            Some(s) => s,
+            None => return Vec::new(),
+        };
+        nodes = nodes
+            .into_iter()
+            .flat_map(alternatives)
+            .filter_map(|n| {
+                n.get("properties")
+                    .and_then(|p| p.get(key))
+                    .or(n.get("additionalProperties").filter(|a| a.is_object()))
+            })
+            .collect();
+        for _ in indexes {
+            nodes = nodes
+                .into_iter()
+                .flat_map(alternatives)
+                .filter_map(|n| n.get("items"))
+                .collect();
+        }
+    }
+
+    let mut types = Vec::new();
+    for n in nodes.into_iter().flat_map(alternatives) {
+        match n.get("type") {
+            Some(Value::String(t)) => types.push(t.to_string()),
+            Some(Value::Array(a)) => {
+                types.extend(a.iter().filter_map(|t| t.as_str()).map(String::from))
+            }
+            _ => (),
+        }
+        if n.get("enum")
+            .and_then(|e| e.as_array())
+            .is_some_and(|e| e.iter().any(|v| v.is_string()))
+        {
+            types.push("string".to_string());
+        }
+    }
+    types
+}
+
+/// Schema and what it refers to, with anyOf/oneOf/allOf alternatives flattened.
+fn alternatives(schema: &Value) -> Vec<&Value> {
+    if let Some(r) = schema.get("$ref").and_then(|r| r.as_str()) {
+        return SCHEMA
+            .pointer(r.trim_start_matches('#'))
+            .map(alternatives)
+            .unwrap_or_default();
+    }
+
+    let mut out = vec![schema];
+    for k in ["anyOf", "oneOf", "allOf"] {
+        if let Some(a) = schema.get(k).and_then(|a| a.as_array()) {
+            out.extend(a.iter().flat_map(alternatives));
+        }
+    }
+    out
+}
+
+/// Key and array indexes of path segment, f.e. programs[0] is ("programs", [0]).
+fn segment(segment: &str) -> Option<(&str, Vec<usize>)> {
+    let (key, mut rest) = segment.split_at(segment.find('[').unwrap_or(segment.len()));
+    let mut indexes = Vec::new();
+    while !rest.is_empty() {
+        let (i, tail) = rest.strip_prefix('[')?.split_once(']')?;
+        indexes.push(i.parse().ok()?);
+        rest = tail;
+    }
+    (!key.is_empty()).then_some((key, indexes))
+}
+
+/// Index of array element key made by nested, f.e. [0].
+fn index(key: &str) -> Option<usize> {
+    key.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+/// Whether object only holds array elements, so it changes an existing array.
+fn is_elements(value: &Value) -> bool {
+    value
+        .as_object()
+        .is_some_and(|o| !o.is_empty() && o.keys().all(|k| index(k).is_some()))
+}
+
+/// Builds {"a": {"b": value}} from a.b. Array elements, f.e. of programs[0].init, are given as
+/// {"programs": {"[0]": {"init": value}}} and merged into elements config already has.
+fn nested(path: &str, value: Value) -> Result<Value, anyhow::Error> {
+    let segments = path
+        .split('.')
+        .map(segment)
+        .collect::<Option<Vec<_>>>()
+        .with_context(|| format!("Invalid config path \"{}\"", path))?;
+
+    Ok(segments
+        .into_iter()
+        .rev()
+        .fold(value, |acc, (key, indexes)| {
+            let acc = indexes.iter().rev().fold(acc, |acc, i| {
+                Value::Object(Map::from_iter([(format!("[{}]", i), acc)]))
+            });
+            Value::Object(Map::from_iter([(key.to_string(), acc)]))
+        }))
+}
+
+/// Deep merges objects. Arrays and other values are replaced, unless layer changes single
+/// elements.
+fn merge(
+    target: &mut Value,
+    layer: &Value,
+    path: &str,
+    source: &str,
+    origins: &mut BTreeMap<String, String>,
+) -> Result<(), anyhow::Error> {
+    match (target, layer) {
+        (t, Value::Object(l)) if is_elements(layer) => {
+            for (k, v) in l {
+                let i = index(k).unwrap_or_default();
+                let p = format!("{}[{}]", path, i);
+                let entry = match t {
+                    Value::Array(a) => {
+                        let len = a.len();
+                        a.get_mut(i).with_context(|| {
+                            format!("{} does not exist. {} has {} elements", p, path, len)
+                        })?
+                    }
+                    _ => return Err(anyhow!("{} does not exist. {} is not a list", p, path)),
+                };
+                merge(entry, v, &p, source, origins)?;
+            }
+        }
+        (Value::Object(t), Value::Object(l)) => {
+            for (k, v) in l {
+                let p = if path.is_empty() {
+                    k.to_string()
+                } else {
+                    format!("{}.{}", path, k)
+                };
+                let entry = t.entry(k.to_string()).or_insert(Value::Null);
+                if !is_elements(v) && (!v.is_object() || !entry.is_object()) {
+                    origins.retain(|o, _| {
+                        o != &p
+                            && !o.starts_with(&format!("{}.", p))
+                            && !o.starts_with(&format!("{}[", p))
+                    });
+                    *entry = match v {
+                        Value::Object(_) => Value::Object(Map::new()),
+                        _ => Value::Null,
+                    };
+                }
+                merge(entry, v, &p, source, origins)?;
+            }
+        }
+        (t, l) => {
+            *t = l.clone();
+            origins.insert(path.to_string(), source.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Merges layer over value the same way config layers are merged. Unset (null) values of layer
+/// are ignored.
+pub fn overlay(value: &mut Value, mut layer: Value) -> Result<(), anyhow::Error> {
+    strip_nulls(&mut layer);
+    merge(value, &layer, "", "", &mut BTreeMap::new())
+}
+
+/// Removes unset (null) values from objects.
+pub fn strip_nulls(value: &mut Value) {
+    if let Value::Object(o) = value {
+        o.retain(|_, v| !v.is_null());
+        o.values_mut().for_each(strip_nulls);
+    }
+}
+
+/// hpx config show
+pub fn show(resolved: &Resolved, with_origins: bool) -> Result<(), anyhow::Error> {
+    if !with_origins {
+        println!("{}", serde_json::to_string_pretty(&resolved.value)?);
+        return Ok(());
+    }
+
+    let width = resolved
+        .origins
+        .keys()
+        .map(|k| k.len())
+        .max()
+        .unwrap_or_default();
+    for (path, source) in &resolved.origins {
+        let value = path
+            .split('.')
+            .try_fold(&resolved.value, |v, s| {
+                let (key, indexes) = segment(s)?;
+                indexes.iter().try_fold(v.get(key)?, |v, i| v.get(i))
+            })
+            .unwrap_or(&Value::Null);
+        println!(
+            "{:<width$} = {}  {}",
+            path.as_str().bold(),
+            value,
+            format!("# {}", source).dark_grey(),
+            width = width
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn splits_path_segments() {
+        assert_eq!(segment("init"), Some(("init", vec![])));
+        assert_eq!(segment("programs[1]"), Some(("programs", vec![1])));
+        assert_eq!(segment("a[0][2]"), Some(("a", vec![0, 2])));
+        assert_eq!(segment("programs[x]"), None);
+        assert_eq!(segment("programs[0"), None);
+        assert_eq!(segment("[0]"), None);
+        assert_eq!(segment(""), None);
+    }
+
+    #[test]
+    fn nests_path() {
+        assert_eq!(
+            nested("init.iface", json!("lo")).unwrap(),
+            json!({"init": {"iface": "lo"}})
+        );
+        assert_eq!(
+            nested("programs[1].init.iface", json!("lo")).unwrap(),
+            json!({"programs": {"[1]": {"init": {"iface": "lo"}}}})
+        );
+        assert!(nested("init..iface", json!("lo")).is_err());
+        assert!(nested("", json!("lo")).is_err());
+    }
+
+    #[test]
+    fn merges_layers() {
+        let mut value = json!({
+            "init": {"name": "Top", "iface": "eth0"},
+            "programs": [{"init": {"name": "A"}}, {"init": {"name": "B"}}]
+        });
+        let mut origins = BTreeMap::new();
+
+        let layer = json!({"init": {"iface": "lo"}});
+        merge(&mut value, &layer, "", "set", &mut origins).unwrap();
+        let layer = nested("programs[1].init.iface", json!("eth1")).unwrap();
+        merge(&mut value, &layer, "", "set", &mut origins).unwrap();
+
+        assert_eq!(
+            value,
+            json!({
+                "init": {"name": "Top", "iface": "lo"},
+                "programs": [{"init": {"name": "A"}}, {"init": {"name": "B", "iface": "eth1"}}]
+            })
+        );
+        assert_eq!(
+            origins.keys().collect::<Vec<_>>(),
+            vec!["init.iface", "programs[1].init.iface"]
+        );
+
+        // Arrays of files are replaced whole, with origins of their elements
+        let layer = json!({"programs": [{"init": {"name": "C"}}]});
+        merge(&mut value, &layer, "", "file", &mut origins).unwrap();
+        assert_eq!(value["programs"], json!([{"init": {"name": "C"}}]));
+        assert_eq!(
+            origins.keys().collect::<Vec<_>>(),
+            vec!["init.iface", "programs"]
+        );
+
+        let layer = nested("programs[1].init.iface", json!("eth1")).unwrap();
+        let error = merge(&mut value, &layer, "", "set", &mut origins).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "programs[1] does not exist. programs has 1 elements"
+        );
+        let layer = nested("init[0].iface", json!("eth1")).unwrap();
+        let error = merge(&mut value, &layer, "", "set", &mut origins).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "init[0] does not exist. init is not a list"
+        );
+    }
+
+    #[test]
+    fn finds_field_types() {
+        let has = |path: &str, t: &str| field_types(path).iter().any(|f| f == t);
+
+        assert!(has("init.port", "integer"));
+        assert!(!has("init.port", "string"));
+        assert!(has("init.iface", "string"));
+        assert!(has("init.bruteforce[0].port", "integer"));
+        assert!(has("init.whitelist.enabled", "boolean"));
+        assert!(has("data.blacklist", "array"));
+        // Enums are strings
+        assert!(has("init.prog_type", "string"));
+        assert!(field_types("init.nope").is_empty());
+        assert!(field_types("init.bruteforce[x].port").is_empty());
+    }
+
+    #[test]
+    fn reads_values_by_field_type() {
+        assert_eq!(parse_value("init.username", "1234"), json!("1234"));
+        assert_eq!(parse_value("init.port", "2222"), json!(2222));
+        assert_eq!(parse_value("init.port", "ssh"), json!("ssh"));
+        assert_eq!(parse_value("init.whitelist.enabled", "true"), json!(true));
+        assert_eq!(
+            parse_value("data.blacklist", r#"["10.0.0.1"]"#),
+            json!(["10.0.0.1"])
+        );
+        // Only fields taking arrays or objects read them as JSON
+        assert_eq!(parse_value("init.iface", "[1]"), json!("[1]"));
+        assert_eq!(parse_value("init.nope", "1"), json!("1"));
+    }
+}
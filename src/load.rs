@@ -1,16 +1,14 @@
 use std::{
     fs::{self, create_dir_all, File},
-    io::{self, stdout, Read, Write},
-    net::TcpStream,
-    os::unix::fs::MetadataExt,
+    io::{self, stdout, Write},
     path::Path,
-    process::Command,
     sync::{Arc, Mutex},
     thread,
     time::Duration,
 };
 
 use anyhow::{anyhow, Context};
+use chrono::Utc;
 use crossterm::{
     style::Stylize,
     terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
@@ -19,17 +17,25 @@ use crossterm::{
 use libbpf_rs::{MapCore, MapFlags, ObjectBuilder};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use ssh2::Session;
 use tokio::signal;
 
 use crate::{
+    capacity,
     cli::Load,
-    config::{DEFAULT_NAME, DEFAULT_NET_IFACE},
-    maps::{self, load_map_data_local, load_map_data_local_temp, load_map_data_remote},
-    objects, programs, Config, SSH_PASS, WORKING_DIR,
+    config::{BuildMode, DEFAULT_NAME, DEFAULT_NET_IFACE, DEFAULT_XDP_MODE},
+    engine::{self, BTF_DUMP, CLANG_ARGS},
+    fleet, geo,
+    maps::{
+        self, load_control, load_control_local_temp, load_map_data, load_map_data_local_temp,
+        load_net_data, load_net_data_local_temp,
+    },
+    objects, programs, schedule,
+    ssh::shell_quote,
+    target::{Target, BUILD_TIMEOUT, COMMAND_TIMEOUT},
+    Config, WORKING_DIR,
 };
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Progs {
     ids: Vec<usize>,
     progs: Vec<Prog>,
@@ -38,6 +44,11 @@ pub struct Progs {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Prog {
     id: usize,
+    #[serde(default)]
+    name: String,
+    /// How program was compiled, None for local targets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    build: Option<BuildMode>,
     data: Vec<Maps>,
 }
 
@@ -55,28 +66,8 @@ struct ProgData {
 }
 
 pub async fn load(options: &mut Load, config: Config) -> Result<usize, anyhow::Error> {
-    let hostname = config.init.as_ref().unwrap().hostname.as_ref();
-    let port = config.init.as_ref().unwrap().port.as_ref();
-    let path = format!(
-        "{}/out/generated.o",
-        WORKING_DIR
-            .to_str()
-            .with_context(|| "Failed to parse HOME directory".to_string())?
-    );
-
-    if hostname.is_none()
-        || *hostname.as_ref().unwrap() == "localhost"
-        || *hostname.as_ref().unwrap() == "127.0.0.1"
-    {
-        match sudo::check() {
-            sudo::RunningAs::Root => (),
-            sudo::RunningAs::User => {
-                println!("{}: Requesting sudo privileges", "Load".red().bold());
-                let _ = sudo::with_env(&["HOME"]);
-            }
-            sudo::RunningAs::Suid => todo!(),
-        }
-    }
+    let path = engine::out_path(&config, "o")?;
+    let target = Target::connect(config.init.as_ref().unwrap(), "Load".red().bold())?;
 
     let config_iface = config.init.as_ref().unwrap().iface.as_ref();
 
@@ -84,6 +75,12 @@ pub async fn load(options: &mut Load, config: Config) -> Result<usize, anyhow::E
         && !options.iface.is_empty()
         && *config_iface.unwrap() != options.iface
     {
+        if fleet::is_host() {
+            return Err(anyhow!(
+                "Network interface {} differs from config and can't be confirmed on inventory hosts. Set iface of the host in inventory instead",
+                options.iface
+            ));
+        }
         let mut action = String::new();
         print!(
             "{}: Network interface differs from config. Are you sure you want to proceed? [Y/n] ",
@@ -111,11 +108,12 @@ pub async fn load(options: &mut Load, config: Config) -> Result<usize, anyhow::E
         );
         options.iface = String::from(DEFAULT_NET_IFACE);
     }
+    options
+        .xdp_flags
+        .get_or_insert(config.init.as_ref().unwrap().get_xdp_mode());
 
-    if hostname.is_none()
-        || *hostname.as_ref().unwrap() == "localhost"
-        || *hostname.as_ref().unwrap() == "127.0.0.1"
-    {
+    // Temporary programs live in this process, so they can't be loaded elsewhere
+    if let Target::Local = target {
         let mut action = String::new();
 
         print!(
@@ -132,63 +130,12 @@ pub async fn load(options: &mut Load, config: Config) -> Result<usize, anyhow::E
             load_local_temp(options, config, &path).await?;
             return Ok(0);
         } else if action == "2" {
-            return load_local(options, config, &path);
+            return load_target(options, config, &path, &target);
         }
 
         return Err(anyhow!("Cancelled"));
-    } else if hostname.is_some() {
-        let tcp =
-            TcpStream::connect(format!("{}:{}", hostname.unwrap(), port.unwrap_or(&22))).unwrap();
-        let mut session = Session::new().unwrap();
-        session.set_tcp_stream(tcp);
-        session.handshake().unwrap();
-
-        let mut username: String = String::new();
-        if config.init.as_ref().unwrap().username.is_none() {
-            print!("Username: ");
-            io::stdout().flush()?;
-            io::stdin().read_line(&mut username)?;
-        } else {
-            username = config
-                .init
-                .as_ref()
-                .unwrap()
-                .username
-                .as_ref()
-                .unwrap()
-                .to_string();
-            println!("{}: Using username \"{}\"", "Load".red().bold(), &username);
-        }
-
-        let password: String;
-        unsafe {
-            let pass = (*SSH_PASS.get()).lock().unwrap();
-            if !pass.is_empty() {
-                password = (*pass).clone();
-            } else {
-                password = rpassword::prompt_password("Password: ")?;
-            }
-        }
-
-        session.userauth_password(username.trim(), password.trim())?;
-
-        unsafe {
-            let pass = (*SSH_PASS.get()).lock().unwrap();
-            if pass.is_empty() {
-                let new = SSH_PASS.get().as_mut().unwrap();
-                *new = Mutex::new(password.clone());
-            }
-        }
-
-        println!(
-            "{}: Connected to {}\n",
-            "Load".red().bold(),
-            hostname.unwrap()
-        );
-        send_file(&config, &path, &session, &password)?;
-        return load_remote(options, config, &session, &password);
     }
-    Ok(0)
+    load_target(options, config, &path, &target)
 }
 
 async fn load_local_temp(
@@ -202,6 +149,10 @@ async fn load_local_temp(
     let whitelist = maps::get_map(&object, "whitelist");
     let blacklist = maps::get_map(&object, "blacklist");
     let graylist = maps::get_map(&object, "graylist");
+    let whitelist_net = maps::get_map(&object, "whitelist_net");
+    let blacklist_net = maps::get_map(&object, "blacklist_net");
+    let control = maps::get_map(&object, "control");
+    let overflow = maps::get_map(&object, "overflow");
     let programs =
         programs::get_programs(&object).with_context(|| "Program not found".to_string())?;
 
@@ -222,52 +173,59 @@ async fn load_local_temp(
 
     let mut bl_ip_arr: Vec<String> = Vec::new();
     let mut bl_last_arr_len: usize = 0;
+    let mut last_capacity: Vec<String> = Vec::new();
     stdout().execute(EnterAlternateScreen)?;
 
     if let Some(wl) = &whitelist {
-        if config.data.is_some() {
-            load_map_data_local_temp(
-                wl,
-                &config
-                    .data
-                    .as_ref()
-                    .unwrap()
-                    .whitelist
-                    .clone()
-                    .unwrap_or_default(),
-            )?
+        if let Some(data) = &config.data {
+            let entries = data.whitelist.clone().unwrap_or_default();
+            let failed = load_map_data_local_temp(wl, &entries)?;
+            capacity::report(config.init.as_ref().unwrap(), "whitelist", &entries, failed);
         }
     }
     if let Some(bl) = &blacklist {
-        if config.data.is_some() {
-            load_map_data_local_temp(
-                bl,
-                &config
-                    .data
-                    .as_ref()
-                    .unwrap()
-                    .blacklist
-                    .clone()
-                    .unwrap_or_default(),
-            )?
+        if let Some(data) = &config.data {
+            let entries = data.blacklist.clone().unwrap_or_default();
+            let failed = load_map_data_local_temp(bl, &entries)?;
+            capacity::report(config.init.as_ref().unwrap(), "blacklist", &entries, failed);
         }
     }
     if let Some(gl) = &graylist {
-        if config.data.is_some() {
-            load_map_data_local_temp(
-                gl,
-                &config
-                    .data
-                    .as_ref()
-                    .unwrap()
-                    .graylist
-                    .clone()
-                    .unwrap_or_default(),
-            )?
+        if let Some(data) = &config.data {
+            let entries = data.graylist.clone().unwrap_or_default();
+            let failed = load_map_data_local_temp(gl, &entries)?;
+            capacity::report(config.init.as_ref().unwrap(), "graylist", &entries, failed);
         }
     }
 
+    if let Some(wn) = &whitelist_net {
+        load_net_data_local_temp(
+            wn,
+            &geo::expand_list(
+                config.data.as_ref().and_then(|d| d.whitelist.as_ref()),
+                config.init.as_ref().unwrap().mmdb.as_ref(),
+            )?,
+        )?
+    }
+    if let Some(bn) = &blacklist_net {
+        load_net_data_local_temp(
+            bn,
+            &geo::expand_list(
+                config.data.as_ref().and_then(|d| d.blacklist.as_ref()),
+                config.init.as_ref().unwrap().mmdb.as_ref(),
+            )?,
+        )?
+    }
+
     while !(*should_terminate.lock().unwrap()) {
+        // Temporary load has no separate scheduler, so keep control map in sync here
+        if let Some(c) = &control {
+            load_control_local_temp(
+                c,
+                &schedule::control_state(config.init.as_ref().unwrap(), Utc::now())?,
+            )?;
+        }
+
         // to_be_bytes converts to [127, 0, 0, 1]
         if let Some(bl) = &blacklist {
             for k in bl.keys() {
@@ -277,7 +235,7 @@ async fn load_local_temp(
                 let data = bl.lookup(&k, MapFlags::ANY)?.unwrap();
                 for (i, b) in data[0..4].iter().enumerate() {
                     if i > 0 {
-                        ip.push_str(format!(".{}", &b.to_string()).as_str());
+                        ip.push_str(format!(".{}", b).as_str());
                         continue;
                     }
                     ip.push_str((b.to_string()).to_string().as_str());
@@ -289,13 +247,42 @@ async fn load_local_temp(
             }
         }
 
+        // Capacity rows: used/max, rejected insertions and fill warning per list
+        let rejected = match &overflow {
+            Some(o) => capacity::overflow_counts(o)?,
+            None => vec![0; capacity::OVERFLOW_SIZE],
+        };
+        let mut capacity_rows: Vec<String> = Vec::new();
+        for (name, map, index) in [
+            ("whitelist", &whitelist, capacity::OVERFLOW_WHITELIST),
+            ("blacklist", &blacklist, capacity::OVERFLOW_BLACKLIST),
+            ("graylist", &graylist, capacity::OVERFLOW_GRAYLIST),
+        ] {
+            let (Some(m), Some((max, _))) =
+                (map, capacity::capacity(config.init.as_ref().unwrap(), name))
+            else {
+                continue;
+            };
+            let used = m.keys().count();
+            capacity_rows.push(format!(
+                " {:<22} │ {}/{} ({} rejected)",
+                name,
+                used,
+                max,
+                rejected[index as usize]
+            ));
+            if let Some(w) = capacity::fill_warning(config.init.as_ref().unwrap(), name, used) {
+                capacity_rows.push(format!(" {}", w.yellow()));
+            }
+        }
+
         let last_blaclisted_ip: &str = if !bl_ip_arr.is_empty() {
             bl_ip_arr.last().unwrap()
         } else {
             "No blacklisted IPs."
         };
 
-        if bl_last_arr_len != bl_ip_arr.len() {
+        if bl_last_arr_len != bl_ip_arr.len() || last_capacity != capacity_rows {
             stdout().execute(Clear(ClearType::All))?;
             println!(
                 "├────────────── {} ───────────────┤",
@@ -304,7 +291,7 @@ async fn load_local_temp(
             println!("├───────────────────────┬────────────────┤");
             println!(
                 " Total whitelisted IPs  │ {}",
-                &config
+                config
                     .data
                     .as_ref()
                     .unwrap()
@@ -327,8 +314,18 @@ async fn load_local_temp(
             );
             println!("├───────────────────────┼────────────────┤");
             println!(" Last banned IP         │ {} ", last_blaclisted_ip.bold());
+            println!("└───────────────────────┴────────────────┘\n");
+            println!(
+                "├────────────── {} ────────────────┤",
+                "CAPACITY".to_string().bold().white()
+            );
+            println!("├───────────────────────┬────────────────┤");
+            for row in &capacity_rows {
+                println!("{}", row);
+            }
             println!("└───────────────────────┴────────────────┘");
             bl_last_arr_len = bl_ip_arr.len();
+            last_capacity = capacity_rows;
         }
         thread::sleep(Duration::from_secs(5));
     }
@@ -338,247 +335,185 @@ async fn load_local_temp(
     Ok(())
 }
 
-fn load_local(options: &mut Load, config: Config, path: &str) -> Result<usize, anyhow::Error> {
-    let name = config.init.as_ref().unwrap().name.as_ref();
-    let xdp_flag = match options.xdp_flags.as_ref() {
-        "generic" => "xdpgeneric",
-        "native" => "xdpdrv",
-        "offloaded" => "xdpoffload",
-        _ => "xdpgeneric",
-    };
-    let mut prog_id: u64 = 0;
-
-    Command::new("bpftool")
-        .arg("prog")
-        .arg("load")
-        .arg(path)
-        .arg(format!(
-            "/sys/fs/bpf/{}",
-            name.unwrap_or(&DEFAULT_NAME.to_string())
-        ))
-        .output()?;
-
-    let output = String::from_utf8(
-        Command::new("bpftool")
-            .arg("prog")
-            .arg("show")
-            .arg("-j")
-            .output()
-            .unwrap()
-            .stdout,
-    )?;
-
-    let progs: Value = serde_json::from_str(&output)?;
-    if let Some(progs) = progs.as_array() {
-        for p in progs {
-            if *name.unwrap_or(&DEFAULT_NAME.to_string()) == p["name"] {
-                prog_id = p["id"].as_u64().with_context(|| {
-                    format!(
-                        "Program {} was not loaded",
-                        &name.unwrap_or(&DEFAULT_NAME.to_string())
-                    )
-                })?;
-                break;
-            }
-        }
-    } else {
-        return Err(anyhow!(
-            "Program {} was not loaded",
-            &name.unwrap_or(&DEFAULT_NAME.to_string())
-        ));
-    }
-
-    let output = String::from_utf8(
-        Command::new("bpftool")
-            .arg("map")
-            .arg("show")
-            .arg("-j")
-            .output()
-            .unwrap()
-            .stdout,
-    )?;
-    let maps: Value = serde_json::from_str(&output)?;
-
-    // Load map data if needed
-    let mut data: Vec<Maps> = vec![];
-    if let Some(maps) = maps.as_array() {
-        for m in maps {
-            if "whitelist" == m["name"]
-                && config.data.as_ref().is_some()
-                && config.data.as_ref().unwrap().whitelist.as_ref().is_some()
-            {
-                let wid = m["id"]
-                    .as_u64()
-                    .with_context(|| "Map 'whitelist' was not created".to_string())?;
-                load_map_data_local(
-                    wid,
-                    config.data.as_ref().unwrap().whitelist.as_ref().unwrap(),
-                )?;
-                data.push(Maps {
-                    whitelist: Some(ProgData {
-                        key: config.data.as_ref().unwrap().whitelist.clone().unwrap(),
-                        value: Vec::new(),
-                    }),
-                    blacklist: None,
-                    graylist: None,
-                });
-                continue;
-            }
-            if "blacklist" == m["name"]
-                && config.data.as_ref().is_some()
-                && config.data.as_ref().unwrap().blacklist.as_ref().is_some()
-            {
-                let bid = m["id"]
-                    .as_u64()
-                    .with_context(|| "Map 'blacklist' was not created".to_string())?;
-                load_map_data_local(
-                    bid,
-                    config.data.as_ref().unwrap().blacklist.as_ref().unwrap(),
-                )?;
-                data.push(Maps {
-                    whitelist: None,
-                    blacklist: Some(ProgData {
-                        key: config.data.as_ref().unwrap().blacklist.clone().unwrap(),
-                        value: Vec::new(),
-                    }),
-                    graylist: None,
-                });
-                continue;
-            }
-            if "graylist" == m["name"]
-                && config.data.as_ref().is_some()
-                && config.data.as_ref().unwrap().graylist.as_ref().is_some()
-            {
-                let gid = m["id"]
-                    .as_u64()
-                    .with_context(|| "Map 'graylist' was not created".to_string())?;
-                load_map_data_local(
-                    gid,
-                    config.data.as_ref().unwrap().graylist.as_ref().unwrap(),
-                )?;
-                data.push(Maps {
-                    whitelist: None,
-                    blacklist: None,
-                    graylist: Some(ProgData {
-                        key: config.data.as_ref().unwrap().graylist.clone().unwrap(),
-                        value: Vec::new(),
-                    }),
-                });
-                continue;
-            }
-        }
-    } else {
-        return Err(anyhow!(
-            "Program {} was not loaded",
-            &name.unwrap_or(&DEFAULT_NAME.to_string())
-        ));
-    }
-
-    Command::new("bpftool")
-        .arg("net")
-        .arg("attach")
-        .arg(xdp_flag)
-        .arg("id")
-        .arg(prog_id.to_string())
-        .arg("dev")
-        .arg(&options.iface)
-        .output()?;
-
-    let p = format!(
-        "{}/data",
+fn progs_path() -> Result<String, anyhow::Error> {
+    Ok(format!(
+        "{}/data/progs.json",
         WORKING_DIR
             .to_str()
             .with_context(|| "Failed to parse HOME directory".to_string())?,
-    );
-    let path = Path::new(&p);
-
-    if !path.exists() {
-        create_dir_all(path)?;
-    }
+    ))
+}
 
-    let mut loaded_progs;
+fn read_loaded() -> Progs {
+    progs_path()
+        .ok()
+        .and_then(|p| fs::read_to_string(p).ok())
+        .and_then(|c| serde_json::from_str(&c).ok())
+        .unwrap_or_default()
+}
 
-    let p = format!(
-        "{}/data/progs.json",
-        WORKING_DIR
-            .to_str()
-            .with_context(|| "Failed to parse HOME directory".to_string())?,
-    );
+fn write_loaded(progs: &Progs) -> Result<(), anyhow::Error> {
+    let p = progs_path()?;
     let path = Path::new(&p);
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
 
-    loaded_progs = File::create(path)?;
-    let progs: Progs = Progs {
-        ids: vec![prog_id as usize],
-        progs: vec![Prog {
-            id: prog_id as usize,
-            data,
-        }],
-    };
-    let json_data = serde_json::to_string(&progs)?;
+    let mut loaded_progs = File::create(path)?;
+    let json_data = serde_json::to_string(progs)?;
     loaded_progs.write_all(json_data.as_bytes())?;
+    Ok(())
+}
 
-    Ok(prog_id as usize)
+/// Records loaded program in progs.json. Earlier entry of program with same name is replaced.
+fn save_loaded(
+    name: &str,
+    prog_id: u64,
+    build: Option<BuildMode>,
+    data: Vec<Maps>,
+) -> Result<(), anyhow::Error> {
+    let mut progs = read_loaded();
+    progs.progs.retain(|p| p.name != name);
+    progs.progs.push(Prog {
+        id: prog_id as usize,
+        name: name.to_string(),
+        build,
+        data,
+    });
+    progs.ids = progs.progs.iter().map(|p| p.id).collect();
+    write_loaded(&progs)
 }
 
-fn send_file(
+/// Removes unloaded program from progs.json.
+pub fn forget_loaded(name: &str) -> Result<(), anyhow::Error> {
+    let mut progs = read_loaded();
+    progs.progs.retain(|p| p.name != name);
+    progs.ids = progs.progs.iter().map(|p| p.id).collect();
+    write_loaded(&progs)
+}
+
+/// Puts compiled program on target, built as init.build says. Local and namespace targets share
+/// the kernel of this machine, so local object is used for them. Returns build mode used.
+fn send_program(
     config: &Config,
     path: &str,
-    session: &Session,
-    password: &str,
-) -> Result<(), anyhow::Error> {
-    let name = config.init.as_ref().unwrap().name.as_ref().unwrap();
-    let size = File::open(path)?.metadata()?.size();
-    let file_contents = fs::read(path)?;
-
-    println!("{}: Sending compiled eBPF program...", "Load".red().bold());
-    let mut channel = session
-        .scp_send(Path::new("/tmp/generated.o"), 0o644, size, None)
-        .unwrap();
-    channel.write_all(&file_contents)?;
-    channel.send_eof()?;
-    channel.wait_eof()?;
-    channel.close()?;
-    channel.wait_close()?;
+    target: &Target,
+) -> Result<Option<BuildMode>, anyhow::Error> {
+    let name = config
+        .init
+        .as_ref()
+        .unwrap()
+        .name
+        .as_deref()
+        .unwrap_or(DEFAULT_NAME);
+    let remote_path = format!("/tmp/{}.o", name);
+    let build = match target {
+        Target::Ssh(_) => Some(config.init.as_ref().unwrap().get_build()),
+        Target::Local | Target::Netns(_) => None,
+    };
+
+    match build {
+        None | Some(BuildMode::Local) => {
+            println!("{}: Sending compiled eBPF program...", "Load".red().bold());
+            target.write(&remote_path, &fs::read(path)?)?;
+        }
+        Some(BuildMode::TargetBtf) => {
+            println!(
+                "{}: Fetching kernel types of {}...",
+                "Load".red().bold(),
+                target
+            );
+            let btf = target
+                .exec(BTF_DUMP, COMMAND_TIMEOUT)
+                .with_context(|| format!("Failed to dump BTF of {}", target))?;
+            println!("{}: Compiling eBPF program...", "Load".red().bold());
+            let object = engine::compile_with_btf(config, &target.to_string(), &btf.stdout)?;
+            println!("{}: Sending compiled eBPF program...", "Load".red().bold());
+            target.write(&remote_path, &fs::read(object)?)?;
+        }
+        Some(BuildMode::Target) => {
+            let source = engine::out_path(config, "c")?;
+            let remote_source = format!("/tmp/{}.c", name);
+            println!("{}: Sending eBPF program source...", "Load".red().bold());
+            target.write(
+                &remote_source,
+                &fs::read(&source).with_context(|| {
+                    format!("{} was not generated. Run hpx generate first", source)
+                })?,
+            )?;
+
+            println!(
+                "{}: Compiling eBPF program on {}...",
+                "Load".red().bold(),
+                target
+            );
+            // Source is compiled next to vmlinux.h of target in a directory of its own
+            target
+                .exec(
+                    &format!(
+                        "d=$(mktemp -d) && cp {src} \"$d\" && {} > \"$d/vmlinux.h\" && \
+                         clang {} -c \"$d\"/{name} -o {obj}; s=$?; rm -rf \"$d\" {src}; exit $s",
+                        BTF_DUMP,
+                        CLANG_ARGS,
+                        src = shell_quote(&remote_source),
+                        name = shell_quote(&format!("{}.c", name)),
+                        obj = shell_quote(&remote_path),
+                    ),
+                    BUILD_TIMEOUT,
+                )
+                .with_context(|| format!("Failed to compile program on {}", target))?;
+        }
+    }
+    if let Some(build) = build {
+        let how = match build {
+            BuildMode::Local => "compiled here against kernel types of this machine".to_string(),
+            BuildMode::TargetBtf => format!("compiled here against kernel types of {}", target),
+            BuildMode::Target => format!("compiled on {}", target),
+        };
+        println!("{}: Build mode: {} ({})", "Load".red().bold(), build, how);
+    }
 
     println!("{}: Loading eBPF program...", "Load".red().bold());
-    let mut channel = session.channel_session().unwrap();
-    channel.exec(
-        format!(
-            "echo {} | sudo -S bpftool prog load {} /sys/fs/bpf/{}",
-            password, "/tmp/generated.o", name
+    target
+        .exec(
+            &format!(
+                "bpftool prog load {} {}",
+                shell_quote(&remote_path),
+                shell_quote(&format!("/sys/fs/bpf/{}", name))
+            ),
+            COMMAND_TIMEOUT,
         )
-        .as_str(),
-    )?;
-    Ok(())
+        .with_context(|| "Failed to load program".to_string())?;
+    Ok(build)
 }
-fn load_remote(
+fn load_target(
     options: &mut Load,
     config: Config,
-    session: &Session,
-    password: &str,
+    path: &str,
+    target: &Target,
 ) -> Result<usize, anyhow::Error> {
-    let mut prog_id: u64 = 0;
-    let name = config.init.as_ref().unwrap().name.as_ref().unwrap();
-    let xdp_flag = match options.xdp_flags.as_ref() {
-        "generic" => "xdpgeneric",
-        "native" => "xdpdrv",
-        "offloaded" => "xdpoffload",
-        _ => "xdpgeneric",
-    };
-
-    let mut output = String::new();
-    let mut channel = session.channel_session()?;
-    channel.exec(format!("echo {} | sudo -S bpftool prog show -j", password).as_str())?;
-    channel.read_to_string(&mut output)?;
-    channel.wait_close()?;
+    let build = send_program(&config, path, target)?;
 
+    let mut prog_id: u64 = 0;
+    let mut map_ids: Vec<u64> = Vec::new();
+    let name = config
+        .init
+        .as_ref()
+        .unwrap()
+        .name
+        .as_deref()
+        .unwrap_or(DEFAULT_NAME);
+    let xdp_flag = options.xdp_flags.unwrap_or(DEFAULT_XDP_MODE).bpftool_flag();
+
+    let output = target.exec("bpftool prog show -j", COMMAND_TIMEOUT)?.stdout;
     let progs: Value = serde_json::from_str(&output)?;
     if let Some(progs) = progs.as_array() {
         for p in progs {
             if *name == p["name"] {
                 prog_id = p["id"]
                     .as_u64()
-                    .with_context(|| format!("Program {} was not loaded", &name))?;
+                    .with_context(|| format!("Program {} was not loaded", name))?;
+                map_ids = maps::map_ids(p);
                 break;
             }
         }
@@ -586,19 +521,49 @@ fn load_remote(
         return Err(anyhow!("Program {} was not loaded", &name));
     }
 
-    output.clear();
-
-    channel = session.channel_session()?;
-    channel.exec(format!("echo {} | sudo -S bpftool map show -j", password).as_str())?;
-    channel.read_to_string(&mut output)?;
-    channel.wait_close()?;
+    let output = target.exec("bpftool map show -j", COMMAND_TIMEOUT)?.stdout;
     let maps: Value = serde_json::from_str(&output)?;
 
     println!("{}: Loading map data...", "Load".red().bold());
     // Load map data if needed
     let mut data: Vec<Maps> = vec![];
+    let mut scheduled = false;
     if let Some(maps) = maps.as_array() {
-        for m in maps {
+        // Only maps of this program. Other loaded programs have maps with same names
+        for m in maps
+            .iter()
+            .filter(|m| map_ids.contains(&m["id"].as_u64().unwrap_or_default()))
+        {
+            if "control" == m["name"] {
+                let id = m["id"]
+                    .as_u64()
+                    .with_context(|| "Map 'control' was not created".to_string())?;
+                load_control(
+                    id,
+                    &schedule::control_state(config.init.as_ref().unwrap(), Utc::now())?,
+                    target,
+                )?;
+                scheduled = true;
+                continue;
+            }
+            if "whitelist_net" == m["name"] || "blacklist_net" == m["name"] {
+                let id = m["id"]
+                    .as_u64()
+                    .with_context(|| format!("Map {} was not created", m["name"]))?;
+                let entries = config.data.as_ref().and_then(|d| {
+                    if "whitelist_net" == m["name"] {
+                        d.whitelist.as_ref()
+                    } else {
+                        d.blacklist.as_ref()
+                    }
+                });
+                load_net_data(
+                    id,
+                    &geo::expand_list(entries, config.init.as_ref().unwrap().mmdb.as_ref())?,
+                    target,
+                )?;
+                continue;
+            }
             if "whitelist" == m["name"]
                 && config.data.as_ref().is_some()
                 && config.data.as_ref().unwrap().whitelist.as_ref().is_some()
@@ -606,12 +571,17 @@ fn load_remote(
                 let wid = m["id"]
                     .as_u64()
                     .with_context(|| "Map 'whitelist' was not created".to_string())?;
-                load_map_data_remote(
+                let failed = load_map_data(
                     wid,
                     config.data.as_ref().unwrap().whitelist.as_ref().unwrap(),
-                    session,
-                    password,
+                    target,
                 )?;
+                capacity::report(
+                    config.init.as_ref().unwrap(),
+                    "whitelist",
+                    config.data.as_ref().unwrap().whitelist.as_ref().unwrap(),
+                    failed,
+                );
                 data.push(Maps {
                     whitelist: Some(ProgData {
                         key: config.data.as_ref().unwrap().whitelist.clone().unwrap(),
@@ -629,12 +599,17 @@ fn load_remote(
                 let bid = m["id"]
                     .as_u64()
                     .with_context(|| "Map 'blacklist' was not created".to_string())?;
-                load_map_data_remote(
+                let failed = load_map_data(
                     bid,
                     config.data.as_ref().unwrap().blacklist.as_ref().unwrap(),
-                    session,
-                    password,
+                    target,
                 )?;
+                capacity::report(
+                    config.init.as_ref().unwrap(),
+                    "blacklist",
+                    config.data.as_ref().unwrap().blacklist.as_ref().unwrap(),
+                    failed,
+                );
                 data.push(Maps {
                     whitelist: None,
                     blacklist: Some(ProgData {
@@ -652,12 +627,17 @@ fn load_remote(
                 let gid = m["id"]
                     .as_u64()
                     .with_context(|| "Map 'graylist' was not created".to_string())?;
-                load_map_data_remote(
+                let failed = load_map_data(
                     gid,
                     config.data.as_ref().unwrap().graylist.as_ref().unwrap(),
-                    session,
-                    password,
+                    target,
                 )?;
+                capacity::report(
+                    config.init.as_ref().unwrap(),
+                    "graylist",
+                    config.data.as_ref().unwrap().graylist.as_ref().unwrap(),
+                    failed,
+                );
                 data.push(Maps {
                     whitelist: None,
                     blacklist: None,
@@ -673,49 +653,26 @@ fn load_remote(
         return Err(anyhow!("Program {} was not loaded", &name));
     }
 
-    channel = session.channel_session().unwrap();
-    channel.exec(
-        format!(
-            "echo {} | sudo -S bpftool net attach {} id {} dev {}",
-            password, xdp_flag, prog_id, &options.iface
+    target
+        .exec(
+            &format!(
+                "bpftool net attach {} id {} dev {}",
+                xdp_flag,
+                prog_id,
+                shell_quote(&options.iface)
+            ),
+            COMMAND_TIMEOUT,
         )
-        .as_str(),
-    )?;
-    channel.read_to_string(&mut output).unwrap();
-    channel.wait_close()?;
+        .with_context(|| format!("Failed to attach program to {}", options.iface))?;
 
-    let p = format!(
-        "{}/data",
-        WORKING_DIR
-            .to_str()
-            .with_context(|| "Failed to parse HOME directory".to_string())?,
-    );
-    let path = Path::new(&p);
+    save_loaded(name, prog_id, build, data)?;
 
-    if !path.exists() {
-        create_dir_all(path)?;
+    if scheduled {
+        println!(
+            "{}: Program has schedules. Run \"hpx schedule\" to keep them enforced",
+            "Load".red().bold()
+        );
     }
 
-    let mut loaded_progs;
-
-    let p = format!(
-        "{}/data/progs.json",
-        WORKING_DIR
-            .to_str()
-            .with_context(|| "Failed to parse HOME directory".to_string())?,
-    );
-    let path = Path::new(&p);
-
-    loaded_progs = File::create(path)?;
-    let progs: Progs = Progs {
-        ids: vec![prog_id as usize],
-        progs: vec![Prog {
-            id: prog_id as usize,
-            data,
-        }],
-    };
-    let json_data = serde_json::to_string(&progs)?;
-    loaded_progs.write_all(json_data.as_bytes())?;
-
     Ok(prog_id as usize)
 }
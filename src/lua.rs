@@ -1,4 +1,8 @@
-use std::{fs, path::Path};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use crossterm::style::Stylize;
 use mlua::{ExternalResult, Lua, LuaSerdeExt};
@@ -7,15 +11,23 @@ use serde_json::Value as JsonValue;
 
 use crate::{
     analyze,
-    cli::{Analyze, Generate, Load, Unload},
+    cli::{Analyze, Fleet, Generate, Load, Unload},
     config::Config,
     engine::generator,
+    interpolate, layers,
     load::load,
     maps::get_map_data,
+    migrate, sources,
+    target::TargetKind,
     unload::unload,
+    validate,
 };
 
-pub async fn run_script(work_dir: &str, script_path: Option<&str>) -> mlua::Result<()> {
+pub async fn run_script(
+    work_dir: &str,
+    script_path: Option<&str>,
+    profile: Option<&str>,
+) -> mlua::Result<()> {
     let default_path = format!("{}/scripts/run.lua", work_dir);
     let mut curr_path = std::env::current_dir().expect("Error: Failed to get current directory");
     let mut path = Path::new(&default_path);
@@ -24,92 +36,108 @@ pub async fn run_script(work_dir: &str, script_path: Option<&str>) -> mlua::Resu
         path = Path::new(p)
     }
 
-    if script_path.is_some() {
-        curr_path.push(script_path.unwrap());
+    if let Some(p) = script_path {
+        curr_path.push(p);
         path = curr_path.as_path();
     }
 
     if path.exists() {
         let lua = Lua::new();
+        // List files of configs are relative to the script, like those of config files
+        lua.set_app_data(ScriptDir(
+            path.parent().unwrap_or(Path::new("")).to_path_buf(),
+        ));
+        if let Some(name) = profile {
+            lua.set_app_data(Profile(name.to_string()));
+        }
 
         let analyze_func = lua.create_async_function(|lua, opts: mlua::Table| async move {
             let cfg: mlua::Table = opts.get(1)?;
             if std::env::var("HPX_ANALYZED").unwrap_or("0".to_string()) == "1" {
                 return Ok(true);
             }
-            let val = cfg.serialize(mlua::serde::Serializer::new(&lua))?;
-            let json_data = serde_json::to_string(&val).map_err(mlua::Error::external)?;
-            let config: Config = serde_json::from_str(&json_data).map_err(mlua::Error::external)?;
-            match analyze(
-                Analyze {
-                    noconfirm: Some("".to_string()),
-                },
-                config,
-            )
-            .await
-            {
-                Ok(ret) => {
-                    std::env::set_var("HPX_ANALYZED", "1");
-                    Ok(ret)
+            let program: Option<String> = opts.get(2)?;
+            let mut compatible = true;
+            for config in select(read_config(&lua, cfg)?, program)? {
+                match analyze(
+                    Analyze {
+                        noconfirm: Some("".to_string()),
+                        fleet: Fleet::default(),
+                    },
+                    config,
+                )
+                .await
+                {
+                    Ok(ret) => compatible &= ret,
+                    Err(e) => return Err(mlua::Error::runtime(e)),
                 }
-                Err(e) => Err(mlua::Error::runtime(e)),
             }
+            std::env::set_var("HPX_ANALYZED", "1");
+            Ok(compatible)
         })?;
 
-        let generate_func =
-            lua.create_function(|lua, opts: mlua::Table| -> mlua::Result<(bool, String)> {
+        let generate_func = lua.create_function(
+            |lua, opts: mlua::Table| -> mlua::Result<(bool, mlua::Value)> {
                 let cfg: mlua::Table = opts.get(1)?;
                 if std::env::var("HPX_GENERATED").unwrap_or("0".to_string()) == "1" {
-                    return Ok((false, "".to_string()));
+                    return Ok((false, lua.to_value("")?));
                 }
-                let val = cfg.serialize(mlua::serde::Serializer::new(lua))?;
-                let json_data = serde_json::to_string(&val).map_err(mlua::Error::external)?;
-                let config: Config =
-                    serde_json::from_str(&json_data).map_err(mlua::Error::external)?;
-                match generator(
-                    Generate {
-                        noconfirm: Some("".to_string()),
-                    },
-                    config,
-                ) {
-                    Ok(ret) => {
-                        std::env::set_var("HPX_GENERATED", "1");
-                        Ok(ret)
+                let program: Option<String> = opts.get(2)?;
+                let mut generated = true;
+                let mut objects = Vec::new();
+                for config in select(read_config(lua, cfg)?, program)? {
+                    let name = config.name().to_string();
+                    match generator(
+                        Generate {
+                            noconfirm: Some("".to_string()),
+                        },
+                        config,
+                    ) {
+                        Ok((ret, object)) => {
+                            generated &= ret;
+                            objects.push((name, object));
+                        }
+                        Err(e) => return Err(mlua::Error::runtime(e)),
                     }
-                    Err(e) => Err(mlua::Error::runtime(e)),
                 }
-            })?;
+                std::env::set_var("HPX_GENERATED", "1");
+                Ok((generated, per_program(lua, objects)?))
+            },
+        )?;
 
         let load_func = lua.create_async_function(|lua, opts: mlua::Table| async move {
             let cfg: mlua::Table = opts.get(1)?;
             let iface: mlua::String = opts.get(2)?;
             let xdp_flags: mlua::String = opts.get(3)?;
 
-            let val = cfg.serialize(mlua::serde::Serializer::new(&lua))?;
-            let json_data = serde_json::to_string(&val).map_err(mlua::Error::external)?;
-            let config: Config = serde_json::from_str(&json_data).map_err(mlua::Error::external)?;
+            let program: Option<String> = opts.get(4)?;
+            let configs = select(read_config(&lua, cfg)?, program)?;
+            check_sudo_for(&configs);
 
-            let hostname = config.init.as_ref().unwrap().hostname.as_ref();
-            if hostname.is_none()
-                || *hostname.as_ref().unwrap() == "localhost"
-                || *hostname.as_ref().unwrap() == "127.0.0.1"
-            {
-                check_sudo();
-            }
-
-            match load(
-                &mut Load {
-                    iface: iface.to_string_lossy(),
-                    xdp_flags: xdp_flags.to_string_lossy(),
-                },
-                config,
-            )
-            .await
-            .into_lua_err()
-            {
-                Ok(ret) => Ok(ret),
-                Err(e) => Err(mlua::Error::runtime(e)),
+            let mut ids = Vec::new();
+            for config in configs {
+                let name = config.name().to_string();
+                match load(
+                    &mut Load {
+                        iface: iface.to_string_lossy(),
+                        xdp_flags: Some(
+                            xdp_flags
+                                .to_string_lossy()
+                                .parse()
+                                .map_err(mlua::Error::runtime)?,
+                        ),
+                        fleet: Fleet::default(),
+                    },
+                    config,
+                )
+                .await
+                .into_lua_err()
+                {
+                    Ok(ret) => ids.push((name, ret)),
+                    Err(e) => return Err(mlua::Error::runtime(e)),
+                }
             }
+            per_program(&lua, ids)
         })?;
 
         let unload_func = lua.create_function(|lua, opts: mlua::Table| {
@@ -118,57 +146,53 @@ pub async fn run_script(work_dir: &str, script_path: Option<&str>) -> mlua::Resu
             let xdp_flags: mlua::String = opts.get(3)?;
             let prog_id: mlua::Integer = opts.get(4)?;
 
-            let val = cfg.serialize(mlua::serde::Serializer::new(lua))?;
-            let json_data = serde_json::to_string(&val).map_err(mlua::Error::external)?;
-            let config: Config = serde_json::from_str(&json_data).map_err(mlua::Error::external)?;
+            let program: Option<String> = opts.get(5)?;
+            let configs = select(read_config(lua, cfg)?, program)?;
+            check_sudo_for(&configs);
 
-            let hostname = config.init.as_ref().unwrap().hostname.as_ref();
-            if hostname.is_none()
-                || *hostname.as_ref().unwrap() == "localhost"
-                || *hostname.as_ref().unwrap() == "127.0.0.1"
-            {
-                check_sudo();
-            }
-
-            match unload(
-                &mut Unload {
-                    iface: iface.to_string_lossy(),
-                    xdp_flags: xdp_flags.to_string_lossy(),
-                    pid: prog_id.to_string(),
-                },
-                config,
-            )
-            .into_lua_err()
-            {
-                Ok(ret) => Ok(ret),
-                Err(e) => Err(mlua::Error::runtime(e)),
+            for config in configs {
+                if let Err(e) = unload(
+                    &mut Unload {
+                        iface: iface.to_string_lossy(),
+                        xdp_flags: Some(
+                            xdp_flags
+                                .to_string_lossy()
+                                .parse()
+                                .map_err(mlua::Error::runtime)?,
+                        ),
+                        pid: prog_id.to_string(),
+                        fleet: Fleet::default(),
+                    },
+                    config,
+                )
+                .into_lua_err()
+                {
+                    return Err(mlua::Error::runtime(e));
+                }
             }
+            Ok(())
         })?;
 
         let get_map_data_func = lua.create_function(|lua, opts: mlua::Table| {
             let cfg: mlua::Table = opts.get(1)?;
             let map_name: mlua::String = opts.get(2)?;
 
-            let val = cfg.serialize(mlua::serde::Serializer::new(lua))?;
-            let json_data = serde_json::to_string(&val).map_err(mlua::Error::external)?;
-            let config: Config = serde_json::from_str(&json_data).map_err(mlua::Error::external)?;
+            let program: Option<String> = opts.get(3)?;
+            let configs = select(read_config(lua, cfg)?, program)?;
+            check_sudo_for(&configs);
 
-            let hostname = config.init.as_ref().unwrap().hostname.as_ref();
-            if hostname.is_none()
-                || *hostname.as_ref().unwrap() == "localhost"
-                || *hostname.as_ref().unwrap() == "127.0.0.1"
-            {
-                check_sudo();
-            }
-            match get_map_data(&config, &map_name.to_string_lossy()) {
-                Ok(ret) => {
-                    let json: JsonValue =
-                    serde_json::from_str(&ret).map_err(mlua::Error::external)?;
-                    Ok(lua.to_value(&json)?)
+            let mut data = Vec::new();
+            for config in configs {
+                match get_map_data(&config, &map_name.to_string_lossy()) {
+                    Ok(ret) => {
+                        let json: JsonValue =
+                            serde_json::from_str(&ret).map_err(mlua::Error::external)?;
+                        data.push((config.name().to_string(), json));
+                    }
+                    Err(e) => return Err(mlua::Error::runtime(e)),
                 }
-                Err(e) => Err(mlua::Error::runtime(e)),
             }
-
+            per_program(lua, data)
         })?;
 
         lua.globals().set("analyze", analyze_func)?;
@@ -186,6 +210,61 @@ pub async fn run_script(work_dir: &str, script_path: Option<&str>) -> mlua::Resu
     Ok(())
 }
 
+/// Directory of running script.
+struct ScriptDir(PathBuf);
+
+/// Profile chosen with --profile.
+struct Profile(String);
+
+/// Config passed to Lua function. Placeholders, profile and list files are handled and config
+/// is checked the same way as for config files.
+fn read_config(lua: &Lua, cfg: mlua::Table) -> mlua::Result<Config> {
+    let val = cfg.serialize(mlua::serde::Serializer::new(lua))?;
+    let mut value = serde_json::to_value(&val).map_err(mlua::Error::external)?;
+    migrate::migrate(&mut value).map_err(mlua::Error::runtime)?;
+    interpolate::interpolate(&mut value).map_err(|e| mlua::Error::runtime(format!("{:#}", e)))?;
+    if let Some(profile) = lua.app_data_ref::<Profile>() {
+        layers::apply_profile(&mut value, &profile.0)
+            .map_err(|e| mlua::Error::runtime(format!("{:#}", e)))?;
+    }
+    let mut config: Config = serde_json::from_value(value).map_err(mlua::Error::external)?;
+    let dir = lua
+        .app_data_ref::<ScriptDir>()
+        .map(|d| d.0.clone())
+        .unwrap_or_default();
+    sources::resolve(&mut config, &dir).map_err(|e| mlua::Error::runtime(format!("{:#}", e)))?;
+    validate::check(&config).map_err(mlua::Error::runtime)?;
+    Ok(config)
+}
+
+/// Programs Lua function acts on. All of them or the one named by program option.
+fn select(config: Config, program: Option<String>) -> mlua::Result<Vec<Config>> {
+    config
+        .select(program.as_deref())
+        .map_err(mlua::Error::runtime)
+}
+
+/// Result of single program as is, results of several as table by program name.
+fn per_program<T: Serialize>(
+    lua: &Lua,
+    mut results: Vec<(String, T)>,
+) -> mlua::Result<mlua::Value> {
+    if results.len() == 1 {
+        return lua.to_value(&results.remove(0).1);
+    }
+    lua.to_value(&results.into_iter().collect::<BTreeMap<String, T>>())
+}
+
+/// Asks for sudo if any program runs on this machine.
+fn check_sudo_for(configs: &[Config]) {
+    if configs
+        .iter()
+        .any(|c| TargetKind::of(c.init.as_ref().unwrap()).is_local())
+    {
+        check_sudo();
+    }
+}
+
 fn check_sudo() {
     match sudo::check() {
         sudo::RunningAs::Root => (),
@@ -1,36 +1,49 @@
 #![feature(sync_unsafe_cell)]
 
 mod analyze;
+mod capacity;
 mod cli;
 mod config;
+mod convert;
+mod credentials;
 mod engine;
+mod fleet;
+mod geo;
 mod get;
 mod helpers;
+mod init;
+mod interpolate;
+mod layers;
 mod load;
 mod lua;
 mod maps;
+mod migrate;
 mod objects;
 mod programs;
+mod schedule;
 mod secret;
 mod snippets;
+mod sources;
+mod ssh;
+mod target;
 mod unload;
+mod validate;
 
 use analyze::analyze;
 use anyhow::{anyhow, Context};
 use clap::Parser;
-use cli::{Commands, Get, Options};
+use cli::{Commands, ConfigCommand, Get, Options};
 use config::Config;
 use crossterm::style::Stylize;
 use engine::generator;
 use get::{
     get_base_config, get_default_config, get_example_config, get_lua_api, get_lua_func_opts,
+    get_schema,
 };
 use home::home_dir;
 use load::load;
 use lua::run_script;
-use std::cell::SyncUnsafeCell;
 use std::fs;
-use std::sync::Mutex;
 use std::{
     path::{Path, PathBuf},
     process::Command,
@@ -49,9 +62,6 @@ pub static WORKING_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
     .into()
 });
 
-// I DONT LIKE THIS BUT IDK OTHER WAY TO DO THIS
-static SSH_PASS: SyncUnsafeCell<Mutex<String>> = SyncUnsafeCell::new(Mutex::new(String::new()));
-
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     let working_dir = Path::new(
@@ -79,11 +89,12 @@ async fn main() -> Result<(), anyhow::Error> {
                 .as_str(),
             ])
             .output()?;
-    } else {
+    } else if !fleet::is_host() {
+        // Inventory hosts are run by hpx, which checked it already
         Command::new("sh")
             .args([
                 "-c",
-                format!("bpftool btf dump file /sys/kernel/btf/vmlinux format c > /tmp/vmlinux.h",)
+                "bpftool btf dump file /sys/kernel/btf/vmlinux format c > /tmp/vmlinux.h".to_string()
                     .as_str(),
             ])
             .output()?;
@@ -106,67 +117,84 @@ async fn main() -> Result<(), anyhow::Error> {
     }
 
     let options = Options::parse();
-    let config: Config;
-
-    let config_path = options.config;
-    if let Some(c) = config_path {
-        let config_file = Path::new(&c);
-
-        let user_config_file;
-
-        if !config_file.exists() && !c.is_empty() {
-            return Err(anyhow!(
-                "File does not exist!\nSearched locations: {}",
-                config_file.display()
-            ));
-        }
-
-        match config_file.extension() {
-            Some(e) => match e.to_str().unwrap() {
-                "json" => {
-                    user_config_file = fs::read_to_string(config_file)?;
-                    config = serde_json::from_str(&user_config_file)?;
-                }
-                "toml" => {
-                    user_config_file = fs::read_to_string(config_file)?;
-                    config = toml::from_str(&user_config_file)?;
-                }
-                _ => {
-                    return Err(anyhow!(
-                        "File type is not supported!\nSuppored file types: JSON, TOML."
-                    ));
-                }
-            },
-            None => {
-                config = Config::default();
-            }
+    let program = options.program.as_deref();
+    let profile = options.profile.as_deref();
+
+    // Config is only resolved by commands using it, so init or get work with a broken one
+    let resolve = || layers::resolve(&options.config, &options.set, profile);
+    // Commands acting on programs need a valid config. Config subcommands report problems
+    // themselves and Lua scripts check the configs they build
+    let config = || -> Result<Config, anyhow::Error> {
+        let config = resolve()?.config;
+        validate::check(&config)?;
+        Ok(config)
+    };
+
+    // Every inventory host is run by a copy of hpx, which does the command for that host only
+    let fleet = match &options.command {
+        Commands::Analyze(o) => Some((&o.fleet, "analyze", "Analyze".blue().bold())),
+        Commands::Load(o) => Some((&o.fleet, "load", "Load".red().bold())),
+        Commands::Unload(o) => Some((&o.fleet, "unload", "Unload".red().bold())),
+        _ => None,
+    };
+    if let Some((fleet, command, prefix)) = fleet {
+        if fleet.inventory.is_some() && !fleet::is_host() {
+            return fleet::rollout(fleet, command, prefix);
         }
-    } else {
-        config = Config::default();
     }
 
     match options.command {
         Commands::Generate(options) => {
-            generator(options, config)?;
+            for config in config()?.select(program)? {
+                generator(options.clone(), config)?;
+            }
         }
         Commands::Analyze(options) => {
-            analyze(options, config).await?;
+            for config in config()?.select(program)? {
+                analyze(options.clone(), config).await?;
+            }
         }
-        Commands::Load(mut options) => {
-            let prog_id = load(&mut options, config).await?;
+        Commands::Load(options) => {
+            for config in config()?.select(program)? {
+                let name = config.name().to_string();
+                let prog_id = load(&mut options.clone(), config).await?;
 
-            if prog_id != 0 {
-                println!("{}: Program ID - {prog_id}", "Load".red().bold());
+                if prog_id != 0 {
+                    println!("{}: {name} program ID - {prog_id}", "Load".red().bold());
+                }
+            }
+        }
+        Commands::Unload(options) => {
+            for config in config()?.select(program)? {
+                unload(&mut options.clone(), config)?;
             }
         }
-        Commands::Unload(mut options) => unload(&mut options, config)?,
         Commands::Secret => secret::secret().await?,
+        Commands::Geo(options) => geo::geo(options, resolve()?.config.select_one(program)?)?,
+        Commands::Schedule(options) => {
+            schedule::schedule(options, config()?.select_one(program)?).await?
+        }
+        Commands::Config(opt) => match opt {
+            ConfigCommand::Validate => validate::config_validate(&resolve()?.config)?,
+            ConfigCommand::Show { resolved } => layers::show(&resolve()?, resolved)?,
+            ConfigCommand::Migrate { files } if files.is_empty() => {
+                migrate::config_migrate(&options.config)?
+            }
+            ConfigCommand::Migrate { files } => migrate::config_migrate(&files)?,
+            ConfigCommand::Convert {
+                input,
+                output,
+                force,
+            } => convert::config_convert(&input, &output, force)?,
+        },
+        Commands::Init(options) => init::init(options)?,
         Commands::Run(options) => {
             let result = run_script(
                 WORKING_DIR
                     .to_str()
                     .with_context(|| "Failed to parse HOME directory".to_string())?,
                 options.path.as_deref(),
+                profile,
             )
             .await;
             match result {
@@ -178,6 +206,7 @@ async fn main() -> Result<(), anyhow::Error> {
             Get::DefaultConfig(o) => get_default_config(o)?,
             Get::ExampleConfig(o) => get_example_config(o)?,
             Get::BaseConfig(o) => get_base_config(o)?,
+            Get::Schema => get_schema()?,
             Get::LuaApi => get_lua_api(),
             Get::LuaFuncOpts(o) => get_lua_func_opts(o),
         },
@@ -1,17 +1,19 @@
-use std::{
-    io::{self, Read, Write},
-    net::TcpStream,
-    process::Command,
-    sync::Mutex,
-};
+use std::net::Ipv4Addr;
 
 use anyhow::anyhow;
 use crossterm::style::Stylize;
-use libbpf_rs::{MapCore, MapFlags, MapImpl, Object};
+use ipnetwork::Ipv4Network;
+use libbpf_rs::{ErrorKind, MapCore, MapFlags, MapImpl, Object};
 use serde_json::Value;
-use ssh2::Session;
 
-use crate::{config::Config, SSH_PASS};
+use crate::{
+    config::Config,
+    geo,
+    target::{Target, COMMAND_TIMEOUT},
+};
+
+/// Errors of map updates when map is full (E2BIG, ENOSPC)
+static MAP_FULL: [&str; 2] = ["Argument list too long", "No space left on device"];
 
 pub fn get_map<'a>(object: &'a Object, name: &'a str) -> Option<MapImpl<'a>> {
     let mut maps = object.maps();
@@ -20,206 +22,198 @@ pub fn get_map<'a>(object: &'a Object, name: &'a str) -> Option<MapImpl<'a>> {
 
 }
 
-pub fn load_map_data_local_temp(map: &MapImpl, data: &Vec<String>) -> Result<(), anyhow::Error> {
+/// Ids of maps used by program from `bpftool prog show -j` output. Programs of one config
+/// share map names, so maps are looked up among these only.
+pub fn map_ids(prog: &Value) -> Vec<u64> {
+    prog["map_ids"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|id| id.as_u64())
+        .collect()
+}
+
+/// Finds id of map of loaded program, given `bpftool prog show -j` and `bpftool map show -j`
+/// outputs.
+pub fn find_map(
+    progs: &str,
+    maps: &str,
+    prog_name: &str,
+    map_name: &str,
+) -> Result<u64, anyhow::Error> {
+    let progs: Value = serde_json::from_str(progs)?;
+    let prog = progs
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|p| prog_name == p["name"])
+        .ok_or(anyhow!("Program {} is not loaded", prog_name))?;
+    let ids = map_ids(prog);
+
+    let maps: Value = serde_json::from_str(maps)?;
+    maps.as_array()
+        .into_iter()
+        .flatten()
+        .filter(|m| ids.contains(&m["id"].as_u64().unwrap_or_default()))
+        .find(|m| map_name == m["name"])
+        .and_then(|m| m["id"].as_u64())
+        .ok_or(anyhow!("Map {} was not found", map_name))
+}
+
+/// Loads addresses into list map. Returns how many did not fit.
+pub fn load_map_data_local_temp(map: &MapImpl, data: &[String]) -> Result<usize, anyhow::Error> {
+    let mut failed = 0;
     if data.is_empty() {
-        return Ok(());
+        return Ok(failed);
     }
 
-    for address in data {
-        let key: Vec<u8> = address.split(".").map(|n| n.parse().unwrap_or(0)).collect();
+    for address in data.iter().filter(|a| !geo::is_geo_entry(a)) {
+        let key: Vec<u8> = address
+            .trim()
+            .parse::<Ipv4Addr>()
+            .map_err(|_| anyhow!("Invalid IPv4 address \"{}\"", address))?
+            .octets()
+            .to_vec();
         let mut value = key.clone();
         let mut empty_data: Vec<u8> = vec![
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
         ];
         value.append(&mut empty_data);
-        map.update(&key, &value, MapFlags::NO_EXIST)?;
+        match map.update(&key, &value, MapFlags::NO_EXIST) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
+            Err(e) if is_map_full(&e.to_string()) => failed += 1,
+            Err(e) => return Err(anyhow!("Failed to add {} to map: {}", address.trim(), e)),
+        }
     }
 
-    Ok(())
+    Ok(failed)
 }
 
-pub fn load_map_data_local(map_id: u64, data: &Vec<String>) -> Result<(), anyhow::Error> {
-    for address in data {
-        let key: Vec<&str> = address.trim().split(".").collect();
+fn is_map_full(error: &str) -> bool {
+    MAP_FULL.iter().any(|m| error.contains(m))
+}
+
+/// Loads addresses into list map with bpftool. Returns how many did not fit.
+pub fn load_map_data(
+    map_id: u64,
+    data: &[String],
+    target: &Target,
+) -> Result<usize, anyhow::Error> {
+    let mut failed = 0;
+    for address in data.iter().filter(|a| !geo::is_geo_entry(a)) {
+        // Parsed so that only octets get into the command
+        let key: Vec<String> = address
+            .trim()
+            .parse::<Ipv4Addr>()
+            .map_err(|_| anyhow!("Invalid IPv4 address \"{}\"", address))?
+            .octets()
+            .iter()
+            .map(|b| b.to_string())
+            .collect();
         let mut value = key.clone();
-        let mut empty_data: Vec<&str> = vec![
-            "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0",
-            "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0",
-        ];
+        let mut empty_data: Vec<String> = vec!["0".to_string(); 28];
         value.append(&mut empty_data);
 
-        Command::new("sh")
-            .args([
-                "-c",
-                format!(
-                    "bpftool map update id {} key {} value {}",
-                    map_id,
-                    key.join(" "),
-                    value.join(" ")
-                )
-                .as_str(),
-            ])
-            .output()?;
+        let command = format!(
+            "bpftool map update id {} key {} value {}",
+            map_id,
+            key.join(" "),
+            value.join(" ")
+        );
+        match target.exec(&command, COMMAND_TIMEOUT) {
+            Ok(_) => (),
+            Err(e) if is_map_full(&e.to_string()) => failed += 1,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(failed)
+}
+
+/// LPM trie key: prefix length followed by address in network byte order.
+fn net_key(net: &Ipv4Network) -> Vec<u8> {
+    let mut key = (net.prefix() as u32).to_ne_bytes().to_vec();
+    key.extend_from_slice(&net.network().octets());
+    key
+}
+
+/// bpftool batch file. One update per prefix is too slow for thousands of prefixes.
+fn net_batch(map_id: u64, nets: &[Ipv4Network]) -> String {
+    let mut batch = String::new();
+    for n in nets {
+        let key: Vec<String> = net_key(n).iter().map(|b| b.to_string()).collect();
+        batch.push_str(&format!(
+            "map update id {} key {} value 1 0 0 0\n",
+            map_id,
+            key.join(" ")
+        ));
+    }
+    batch
+}
+
+pub fn load_net_data_local_temp(map: &MapImpl, nets: &[Ipv4Network]) -> Result<(), anyhow::Error> {
+    for n in nets {
+        map.update(&net_key(n), &1u32.to_ne_bytes(), MapFlags::ANY)?;
     }
 
     Ok(())
 }
 
-pub fn load_map_data_remote(
+pub fn load_net_data(
     map_id: u64,
-    data: &Vec<String>,
-    session: &Session,
-    password: &str,
+    nets: &[Ipv4Network],
+    target: &Target,
 ) -> Result<(), anyhow::Error> {
-    for address in data {
-        let key: Vec<&str> = address.trim().split(".").collect();
-        let mut value = key.clone();
-        let mut empty_data: Vec<&str> = vec![
-            "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0",
-            "0", "0", "0", "0", "0", "0", "0", "0", "0", "0", "0"
-        ];
-        value.append(&mut empty_data);
+    if nets.is_empty() {
+        return Ok(());
+    }
 
-        let mut channel = session.channel_session().unwrap();
-        channel.exec(
-            format!(
-                "echo {} | sudo -S bpftool map update id {} key {} value {}",
-                password,
-                map_id,
-                key.join(" "),
-                value.join(" ")
-            )
-            .as_str(),
-        )?;
+    let path = target.temp_file("hpx_net")?;
+    target.write(&path, net_batch(map_id, nets).as_bytes())?;
+    target.exec(
+        &format!("bpftool batch file {p}; s=$?; rm -f {p}; exit $s", p = path),
+        COMMAND_TIMEOUT,
+    )?;
 
-        channel.send_eof()?;
-        channel.wait_eof()?;
-        channel.close()?;
-        channel.wait_close()?;
+    Ok(())
+}
+
+/// Writes schedule state into control map. Key is the index, value 1 enables list/action.
+pub fn load_control_local_temp(map: &MapImpl, state: &[u32]) -> Result<(), anyhow::Error> {
+    for (i, v) in state.iter().enumerate() {
+        map.update(&(i as u32).to_ne_bytes(), &v.to_ne_bytes(), MapFlags::ANY)?;
     }
+
     Ok(())
 }
 
-pub fn get_map_data(config: &Config, map_name: &str) -> Result<String, anyhow::Error> {
-    let hostname = config.init.as_ref().unwrap().hostname.as_ref();
-    let port = config.init.as_ref().unwrap().port.as_ref();
-
-    if hostname.is_none()
-        || *hostname.as_ref().unwrap() == "localhost"
-        || *hostname.as_ref().unwrap() == "127.0.0.1"
-    {
-        let output = String::from_utf8(
-            Command::new("bpftool")
-                .arg("map")
-                .arg("show")
-                .arg("-j")
-                .output()
-                .unwrap()
-                .stdout,
+pub fn load_control(map_id: u64, state: &[u32], target: &Target) -> Result<(), anyhow::Error> {
+    for (i, v) in state.iter().enumerate() {
+        target.exec(
+            &format!(
+                "bpftool map update id {} key {} value {}",
+                map_id,
+                u32_bytes(i as u32).join(" "),
+                u32_bytes(*v).join(" ")
+            ),
+            COMMAND_TIMEOUT,
         )?;
-        let maps: Value = serde_json::from_str(&output)?;
-
-        if let Some(maps) = maps.as_array() {
-            for m in maps {
-                if map_name == m["name"] {
-                    let output = String::from_utf8(
-                        Command::new("bpftool")
-                            .arg("map")
-                            .arg("dump")
-                            .arg("id")
-                            .arg(m["id"].to_string())
-                            .arg("-j")
-                            .output()
-                            .unwrap()
-                            .stdout,
-                    )?;
-                    return Ok(output);
-                }
-            }
-        } else {
-            return Err(anyhow!("Map {} was not found", &map_name));
-        }
-    } else if hostname.is_some() {
-        let tcp = TcpStream::connect(format!(
-            "{}:{}",
-            hostname.unwrap(),
-            port.unwrap_or(&22)
-        ))
-        .unwrap();
-        let mut session = Session::new().unwrap();
-        session.set_tcp_stream(tcp);
-        session.handshake().unwrap();
-
-        let mut username: String = String::new();
-        if config.init.as_ref().unwrap().username.is_none() {
-            print!("Username: ");
-            io::stdout().flush()?;
-            io::stdin().read_line(&mut username)?;
-        } else {
-            username = config
-                .init
-                .as_ref()
-                .unwrap()
-                .username
-                .as_ref()
-                .unwrap()
-                .to_string();
-            println!("{}: Using username \"{}\"", "Map Data".green().bold(), &username);
-        }
-
-        let password: String;
-        unsafe {
-            let pass = (*SSH_PASS.get()).lock().unwrap();
-            if !pass.is_empty() {
-                password = (*pass).clone();
-            } else {
-                password = rpassword::prompt_password("Password: ")?;
-            }
-        }
+    }
 
-        session.userauth_password(username.trim(), password.trim())?;
+    Ok(())
+}
 
-        unsafe {
-            let pass = (*SSH_PASS.get()).lock().unwrap();
-            if pass.is_empty() {
-                let new = SSH_PASS.get().as_mut().unwrap();
-                *new = Mutex::new(password.clone());
-            }
-        }
+fn u32_bytes(n: u32) -> Vec<String> {
+    n.to_le_bytes().iter().map(|b| b.to_string()).collect()
+}
 
-        println!(
-            "{}: Connected to {}\n",
-            "Map Load".green().bold(),
-            hostname.unwrap()
-        );
+pub fn get_map_data(config: &Config, map_name: &str) -> Result<String, anyhow::Error> {
+    let target = Target::connect(config.init.as_ref().unwrap(), "Map Data".green().bold())?;
 
-        let mut output: String = String::new();
-        let mut channel = session.channel_session()?;
-        channel.exec(format!("echo {} | sudo -S bpftool map show -j", password).as_str())?;
-        channel.read_to_string(&mut output)?;
-
-        let maps: Value = serde_json::from_str(&output)?;
-
-        if let Some(maps) = maps.as_array() {
-            for m in maps {
-                if map_name == m["name"] {
-                    output.clear();
-                    channel = session.channel_session()?;
-                    channel.exec(
-                        format!(
-                            "echo {} | sudo -S bpftool map dump id {} -j",
-                            password, m["id"]
-                        )
-                        .as_str(),
-                    )?;
-                    channel.read_to_string(&mut output)?;
-                    return Ok(output);
-                }
-            }
-        } else {
-            return Err(anyhow!("Map {} was not found", &map_name));
-        }
-    }
+    let progs = target.exec("bpftool prog show -j", COMMAND_TIMEOUT)?.stdout;
+    let maps = target.exec("bpftool map show -j", COMMAND_TIMEOUT)?.stdout;
+    let id = find_map(&progs, &maps, config.name(), map_name)?;
 
-    Ok(String::new())
+    let output = target.exec(&format!("bpftool map dump id {} -j", id), COMMAND_TIMEOUT)?;
+    Ok(output.stdout)
 }
@@ -0,0 +1,347 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context};
+use crossterm::style::Stylize;
+use serde_json::{Map, Value};
+use toml_edit::{DocumentMut, InlineTable, Item, Key};
+
+use crate::config::{check_config, ConfigFormat};
+
+/// Version of config format understood and written by this hpx.
+pub static CURRENT_VERSION: u32 = 1;
+
+/// Change made by migration. Applied to top-level sections and to sections of every program.
+enum Step {
+    /// Renames key of section, f.e. init.type to init.prog_type
+    Rename {
+        section: &'static str,
+        from: &'static str,
+        to: &'static str,
+    },
+}
+
+/// MIGRATIONS[n] upgrades version n to n + 1. Configs without version are version 0.
+static MIGRATIONS: [&[Step]; 1] = [
+    // 0 -> 1: type was renamed to prog_type
+    &[Step::Rename {
+        section: "init",
+        from: "type",
+        to: "prog_type",
+    }],
+];
+
+/// Version of config. Fails if it is newer than this hpx understands.
+pub fn version(value: &Value) -> Result<u32, anyhow::Error> {
+    let version = match value.get("version") {
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .with_context(|| format!("version must be a number, got {}", v))?,
+        None => 0,
+    };
+
+    if version > CURRENT_VERSION {
+        return Err(anyhow!(
+            "Config version {} is newer than supported version {}. Upgrade hpx",
+            version,
+            CURRENT_VERSION
+        ));
+    }
+    Ok(version)
+}
+
+/// Upgrades config to current version in memory. Returns version config had.
+pub fn migrate(value: &mut Value) -> Result<u32, anyhow::Error> {
+    let from = version(value)?;
+    for steps in &MIGRATIONS[from as usize..] {
+        for step in steps.iter() {
+            migrate_json(value, step);
+        }
+    }
+
+    if let Some(config) = value.as_object_mut() {
+        config.insert("version".to_string(), Value::from(CURRENT_VERSION));
+    }
+    Ok(from)
+}
+
+fn migrate_json(value: &mut Value, step: &Step) {
+    let Step::Rename { section, from, to } = step;
+
+    let mut sections = Vec::new();
+    for (k, v) in value.as_object_mut().into_iter().flatten() {
+        if k == "programs" {
+            sections.extend(
+                v.as_array_mut()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|p| p.get_mut(*section)),
+            );
+        } else if k == section {
+            sections.push(v);
+        }
+    }
+
+    for section in sections.into_iter().filter_map(|s| s.as_object_mut()) {
+        if section.contains_key(*to) || !section.contains_key(*from) {
+            continue;
+        }
+        // Rebuilt to keep key order
+        *section = std::mem::take(section)
+            .into_iter()
+            .map(|(k, v)| {
+                if k == *from {
+                    (to.to_string(), v)
+                } else {
+                    (k, v)
+                }
+            })
+            .collect::<Map<String, Value>>();
+    }
+}
+
+/// Upgrades TOML document of given version in place, keeping comments and layout.
+pub fn migrate_toml_doc(doc: &mut DocumentMut, from: u32) {
+    for steps in &MIGRATIONS[from as usize..] {
+        for step in steps.iter() {
+            migrate_toml(doc, step);
+        }
+    }
+    doc.insert("version", toml_edit::value(CURRENT_VERSION as i64));
+}
+
+fn migrate_toml(doc: &mut DocumentMut, step: &Step) {
+    let Step::Rename { section, from, to } = step;
+
+    match doc.get_mut("programs") {
+        Some(Item::ArrayOfTables(programs)) => {
+            for p in programs.iter_mut() {
+                if let Some(s) = p.get_mut(section) {
+                    rename_toml(s, from, to);
+                }
+            }
+        }
+        Some(Item::Value(toml_edit::Value::Array(programs))) => {
+            for p in programs.iter_mut().filter_map(|p| p.as_inline_table_mut()) {
+                if let Some(toml_edit::Value::InlineTable(s)) = p.get_mut(section) {
+                    rename_inline(s, from, to);
+                }
+            }
+        }
+        _ => (),
+    }
+    if let Some(s) = doc.get_mut(section) {
+        rename_toml(s, from, to);
+    }
+}
+
+fn rename_toml(section: &mut Item, from: &str, to: &str) {
+    match section {
+        Item::Table(t) => {
+            if t.contains_key(to) || !t.contains_key(from) {
+                return;
+            }
+            // Removed and inserted back in order so comments and layout stay in place
+            let keys = t
+                .iter()
+                .map(|(k, _)| k.to_string())
+                .collect::<Vec<String>>();
+            for k in keys {
+                if let Some((key, item)) = t.remove_entry(&k) {
+                    t.insert_formatted(&rename_key(key, from, to), item);
+                }
+            }
+        }
+        Item::Value(toml_edit::Value::InlineTable(t)) => rename_inline(t, from, to),
+        _ => (),
+    }
+}
+
+fn rename_inline(section: &mut InlineTable, from: &str, to: &str) {
+    if section.contains_key(to) || !section.contains_key(from) {
+        return;
+    }
+    let keys = section
+        .iter()
+        .map(|(k, _)| k.to_string())
+        .collect::<Vec<String>>();
+    for k in keys {
+        if let Some((key, value)) = section.remove_entry(&k) {
+            section.insert_formatted(&rename_key(key, from, to), value);
+        }
+    }
+}
+
+fn rename_key(key: Key, from: &str, to: &str) -> Key {
+    if key.get() == from {
+        Key::new(to).with_leaf_decor(key.leaf_decor().clone())
+    } else {
+        key
+    }
+}
+
+/// hpx config migrate
+pub fn config_migrate(files: &[String]) -> Result<(), anyhow::Error> {
+    let files = files.iter().filter(|f| !f.is_empty()).collect::<Vec<_>>();
+    if files.is_empty() {
+        return Err(anyhow!("No config files given. Use -c or list them"));
+    }
+
+    for f in files {
+        let path = Path::new(f);
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        let format = ConfigFormat::from_extension(path).unwrap_or(ConfigFormat::detect(&content));
+
+        let mut value: Value = match format {
+            ConfigFormat::Json => serde_json::from_str(&content)?,
+            ConfigFormat::Toml => toml::from_str(&content)?,
+            ConfigFormat::Yaml => serde_yaml::from_str(&content)?,
+        };
+        let from = migrate(&mut value).with_context(|| format!("In {}", path.display()))?;
+        if from == CURRENT_VERSION {
+            println!(
+                "{}: {} is already at version {}",
+                "Migrate".yellow().bold(),
+                path.display(),
+                CURRENT_VERSION
+            );
+            continue;
+        }
+
+        let migrated = match format {
+            ConfigFormat::Toml => {
+                let mut doc = content.parse::<DocumentMut>()?;
+                migrate_toml_doc(&mut doc, from);
+                doc.to_string()
+            }
+            ConfigFormat::Json => {
+                format!("{}\n", serde_json::to_string_pretty(&version_first(value))?)
+            }
+            ConfigFormat::Yaml => serde_yaml::to_string(&version_first(value))?,
+        };
+
+        // Never write a file hpx can't read back
+        check_config(&migrated, format).with_context(|| {
+            format!(
+                "Migrated {} is not valid. File was not changed",
+                path.display()
+            )
+        })?;
+        fs::write(path, migrated)
+            .with_context(|| format!("Failed to write config {}", path.display()))?;
+
+        println!(
+            "{}: {} upgraded from version {} to {}{}",
+            "Migrate".yellow().bold(),
+            path.display(),
+            from,
+            CURRENT_VERSION,
+            if format == ConfigFormat::Yaml {
+                ". YAML comments were not preserved"
+            } else {
+                ""
+            }
+        );
+    }
+
+    Ok(())
+}
+
+/// Moves version to the top, where people look for it.
+pub fn version_first(value: Value) -> Value {
+    let mut ordered = Map::new();
+    if let Value::Object(config) = value {
+        ordered.insert("version".to_string(), Value::from(CURRENT_VERSION));
+        ordered.extend(config.into_iter().filter(|(k, _)| k != "version"));
+    }
+    Value::Object(ordered)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn reads_version() {
+        assert_eq!(version(&json!({})).unwrap(), 0);
+        assert_eq!(version(&json!({"version": 1})).unwrap(), 1);
+        assert!(version(&json!({"version": "1"})).is_err());
+        assert_eq!(
+            version(&json!({"version": CURRENT_VERSION + 1}))
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Config version {} is newer than supported version {}. Upgrade hpx",
+                CURRENT_VERSION + 1,
+                CURRENT_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn renames_keys_of_sections_and_programs() {
+        let mut value = json!({
+            "init": {"name": "Old", "type": "dns", "iface": "lo"},
+            "programs": [{"init": {"type": "ip"}}, {"data": {}}]
+        });
+
+        assert_eq!(migrate(&mut value).unwrap(), 0);
+        assert_eq!(
+            value,
+            json!({
+                "init": {"name": "Old", "prog_type": "dns", "iface": "lo"},
+                "programs": [{"init": {"prog_type": "ip"}}, {"data": {}}],
+                "version": CURRENT_VERSION
+            })
+        );
+        // Key order is kept
+        assert_eq!(
+            value["init"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["name", "prog_type", "iface"]
+        );
+    }
+
+    #[test]
+    fn keeps_new_key_and_current_configs() {
+        let mut value = json!({"init": {"type": "dns", "prog_type": "ip"}});
+        migrate(&mut value).unwrap();
+        assert_eq!(value["init"], json!({"type": "dns", "prog_type": "ip"}));
+
+        let mut value = json!({"version": CURRENT_VERSION, "init": {"type": "dns"}});
+        assert_eq!(migrate(&mut value).unwrap(), CURRENT_VERSION);
+        assert_eq!(value["init"], json!({"type": "dns"}));
+    }
+
+    #[test]
+    fn migrates_toml_with_comments() {
+        let mut doc = "# Honeypot\n[init]\n# Program type\ntype = \"dns\" # dns filter\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+        migrate_toml_doc(&mut doc, 0);
+
+        assert_eq!(
+            doc.to_string(),
+            format!(
+                "version = {}\n# Honeypot\n[init]\n# Program type\nprog_type = \"dns\" # dns filter\n",
+                CURRENT_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn puts_version_first() {
+        let value = version_first(json!({"init": {}, "version": 0}));
+        assert_eq!(
+            value.as_object().unwrap().keys().collect::<Vec<_>>(),
+            vec!["version", "init"]
+        );
+        assert_eq!(value["version"], json!(CURRENT_VERSION));
+    }
+}
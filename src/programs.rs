@@ -3,7 +3,7 @@ use std::{collections::HashMap, os::fd::AsFd};
 use anyhow::Context;
 use libbpf_rs::{Object, ProgramImpl, Xdp};
 
-use crate::{cli::Load, helpers};
+use crate::{cli::Load, config::DEFAULT_XDP_MODE, helpers};
 
 pub fn get_programs(object: &Object) -> Option<HashMap<String, ProgramImpl<'_>>> {
     let programs = object.progs();
@@ -21,7 +21,7 @@ pub fn attach_xdp<'a>(program: &'a ProgramImpl, options: &Load) -> Result<Xdp<'a
     let xdp = Xdp::new(program.as_fd());
     xdp.attach(
         helpers::iface_to_idx(&options.iface)?,
-        helpers::get_xdp_flags(&options.xdp_flags)?,
+        helpers::get_xdp_flags(options.xdp_flags.unwrap_or(DEFAULT_XDP_MODE)),
     )
     .with_context(|| "Failed to attach BPF program to XDP".to_string())?;
     Ok(xdp)
@@ -30,7 +30,7 @@ pub fn attach_xdp<'a>(program: &'a ProgramImpl, options: &Load) -> Result<Xdp<'a
 pub fn detach_xdp(xdp: &Xdp, options: &Load) -> Result<(), anyhow::Error> {
     xdp.detach(
         helpers::iface_to_idx(&options.iface)?,
-        helpers::get_xdp_flags(&options.xdp_flags)?,
+        helpers::get_xdp_flags(options.xdp_flags.unwrap_or(DEFAULT_XDP_MODE)),
     )
     .with_context(|| "Failed to detach BPF program from XDP".to_string())?;
     Ok(())
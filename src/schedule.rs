@@ -0,0 +1,328 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use crossterm::style::Stylize;
+use tokio::signal;
+
+use crate::{
+    cli,
+    config::{Config, Init, List, Schedule, Window},
+    maps::{self, load_control},
+    target::{Target, COMMAND_TIMEOUT},
+};
+
+/// Control map indexes. Must match order used by generator.
+pub static CONTROL_WHITELIST: u32 = 0;
+pub static CONTROL_BLACKLIST: u32 = 1;
+pub static CONTROL_GRAYLIST: u32 = 2;
+pub static CONTROL_DEFAULT: u32 = 3;
+pub static CONTROL_SIZE: usize = 4;
+
+/// Checks if any list or default action carries a schedule.
+pub fn has_schedules(init: &Init) -> bool {
+    init.whitelist
+        .as_ref()
+        .is_some_and(|l| l.get_schedule().is_some())
+        || init
+            .blacklist
+            .as_ref()
+            .is_some_and(|l| l.get_schedule().is_some())
+        || init
+            .graylist
+            .as_ref()
+            .is_some_and(|l| l.get_schedule().is_some())
+        || init.scheduled_action.is_some()
+}
+
+/// Values of control map at given time. Lists without schedule are always on.
+pub fn control_state(init: &Init, now: DateTime<Utc>) -> Result<Vec<u32>, anyhow::Error> {
+    let mut state = vec![1; CONTROL_SIZE];
+
+    let lists: [(u32, Option<&Schedule>); 3] = [
+        (
+            CONTROL_WHITELIST,
+            init.whitelist.as_ref().and_then(|l| l.get_schedule()),
+        ),
+        (
+            CONTROL_BLACKLIST,
+            init.blacklist.as_ref().and_then(|l| l.get_schedule()),
+        ),
+        (
+            CONTROL_GRAYLIST,
+            init.graylist.as_ref().and_then(|l| l.get_schedule()),
+        ),
+    ];
+    for (i, s) in lists {
+        if let Some(s) = s {
+            state[i as usize] = is_active(s, now)? as u32;
+        }
+    }
+
+    state[CONTROL_DEFAULT as usize] = match init.scheduled_action.as_ref() {
+        Some(a) => is_active(&a.schedule, now)? as u32,
+        None => 0,
+    };
+
+    Ok(state)
+}
+
+pub fn is_active(schedule: &Schedule, now: DateTime<Utc>) -> Result<bool, anyhow::Error> {
+    let tz = parse_timezone(schedule)?;
+    let now = now.with_timezone(&tz);
+    let today = now.weekday();
+    let time = now.time();
+
+    for w in &schedule.windows {
+        let start = parse_time(&w.start)?;
+        let end = parse_time(&w.end)?;
+        let days = parse_days(w)?;
+
+        let active = if start < end {
+            days.contains(&today) && time >= start && time < end
+        } else {
+            // Crosses midnight. Early hours belong to window started previous day
+            (days.contains(&today) && time >= start) || (days.contains(&today.pred()) && time < end)
+        };
+
+        if active {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+pub fn parse_timezone(schedule: &Schedule) -> Result<Tz, anyhow::Error> {
+    match schedule.timezone.as_ref() {
+        Some(tz) => tz
+            .parse()
+            .map_err(|_| anyhow!("Unknown timezone \"{}\"", tz)),
+        None => Ok(Tz::UTC),
+    }
+}
+
+pub fn parse_time(time: &str) -> Result<NaiveTime, anyhow::Error> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .with_context(|| format!("Invalid time \"{}\". Expected HH:MM", time))
+}
+
+fn parse_day(day: &str) -> Result<Weekday, anyhow::Error> {
+    day.trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid weekday \"{}\". Expected mon, tue, ...", day))
+}
+
+pub fn parse_days(window: &Window) -> Result<Vec<Weekday>, anyhow::Error> {
+    let days = match window.days.as_ref() {
+        Some(d) => d,
+        None => {
+            return Ok(vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+                Weekday::Sat,
+                Weekday::Sun,
+            ])
+        }
+    };
+
+    let mut parsed = Vec::new();
+    for d in days {
+        match d.split_once('-') {
+            Some((from, to)) => {
+                let mut day = parse_day(from)?;
+                let to = parse_day(to)?;
+                parsed.push(day);
+                while day != to {
+                    day = day.succ();
+                    parsed.push(day);
+                }
+            }
+            None => parsed.push(parse_day(d)?),
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn describe(state: &[u32]) -> String {
+    let names = ["whitelist", "blacklist", "graylist", "scheduled action"];
+    names
+        .iter()
+        .zip(state)
+        .map(|(n, s)| {
+            if *s == 1 {
+                format!("{} {}", n, "(on)".green().bold())
+            } else {
+                format!("{} {}", n, "(off)".red().bold())
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn find_control_map(progs: &str, maps: &str, name: &str) -> Result<u64, anyhow::Error> {
+    maps::find_map(progs, maps, name, "control")
+        .with_context(|| "Control map not found. Is program with schedules loaded?".to_string())
+}
+
+/// Keeps control map of loaded program in sync with schedules.
+pub async fn schedule(options: cli::Schedule, config: Config) -> Result<(), anyhow::Error> {
+    let init = config.init.as_ref().unwrap();
+    if !has_schedules(init) {
+        return Err(anyhow!("No schedules found in config"));
+    }
+
+    let target = Target::connect(init, "Schedule".cyan().bold())?;
+    let progs = target.exec("bpftool prog show -j", COMMAND_TIMEOUT)?.stdout;
+    let output = target.exec("bpftool map show -j", COMMAND_TIMEOUT)?.stdout;
+    let map_id = find_control_map(&progs, &output, config.name())?;
+
+    println!(
+        "{}: Enforcing schedules every {}s. Press Ctrl-C to stop.",
+        "Schedule".cyan().bold(),
+        options.interval
+    );
+
+    let mut last: Vec<u32> = Vec::new();
+    loop {
+        let state = control_state(init, Utc::now())?;
+        if state != last {
+            load_control(map_id, &state, &target)?;
+            println!(
+                "{}: [{}] {}",
+                "Schedule".cyan().bold(),
+                Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                describe(&state)
+            );
+            last = state;
+        }
+
+        tokio::select! {
+            _ = signal::ctrl_c() => {
+                println!("\rTerminating...");
+                break;
+            }
+            _ = tokio::time::sleep(Duration::from_secs(options.interval)) => (),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // 2026-10-16 is a Friday
+        Utc.with_ymd_and_hms(2026, 10, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn schedule(value: serde_json::Value) -> Schedule {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn matches_window_within_day() {
+        let s =
+            schedule(json!({"windows": [{"days": ["mon-fri"], "start": "09:00", "end": "17:00"}]}));
+
+        assert!(is_active(&s, at(16, 9, 0)).unwrap());
+        assert!(is_active(&s, at(16, 16, 59)).unwrap());
+        assert!(!is_active(&s, at(16, 17, 0)).unwrap());
+        assert!(!is_active(&s, at(16, 8, 59)).unwrap());
+        // Saturday
+        assert!(!is_active(&s, at(17, 12, 0)).unwrap());
+    }
+
+    #[test]
+    fn wraps_window_past_midnight() {
+        let s = schedule(json!({"windows": [{"days": ["fri"], "start": "22:00", "end": "06:00"}]}));
+
+        assert!(is_active(&s, at(16, 23, 0)).unwrap());
+        // Early Saturday belongs to window started on Friday
+        assert!(is_active(&s, at(17, 5, 59)).unwrap());
+        assert!(!is_active(&s, at(17, 6, 0)).unwrap());
+        assert!(!is_active(&s, at(17, 23, 0)).unwrap());
+        // Early Friday belongs to Thursday, which has no window
+        assert!(!is_active(&s, at(16, 3, 0)).unwrap());
+    }
+
+    #[test]
+    fn uses_timezone() {
+        let s = schedule(json!({
+            "timezone": "Europe/Vilnius",
+            "windows": [{"start": "09:00", "end": "10:00"}]
+        }));
+
+        // UTC+3 in October
+        assert!(is_active(&s, at(16, 6, 30)).unwrap());
+        assert!(!is_active(&s, at(16, 9, 30)).unwrap());
+
+        let s = schedule(json!({"timezone": "Mars/Olympus", "windows": []}));
+        assert!(is_active(&s, at(16, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn parses_day_ranges() {
+        let window = |days: &[&str]| Window {
+            days: Some(days.iter().map(|d| d.to_string()).collect()),
+            start: "00:00".to_string(),
+            end: "01:00".to_string(),
+        };
+
+        assert_eq!(
+            parse_days(&window(&["fri-mon"])).unwrap(),
+            vec![Weekday::Fri, Weekday::Sat, Weekday::Sun, Weekday::Mon]
+        );
+        assert_eq!(
+            parse_days(&window(&["tue", "thu"])).unwrap(),
+            vec![Weekday::Tue, Weekday::Thu]
+        );
+        assert!(parse_days(&window(&["someday"])).is_err());
+        assert!(parse_time("25:00").is_err());
+    }
+
+    #[test]
+    fn builds_control_state() {
+        let init: Init = serde_json::from_value(json!({
+            "whitelist": {"enabled": true, "max": 8, "action": "allow"},
+            "blacklist": {
+                "enabled": true,
+                "max": 8,
+                "action": "deny",
+                "schedule": {"windows": [{"start": "22:00", "end": "06:00"}]}
+            },
+            "scheduled_action": {
+                "action": "drop",
+                "schedule": {"windows": [{"start": "12:00", "end": "13:00"}]}
+            }
+        }))
+        .unwrap();
+
+        // Lists without schedule and graylist, which is not configured, stay on
+        assert_eq!(
+            control_state(&init, at(16, 12, 30)).unwrap(),
+            vec![1, 0, 1, 1]
+        );
+        assert_eq!(
+            control_state(&init, at(16, 23, 0)).unwrap(),
+            vec![1, 1, 1, 0]
+        );
+
+        let init: Init = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(
+            control_state(&init, at(16, 0, 0)).unwrap(),
+            vec![1, 1, 1, 0]
+        );
+    }
+}
@@ -2,6 +2,7 @@
 pub static BASE_IP: &str = "// clang-format off
 #include \"vmlinux.h\"
 #include <bpf/bpf_helpers.h>
+#include <bpf/bpf_endian.h>
 // clang-format on
 
 struct Data {
@@ -11,14 +12,29 @@ struct Data {
     __u64 last_access_ns;
 };
 
+struct Prefix {
+    __u32 prefixlen;
+    __u32 ip;
+};
+
 static __u64 MS_IN_NS = 1000000;
 
 {{whitelist_map}}
 
+{{whitelist_net_map}}
+
 {{blacklist_map}}
 
+{{blacklist_net_map}}
+
 {{graylist_map}}
 
+{{bruteforce_map}}
+
+{{control_map}}
+
+{{overflow_map}}
+
 SEC(\"xdp\")
 int {{name}}(struct xdp_md *ctx) {
     void *data = (void *)(long)ctx->data;
@@ -44,10 +60,18 @@ int {{name}}(struct xdp_md *ctx) {
 
     {{whitelist_action}}
 
+    {{whitelist_net_action}}
+
     {{blacklist_action}}
 
+    {{blacklist_net_action}}
+
+    {{bruteforce_action}}
+
     {{graylist_action}}
 
+    {{default_action_schedule}}
+
     return {{default_action}};
 }
 
@@ -69,8 +93,16 @@ struct Data {
   __u64 last_access_ns;
 };
 
+struct Prefix {
+  __u32 prefixlen;
+  __u32 ip;
+};
+
 {{whitelist_map}}
+{{whitelist_net_map}}
 {{blacklist_map}}
+{{blacklist_net_map}}
+{{control_map}}
 
 SEC(\"xdp\")
 int {{name}}(struct xdp_md *ctx) {
@@ -105,8 +137,11 @@ int {{name}}(struct xdp_md *ctx) {
     // Extract destination IP address
 
     {{whitelist_action}}
+    {{whitelist_net_action}}
     {{blacklist_action}}
+    {{blacklist_net_action}}
 
+    {{default_action_schedule}}
 
   return {{default_action}};
 }
@@ -114,26 +149,292 @@ int {{name}}(struct xdp_md *ctx) {
 char __license[] SEC(\"license\") = \"GPL\";
 ";
 
+pub static BASE_UDP: &str = "// clang-format off
+#include \"vmlinux.h\"
+#include <bpf/bpf_helpers.h>
+#include <bpf/bpf_endian.h>
+// clang-format on
+
+struct Data {
+    __u32 ip;
+    __u64 rx_packets;
+    __u64 fast_packets;
+    __u64 last_access_ns;
+};
+
+struct Prefix {
+    __u32 prefixlen;
+    __u32 ip;
+};
+
+static __u64 MS_IN_NS = 1000000;
+
+{{whitelist_map}}
+
+{{whitelist_net_map}}
+
+{{blacklist_map}}
+
+{{blacklist_net_map}}
+
+{{graylist_map}}
+
+{{amplification_map}}
+
+{{control_map}}
+
+{{overflow_map}}
+
+SEC(\"xdp\")
+int {{name}}(struct xdp_md *ctx) {
+    void *data = (void *)(long)ctx->data;
+    void *data_end = (void *)(long)ctx->data_end;
+
+    // Check Ethernet header size
+    if (data + sizeof(struct ethhdr) > data_end)
+        return XDP_PASS;
+
+    struct ethhdr *eth = data;
+
+    // Check IP header size
+    struct iphdr *ip = data + sizeof(struct ethhdr);
+    if (data + sizeof(struct ethhdr) + sizeof(struct iphdr) > data_end)
+        return XDP_PASS;
+
+    if (ip->protocol != IPPROTO_UDP) {
+        return XDP_PASS;
+    }
+
+    struct udphdr *udp = (void *)ip + (ip->ihl * 4);
+    if ((void *)(udp + 1) > data_end)
+        return XDP_PASS;
+
+    // Extract source IP address
+    __u32 src_ip = ip->saddr;
+
+    {{whitelist_action}}
+
+    {{whitelist_net_action}}
+
+    {{blacklist_action}}
+
+    {{blacklist_net_action}}
+
+    {{amplification_action}}
+
+    {{graylist_action}}
+
+    {{default_action_schedule}}
+
+    return {{default_action}};
+}
+
+char __license[] SEC(\"license\") = \"GPL\";
+";
+
 /// Map template
 pub static MAP: &str = "#define {{name}}map
 struct {
-	__uint(type, BPF_MAP_TYPE_LRU_HASH);
+	__uint(type, {{map_type}});
 	__type(key, __u32);
 	__type(value, struct Data);
 	__uint(max_entries, {{max}});
 } {{name}} SEC(\".maps\");
 ";
 
+/// Country/ASN prefix map template
+pub static NET_MAP: &str = "#define {{name}}map
+struct {
+	__uint(type, BPF_MAP_TYPE_LPM_TRIE);
+	__type(key, struct Prefix);
+	__type(value, __u32);
+	__uint(map_flags, BPF_F_NO_PREALLOC);
+	__uint(max_entries, {{max}});
+} {{name}} SEC(\".maps\");
+";
+
+/// Runtime control map template (schedules)
+pub static CONTROL_MAP: &str = "#define controlmap
+struct {
+	__uint(type, BPF_MAP_TYPE_ARRAY);
+	__type(key, __u32);
+	__type(value, __u32);
+	__uint(max_entries, {{max}});
+} control SEC(\".maps\");
+";
+
+/// Failed insertion counters. Index: 0 whitelist, 1 blacklist, 2 graylist
+pub static OVERFLOW_MAP: &str = "#define overflowmap
+struct {
+	__uint(type, BPF_MAP_TYPE_ARRAY);
+	__type(key, __u32);
+	__type(value, __u64);
+	__uint(max_entries, {{max}});
+} overflow SEC(\".maps\");
+
+// vmlinux.h has no errno defines
+#ifndef E2BIG
+#define E2BIG 7
+#endif
+
+// Non-evicting maps return -E2BIG when full
+static __always_inline void insert_elem(void *map, void *key, void *value, __u64 flags, __u32 index) {
+    if (bpf_map_update_elem(map, key, value, flags) == -E2BIG) {
+        __u64 *count = bpf_map_lookup_elem(&overflow, &index);
+        if (count)
+            __sync_fetch_and_add(count, 1);
+    }
+}
+";
+
+/// Brute-force tracking map template
+pub static BRUTEFORCE_MAP: &str = "#define bruteforcemap
+struct Attempt {
+    __u32 ip;
+    __u32 port;
+};
+
+struct Attempts {
+    __u64 count;
+    __u64 window_start_ns;
+};
+
+struct {
+	__uint(type, BPF_MAP_TYPE_LRU_HASH);
+	__type(key, struct Attempt);
+	__type(value, struct Attempts);
+	__uint(max_entries, {{max}});
+} bruteforce SEC(\".maps\");
+";
+
+/// UDP amplification tracking map template
+pub static AMPLIFICATION_MAP: &str = "#define amplificationmap
+struct Flow {
+    __u32 ip;
+    __u32 port;
+};
+
+struct Usage {
+    __u64 requests;
+    __u64 request_bytes;
+    __u64 response_bytes;
+    __u64 window_start_ns;
+};
+
+struct {
+	__uint(type, BPF_MAP_TYPE_LRU_HASH);
+	__type(key, struct Flow);
+	__type(value, struct Usage);
+	__uint(max_entries, {{max}});
+} amplification SEC(\".maps\");
+";
+
+/// Match one amplification service port. Requests are counted for their source, responses for
+/// their destination, so both land on the address that asked.
+pub static AMPLIFICATION_PORT: &str = "if (udp->dest == bpf_htons({{port}})) {
+    amp_port = {{port}};
+    amp_ip = src_ip;
+} else if (udp->source == bpf_htons({{port}})) {
+    amp_port = {{port}};
+    amp_ip = ip->daddr;
+    amp_response = 1;
+}
+";
+
+/// Track request rate and response/request size ratio per requesting address and service.
+/// Ratio is only checked for addresses seen asking, so responses to requests sent by the host
+/// itself (never seen by XDP) don't flag it.
+pub static AMPLIFICATION: &str = "if (amp_port) {
+    struct Flow amp_key = {amp_ip, amp_port};
+    __u64 now = bpf_ktime_get_ns();
+    __u64 len = data_end - data;
+    struct Usage *usage = bpf_map_lookup_elem(&amplification, &amp_key);
+    if (!usage || now - usage->window_start_ns > (__u64){{window}} * MS_IN_NS) {
+        struct Usage new = {0, 0, 0, now};
+        bpf_map_update_elem(&amplification, &amp_key, &new, BPF_ANY);
+        usage = bpf_map_lookup_elem(&amplification, &amp_key);
+        if (!usage)
+            return XDP_PASS;
+    }
+    if (amp_response) {
+        __sync_fetch_and_add(&usage->response_bytes, len);
+    } else {
+        __sync_fetch_and_add(&usage->requests, 1);
+        __sync_fetch_and_add(&usage->request_bytes, len);
+    }
+    if (usage->requests > {{max_requests}}
+        || (usage->requests
+            && usage->response_bytes > {{min_bytes}}
+            && usage->response_bytes > usage->request_bytes * {{max_ratio}})) {
+        {{flag}}
+    }
+}
+";
+
+/// Flag amplification source into blacklist
+pub static AMPLIFICATION_DENY: &str = "#ifdef blacklistmap
+        struct Data amp_new = {amp_ip, usage->requests, 0, now};
+        insert_elem(&blacklist, &amp_ip, &amp_new, BPF_NOEXIST, 1);
+    #endif
+        return XDP_DROP;";
+
+/// Flag amplification source into graylist
+pub static AMPLIFICATION_INVESTIGATE: &str = "#ifdef graylistmap
+        struct Data amp_new = {amp_ip, 1, 0, now};
+        insert_elem(&graylist, &amp_ip, &amp_new, BPF_NOEXIST, 2);
+    #endif";
+
+/// Get TCP header (for brute-force detection)
+pub static GET_DATA_TCP: &str = "struct tcphdr *tcp = (void *)ip + (ip->ihl * 4);
+if ((void *)(tcp + 1) > data_end)
+    return XDP_PASS;
+";
+
+/// Count SYNs per source for one service port
+pub static BRUTEFORCE: &str = "if (tcp->syn && !tcp->ack && tcp->dest == bpf_htons({{port}})) {
+    struct Attempt key = {src_ip, {{port}}};
+    struct Attempts *attempts = bpf_map_lookup_elem(&bruteforce, &key);
+    __u64 now = bpf_ktime_get_ns();
+    if (attempts && now - attempts->window_start_ns < (__u64){{window}} * MS_IN_NS) {
+        __sync_fetch_and_add(&attempts->count, 1);
+        if (attempts->count > {{max_attempts}}) {
+        #ifdef blacklistmap
+            struct Data new = {src_ip, 0, attempts->count, now};
+            insert_elem(&blacklist, &src_ip, &new, BPF_NOEXIST, 1);
+        #endif
+            return XDP_DROP;
+        }
+    } else {
+        struct Attempts new = {1, now};
+        bpf_map_update_elem(&bruteforce, &key, &new, BPF_ANY);
+    }
+}
+";
+
 /// Get ip/dns data
 pub static GET_DATA_IP: &str = "struct Data *{{list}}_data = bpf_map_lookup_elem(&{{list}}, &src_ip);";
 pub static GET_DATA_DNS: &str = "struct Data *{{list}}_data = bpf_map_lookup_elem(&{{list}}, &dst);";
 
+/// Get ip/dns prefix match
+pub static GET_NET_IP: &str = "struct Prefix {{list}}_key = {32, src_ip};
+__u32 *{{list}}_data = bpf_map_lookup_elem(&{{list}}, &{{list}}_key);";
+pub static GET_NET_DNS: &str = "struct Prefix {{list}}_key = {32, dst};
+__u32 *{{list}}_data = bpf_map_lookup_elem(&{{list}}, &{{list}}_key);";
+
 /// Allow/Deny action (for whitelist/blacklist)
 pub static ACTION: &str = "if ({{list}}_data) {
 		return {{action}};
 	}
 ";
 
+/// Apply action only while its control flag is set
+pub static SCHEDULED: &str = "__u32 {{list}}_ctrl_key = {{index}};
+__u32 *{{list}}_ctrl = bpf_map_lookup_elem(&control, &{{list}}_ctrl_key);
+if ({{list}}_ctrl && *{{list}}_ctrl) {
+{{action}}
+}
+";
+
 /// Investigate action (for graylist)
 pub static GRAYLIST: &str = "if ({{list}}_data) {
     __u64 time = (__u64){{frequency}} * MS_IN_NS;
@@ -143,7 +444,7 @@ pub static GRAYLIST: &str = "if ({{list}}_data) {
     #ifdef blacklistmap
         if ({{list}}_data->fast_packets >= {{fast_packet_count}}) {
             struct Data new = {src_ip, {{list}}_data->rx_packets, {{list}}_data->fast_packets, bpf_ktime_get_ns()};
-            bpf_map_update_elem(&blacklist, &src_ip, &new, BPF_NOEXIST);
+            insert_elem(&blacklist, &src_ip, &new, BPF_NOEXIST, 1);
             return XDP_DROP;
         }
     #endif
@@ -157,6 +458,6 @@ pub static GRAYLIST: &str = "if ({{list}}_data) {
     __sync_fetch_and_add(&{{list}}_data->last_access_ns, bpf_ktime_get_ns() - {{list}}_data->last_access_ns);
 } else {
     struct Data new = {src_ip, 1, 0,bpf_ktime_get_ns()};
-    bpf_map_update_elem(&{{list}}, &src_ip, &new, BPF_NOEXIST);
+    insert_elem(&{{list}}, &src_ip, &new, BPF_NOEXIST, 2);
 }
 ";
@@ -0,0 +1,309 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use anyhow::Context;
+use ipnetwork::Ipv4Network;
+use serde_json::Value;
+
+use crate::{
+    config::{Column, Config, Data, ListFile, ListFormat, ListSource},
+    geo,
+};
+
+/// Data keys holding list files.
+pub static LIST_FILES: [&str; 3] = ["whitelist_files", "blacklist_files", "graylist_files"];
+
+/// Reads list files of config and merges them into inline lists. Entries are deduplicated,
+/// first occurrence wins.
+pub fn resolve(config: &mut Config, base: &Path) -> Result<(), anyhow::Error> {
+    if let Some(data) = config.data.as_mut() {
+        resolve_data(data, base)?;
+    }
+    for (i, p) in config.programs.iter_mut().flatten().enumerate() {
+        if let Some(data) = p.data.as_mut() {
+            resolve_data(data, base).with_context(|| format!("programs[{}]", i))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn resolve_data(data: &mut Data, base: &Path) -> Result<(), anyhow::Error> {
+    resolve_list(
+        &mut data.whitelist,
+        data.whitelist_files.take(),
+        "whitelist",
+        base,
+    )?;
+    resolve_list(
+        &mut data.blacklist,
+        data.blacklist_files.take(),
+        "blacklist",
+        base,
+    )?;
+    resolve_list(
+        &mut data.graylist,
+        data.graylist_files.take(),
+        "graylist",
+        base,
+    )?;
+
+    Ok(())
+}
+
+fn resolve_list(
+    list: &mut Option<Vec<String>>,
+    files: Option<Vec<ListSource>>,
+    name: &str,
+    base: &Path,
+) -> Result<(), anyhow::Error> {
+    let files = match files {
+        Some(f) => f,
+        None => return Ok(()),
+    };
+
+    let mut entries = list.take().unwrap_or_default();
+    for (i, f) in files.iter().enumerate() {
+        entries
+            .extend(read_source(f, base).with_context(|| format!("data.{}_files[{}]", name, i))?);
+    }
+
+    let mut seen = HashSet::new();
+    entries.retain(|e| seen.insert(e.trim().to_string()));
+    *list = Some(entries);
+
+    Ok(())
+}
+
+fn read_source(source: &ListSource, base: &Path) -> Result<Vec<String>, anyhow::Error> {
+    let file = match source {
+        ListSource::Path(p) => ListFile {
+            path: p.clone(),
+            format: None,
+            column: None,
+        },
+        ListSource::File(f) => f.clone(),
+    };
+
+    let path = base.join(&file.path);
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read list file {}", path.display()))?;
+
+    let format = file.format.unwrap_or_else(|| {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(|e| e.parse().ok())
+            .unwrap_or(ListFormat::Plain)
+    });
+
+    match format {
+        ListFormat::Plain => Ok(parse_plain(&content)),
+        ListFormat::Csv => parse_csv(&content, file.column.as_ref()),
+        ListFormat::Json => parse_json(&content, file.column.as_ref()),
+    }
+}
+
+/// Drops # comments and blank lines.
+fn strip_comments(content: &str) -> impl Iterator<Item = &str> {
+    content
+        .lines()
+        .map(|l| l.split('#').next().unwrap_or_default().trim())
+        .filter(|l| !l.is_empty())
+}
+
+/// One entry per line.
+fn parse_plain(content: &str) -> Vec<String> {
+    strip_comments(content).map(|l| l.to_string()).collect()
+}
+
+/// Column by header name (first row is header) or by index. First column by default. Without
+/// name, first row is skipped as header when its column holds no address.
+fn parse_csv(content: &str, column: Option<&Column>) -> Result<Vec<String>, anyhow::Error> {
+    let mut rows = strip_comments(content)
+        .map(|l| {
+            l.split(',')
+                .map(|f| f.trim().trim_matches('"').trim())
+                .collect::<Vec<&str>>()
+        })
+        .peekable();
+
+    let index = match column {
+        Some(Column::Name(name)) => {
+            let header = rows
+                .next()
+                .with_context(|| "CSV file is empty".to_string())?;
+            header
+                .iter()
+                .position(|h| h == name)
+                .with_context(|| format!("Column \"{}\" not found in CSV header", name))?
+        }
+        Some(Column::Index(i)) => *i,
+        None => 0,
+    };
+    if !matches!(column, Some(Column::Name(_)))
+        && rows
+            .peek()
+            .and_then(|header| header.get(index))
+            .is_some_and(|h| !is_entry(h))
+    {
+        rows.next();
+    }
+
+    let mut entries = Vec::new();
+    for (i, row) in rows.enumerate() {
+        let entry = row
+            .get(index)
+            .with_context(|| format!("Row {} has no column {}", i + 1, index))?;
+        if !entry.is_empty() {
+            entries.push(entry.to_string());
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Address, network or country/ASN entry.
+fn is_entry(field: &str) -> bool {
+    field.parse::<Ipv4Network>().is_ok() || geo::is_geo_entry(field)
+}
+
+/// Array of strings, or array of objects with column as key. Lines starting with # or // are
+/// comments.
+fn parse_json(content: &str, column: Option<&Column>) -> Result<Vec<String>, anyhow::Error> {
+    let content: String = content
+        .lines()
+        .filter(|l| {
+            let l = l.trim_start();
+            !l.starts_with('#') && !l.starts_with("//")
+        })
+        .collect::<Vec<&str>>()
+        .join("\n");
+
+    let values: Vec<Value> =
+        serde_json::from_str(&content).with_context(|| "Expected JSON array".to_string())?;
+
+    let mut entries = Vec::new();
+    for (i, v) in values.iter().enumerate() {
+        let entry = match (v, column) {
+            (Value::String(s), _) => Some(s.as_str()),
+            (Value::Object(o), Some(Column::Name(name))) => o.get(name).and_then(|v| v.as_str()),
+            (Value::Array(a), Some(Column::Index(idx))) => a.get(*idx).and_then(|v| v.as_str()),
+            _ => None,
+        };
+        let entry = entry.with_context(|| format!("Element {} has no address", i))?;
+        entries.push(entry.trim().to_string());
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_plain_lines() {
+        assert_eq!(
+            parse_plain("# blocklist\n10.0.0.1\n\n 10.0.0.2 # scanner\n"),
+            vec!["10.0.0.1", "10.0.0.2"]
+        );
+    }
+
+    #[test]
+    fn skips_csv_header() {
+        let csv = "ip,reason\n10.0.0.1,scan\n\"10.0.0.0/24\",spam\n";
+        assert_eq!(
+            parse_csv(csv, None).unwrap(),
+            vec!["10.0.0.1", "10.0.0.0/24"]
+        );
+
+        // First row holds addresses, so it is not a header
+        assert_eq!(
+            parse_csv("10.0.0.1,scan\n10.0.0.2,spam", None).unwrap(),
+            vec!["10.0.0.1", "10.0.0.2"]
+        );
+        assert_eq!(
+            parse_csv("country\nLT\nAS15169", None).unwrap(),
+            vec!["LT", "AS15169"]
+        );
+    }
+
+    #[test]
+    fn selects_csv_column() {
+        let csv = "# exported\nreason,ip\nscan,10.0.0.1\nspam,10.0.0.2\n";
+        assert_eq!(
+            parse_csv(csv, Some(&Column::Name("ip".to_string()))).unwrap(),
+            vec!["10.0.0.1", "10.0.0.2"]
+        );
+        assert_eq!(
+            parse_csv(csv, Some(&Column::Index(1))).unwrap(),
+            vec!["10.0.0.1", "10.0.0.2"]
+        );
+
+        let error = parse_csv(csv, Some(&Column::Name("addr".to_string()))).unwrap_err();
+        assert_eq!(error.to_string(), "Column \"addr\" not found in CSV header");
+        let error = parse_csv(csv, Some(&Column::Index(2))).unwrap_err();
+        assert_eq!(error.to_string(), "Row 1 has no column 2");
+    }
+
+    #[test]
+    fn reads_json_arrays() {
+        assert_eq!(
+            parse_json("// list\n[\"10.0.0.1\", \" 10.0.0.2 \"]", None).unwrap(),
+            vec!["10.0.0.1", "10.0.0.2"]
+        );
+        assert_eq!(
+            parse_json(
+                "[{\"ip\": \"10.0.0.1\"}]",
+                Some(&Column::Name("ip".to_string()))
+            )
+            .unwrap(),
+            vec!["10.0.0.1"]
+        );
+        assert!(parse_json("[{\"addr\": \"10.0.0.1\"}]", None).is_err());
+    }
+
+    #[test]
+    fn picks_format() {
+        let dir = std::env::temp_dir().join(format!("hpx-formats-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("block.csv"), "ip,reason\n10.0.0.1,scan\n").unwrap();
+        fs::write(dir.join("block.txt"), "[\"10.0.0.2\"]").unwrap();
+
+        let by_extension = ListSource::Path("block.csv".to_string());
+        let given: ListSource =
+            serde_json::from_str(r#"{"path": "block.txt", "format": "JSON"}"#).unwrap();
+        let read = (read_source(&by_extension, &dir), read_source(&given, &dir));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(read.0.unwrap(), vec!["10.0.0.1"]);
+        assert_eq!(read.1.unwrap(), vec!["10.0.0.2"]);
+
+        let error = serde_json::from_str::<ListSource>(r#"{"path": "a", "format": "xml"}"#);
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "unsupported list file format \"xml\". Expected one of: plain, csv, json"
+        );
+    }
+
+    #[test]
+    fn merges_files_into_list() {
+        let dir = std::env::temp_dir().join(format!("hpx-sources-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("block.txt"), "10.0.0.2\n10.0.0.1\n").unwrap();
+
+        let mut list = Some(vec!["10.0.0.1".to_string()]);
+        resolve_list(
+            &mut list,
+            Some(vec![ListSource::Path("block.txt".to_string())]),
+            "blacklist",
+            &dir,
+        )
+        .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            list,
+            Some(vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()])
+        );
+    }
+}
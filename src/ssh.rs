@@ -0,0 +1,649 @@
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Display,
+    fs::{self, OpenOptions},
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
+use home::home_dir;
+use ssh2::{Channel, CheckResult, ErrorCode, HashType, HostKeyType, KnownHostFileKind, Session};
+
+use crate::{
+    config::{HostKeyCheck, Init, PasswordSource, ProxyJump, SshAuth},
+    credentials::Secret,
+    fleet,
+    target::Output,
+};
+
+static CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Keys tried when identity_file is not set, same as ssh does
+static DEFAULT_KEYS: [&str; 3] = ["~/.ssh/id_ed25519", "~/.ssh/id_ecdsa", "~/.ssh/id_rsa"];
+/// libssh2 error when private key can't be read, f.e. because it is encrypted
+const LIBSSH2_ERROR_FILE: i32 = -16;
+/// Prompt sudo is told to print, so it can be told apart from output of command
+static SUDO_PROMPT: &str = "[hpx-sudo-password]";
+pub static POLL_INTERVAL: Duration = Duration::from_millis(10);
+static KNOWN_HOSTS: &str = "~/.ssh/known_hosts";
+/// Exit status of timeout(1) when it stopped command
+static TIMEOUT_STATUS: i32 = 124;
+/// Time remote timeout(1) gets to stop command before session gives up waiting for it
+static TIMEOUT_GRACE: Duration = Duration::from_secs(10);
+/// Seconds between keepalives. Also lets reused sessions find out their connection is gone
+static KEEPALIVE_INTERVAL: u32 = 30;
+
+/// Hosts logged into during this run, by user@host:port. Commands touching the same host
+/// several times (f.e. from Lua scripts or multi-program configs) reuse their session.
+static HOSTS: LazyLock<Mutex<HashMap<String, Arc<Host>>>> = LazyLock::new(Default::default);
+
+/// stdout and stderr of command, and when its timeout started.
+type Streams = (Vec<u8>, Vec<u8>, Instant);
+
+/// Keeps session non-blocking while alive. Blocking is restored when dropped, also on early
+/// returns, so the session is left as other users of cached host expect it.
+struct NonBlocking<'a>(&'a Session);
+
+impl<'a> NonBlocking<'a> {
+    fn new(session: &'a Session) -> Self {
+        session.set_blocking(false);
+        NonBlocking(session)
+    }
+}
+
+impl Drop for NonBlocking<'_> {
+    fn drop(&mut self) {
+        self.0.set_blocking(true);
+    }
+}
+
+/// Logged in SSH session to a host.
+pub struct Host {
+    pub session: Session,
+    pub hostname: String,
+    username: String,
+    source: PasswordSource,
+    /// Login or sudo password, read from source when first needed
+    password: Mutex<Option<Secret>>,
+}
+
+/// Logged in session to init.hostname. Session opened earlier in this run is reused while its
+/// connection is alive.
+pub fn connect(init: &Init, prefix: impl Display) -> Result<Arc<Host>, anyhow::Error> {
+    let key = format!(
+        "{}@{}:{}",
+        init.username.as_deref().unwrap_or_default(),
+        init.hostname.as_deref().unwrap_or_default(),
+        init.port.unwrap_or(22)
+    );
+    let cached = HOSTS.lock().unwrap().get(&key).cloned();
+    if let Some(host) = cached {
+        if host.session.keepalive_send().is_ok() {
+            return Ok(host);
+        }
+    }
+
+    let host = Arc::new(login(init, prefix)?);
+    HOSTS.lock().unwrap().insert(key, host.clone());
+    Ok(host)
+}
+
+/// Opens SSH session to init.hostname and logs in with init.ssh_auth methods in order. With
+/// init.proxy_jump the session is tunneled through the jump hosts.
+pub fn login(init: &Init, prefix: impl Display) -> Result<Host, anyhow::Error> {
+    let hostname = init
+        .hostname
+        .as_deref()
+        .with_context(|| "No hostname to connect to".to_string())?;
+    let port = init.port.unwrap_or(22);
+
+    let mut session = Session::new()?;
+    match init.proxy_jump.as_deref() {
+        Some(hops) if !hops.is_empty() => {
+            session.set_tcp_stream(tunnel(hops, hostname, port, &prefix)?);
+        }
+        _ => {
+            let addr = (hostname, port)
+                .to_socket_addrs()
+                .with_context(|| format!("Failed to resolve {}", hostname))?
+                .next()
+                .with_context(|| format!("Failed to resolve {}", hostname))?;
+            let tcp = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+                .with_context(|| format!("Failed to connect to {}:{}", hostname, port))?;
+            session.set_tcp_stream(tcp);
+        }
+    }
+    session
+        .handshake()
+        .with_context(|| format!("SSH handshake with {} failed", hostname))?;
+    verify_host_key(&session, init, hostname, port, &prefix)?;
+    session.set_keepalive(false, KEEPALIVE_INTERVAL);
+
+    let username = match init.username.as_ref() {
+        Some(u) => {
+            println!("{}: Using username \"{}\"", prefix, u);
+            u.to_string()
+        }
+        None if fleet::is_host() => {
+            return Err(anyhow!(
+                "Username can't be asked for on inventory hosts. Set username"
+            ))
+        }
+        None => {
+            let mut username = String::new();
+            print!("Username: ");
+            io::stdout().flush()?;
+            io::stdin().read_line(&mut username)?;
+            username.trim().to_string()
+        }
+    };
+
+    let source = init.password_source.clone().unwrap_or_default();
+    // Asking for methods also tries "none" auth, which some servers accept
+    let accepted = session.auth_methods(&username)?.to_string();
+    let mut password = None;
+    let mut failures = Vec::new();
+    for method in init.get_ssh_auth() {
+        if session.authenticated() {
+            break;
+        }
+
+        let result = match method {
+            SshAuth::Agent => auth_agent(&session, &username),
+            SshAuth::Key if accepted.contains("publickey") => {
+                auth_key(&session, &username, init.identity_file.as_deref())
+            }
+            SshAuth::Password if accepted.contains("password") => {
+                auth_password(&session, &username, hostname, &source).map(|p| {
+                    password = Some(p);
+                    true
+                })
+            }
+            _ => Ok(false),
+        };
+        if let Err(e) = result {
+            failures.push(format!("{}: {:#}", method, e));
+        }
+    }
+
+    if !session.authenticated() {
+        return Err(anyhow!(
+            "Failed to authenticate as {} on {}. Host accepts: {}. Tried: {}",
+            username,
+            hostname,
+            accepted,
+            if failures.is_empty() {
+                "none of init.ssh_auth methods were usable".to_string()
+            } else {
+                failures.join("; ")
+            }
+        ));
+    }
+
+    Ok(Host {
+        session,
+        hostname: hostname.to_string(),
+        username,
+        source,
+        password: Mutex::new(password),
+    })
+}
+
+/// Logs into last of hops (through the ones before it) and opens direct-tcpip channel from
+/// it to hostname:port. Returns socket the channel is pumped to.
+fn tunnel(
+    hops: &[ProxyJump],
+    hostname: &str,
+    port: u16,
+    prefix: impl Display,
+) -> Result<UnixStream, anyhow::Error> {
+    let (last, before) = hops.split_last().unwrap();
+    let hop = last.host()?;
+    let hop_init = Init {
+        hostname: Some(hop.hostname),
+        port: hop.port,
+        proxy_jump: (!before.is_empty()).then(|| before.to_vec()),
+        username: hop.username,
+        identity_file: hop.identity_file,
+        ssh_auth: hop.ssh_auth,
+        password_source: hop.password_source,
+        host_key_fingerprint: hop.host_key_fingerprint,
+        host_key_check: hop.host_key_check,
+        ..Default::default()
+    };
+    // Plain string, so recursion doesn't instantiate login with ever deeper references
+    let prefix = prefix.to_string();
+    let jump = login(&hop_init, &prefix)?;
+    println!("{}: Jumping through {}", prefix, jump.hostname);
+
+    jump.session.set_timeout(CONNECT_TIMEOUT.as_millis() as u32);
+    let channel = jump
+        .session
+        .channel_direct_tcpip(hostname, port, None)
+        .with_context(|| {
+            format!(
+                "Jump host {} failed to open connection to {}:{}",
+                jump.hostname, hostname, port
+            )
+        })?;
+    jump.session.set_timeout(0);
+
+    let (local, remote) = UnixStream::pair()?;
+    thread::spawn(move || pump(jump, channel, remote));
+    Ok(local)
+}
+
+/// Copies data between socket and channel until either side closes. Owns jump host, so its
+/// session lives as long as the tunnel does.
+fn pump(jump: Host, mut channel: Channel, mut socket: UnixStream) {
+    jump.session.set_blocking(false);
+    if socket.set_nonblocking(true).is_err() {
+        return;
+    }
+
+    let mut buf = [0; 16384];
+    loop {
+        let mut idle = true;
+        match socket.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if write_all(&mut channel, &buf[..n]).is_err() {
+                    break;
+                }
+                idle = false;
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(_) => break,
+        }
+        match channel.read(&mut buf) {
+            Ok(0) if channel.eof() => break,
+            Ok(0) => {}
+            Ok(n) => {
+                if write_all(&mut socket, &buf[..n]).is_err() {
+                    break;
+                }
+                idle = false;
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(_) => break,
+        }
+        if idle {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+    let _ = channel.close();
+}
+
+/// Checks host key against init.host_key_fingerprint if it is set, otherwise against
+/// ~/.ssh/known_hosts. Unknown hosts are trusted on first use after asking, unless
+/// init.host_key_check is strict.
+fn verify_host_key(
+    session: &Session,
+    init: &Init,
+    hostname: &str,
+    port: u16,
+    prefix: impl Display,
+) -> Result<(), anyhow::Error> {
+    let (key, key_type) = session
+        .host_key()
+        .with_context(|| format!("{} did not send a host key", hostname))?;
+    let hash = session
+        .host_key_hash(HashType::Sha256)
+        .with_context(|| format!("Failed to hash host key of {}", hostname))?;
+    let fingerprint = format!("SHA256:{}", base64(hash, false));
+    let key_name = key_type_name(key_type)
+        .with_context(|| format!("{} sent host key of unknown type", hostname))?;
+
+    if let Some(expected) = init.host_key_fingerprint.as_ref() {
+        if expected.trim().trim_end_matches('=') == fingerprint {
+            return Ok(());
+        }
+        return Err(anyhow!(
+            "Host key of {} does not match init.host_key_fingerprint\n  Expected: {}\n  Received: {} ({})\nSomeone may be intercepting the connection. If the key was changed on purpose, update host_key_fingerprint",
+            hostname,
+            expected.trim(),
+            fingerprint,
+            key_name
+        ));
+    }
+
+    let path = expand_home(KNOWN_HOSTS);
+    let mut known_hosts = session.known_hosts()?;
+    if let Ok(content) = fs::read_to_string(&path) {
+        // Lines libssh2 doesn't understand (f.e. @cert-authority) are skipped
+        for line in content.lines() {
+            let _ = known_hosts.read_str(line, KnownHostFileKind::OpenSSH);
+        }
+    }
+
+    match known_hosts.check_port(hostname, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(anyhow!(
+            "Host key of {} does not match the one in {}\n  Received: {} ({})\nSomeone may be intercepting the connection. If the key was changed on purpose, remove the old one with \"ssh-keygen -R {}\"",
+            hostname,
+            path.display(),
+            fingerprint,
+            key_name,
+            known_hosts_name(hostname, port)
+        )),
+        CheckResult::NotFound => match init.get_host_key_check() {
+            HostKeyCheck::Strict => Err(anyhow!(
+                "{} is not in {} and init.host_key_check is strict\n  Received: {} ({})\nAdd the host to known_hosts or set init.host_key_fingerprint",
+                hostname,
+                path.display(),
+                fingerprint,
+                key_name
+            )),
+            HostKeyCheck::Ask if fleet::is_host() => Err(anyhow!(
+                "{} is not in {} and can't be confirmed on inventory hosts\n  Received: {} ({})\nAdd the host to known_hosts or set init.host_key_check = \"strict\" with init.host_key_fingerprint",
+                hostname,
+                path.display(),
+                fingerprint,
+                key_name
+            )),
+            HostKeyCheck::Ask => {
+                println!(
+                    "{}: Host {} is not known. Its {} key fingerprint is {}",
+                    prefix, hostname, key_name, fingerprint
+                );
+                print!("{}: Trust it and add it to {}? [y/N] ", prefix, path.display());
+                io::stdout().flush()?;
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer)?;
+                if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+                    return Err(anyhow!("Host key of {} was not trusted", hostname));
+                }
+
+                remember_host(&path, &known_hosts_name(hostname, port), key_name, key)?;
+                println!("{}: Added {} to {}", prefix, hostname, path.display());
+                Ok(())
+            }
+        },
+        CheckResult::Failure => Err(anyhow!("Failed to check host key of {}", hostname)),
+    }
+}
+
+/// Host as written in known_hosts. Non-standard ports are written as [host]:port.
+fn known_hosts_name(hostname: &str, port: u16) -> String {
+    match port {
+        22 => hostname.to_string(),
+        _ => format!("[{}]:{}", hostname, port),
+    }
+}
+
+/// Appends host key to known_hosts, creating it if needed.
+fn remember_host(path: &Path, host: &str, key_name: &str, key: &[u8]) -> Result<(), anyhow::Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let ends_with_newline = fs::read(path).map_or(true, |c| c.is_empty() || c.ends_with(b"\n"));
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    if !ends_with_newline {
+        writeln!(file)?;
+    }
+    writeln!(file, "{} {} {}", host, key_name, base64(key, true))?;
+    Ok(())
+}
+
+fn key_type_name(key_type: HostKeyType) -> Option<&'static str> {
+    match key_type {
+        HostKeyType::Rsa => Some("ssh-rsa"),
+        HostKeyType::Dss => Some("ssh-dss"),
+        HostKeyType::Ecdsa256 => Some("ecdsa-sha2-nistp256"),
+        HostKeyType::Ecdsa384 => Some("ecdsa-sha2-nistp384"),
+        HostKeyType::Ecdsa521 => Some("ecdsa-sha2-nistp521"),
+        HostKeyType::Ed255219 => Some("ssh-ed25519"),
+        HostKeyType::Unknown => None,
+    }
+}
+
+/// Standard base64, as ssh uses for keys and fingerprints.
+fn base64(data: &[u8], pad: bool) -> String {
+    const CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(CHARS[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else if pad {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Joins paths starting with ~/ to home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Ok(false) if there is no running agent.
+fn auth_agent(session: &Session, username: &str) -> Result<bool, anyhow::Error> {
+    if env::var_os("SSH_AUTH_SOCK").is_none() {
+        return Ok(false);
+    }
+    session.userauth_agent(username)?;
+    Ok(true)
+}
+
+/// Ok(false) if there are no keys to try. Encrypted keys ask for passphrase.
+fn auth_key(
+    session: &Session,
+    username: &str,
+    identity_file: Option<&str>,
+) -> Result<bool, anyhow::Error> {
+    let keys: Vec<PathBuf> = match identity_file {
+        Some(f) => vec![expand_home(f)],
+        None => DEFAULT_KEYS
+            .iter()
+            .map(|k| expand_home(k))
+            .filter(|k| k.exists())
+            .collect(),
+    };
+    if keys.is_empty() {
+        return Ok(false);
+    }
+
+    let mut errors = Vec::new();
+    for key in &keys {
+        let result = match session.userauth_pubkey_file(username, None, key, None) {
+            Err(e) if matches!(e.code(), ErrorCode::Session(LIBSSH2_ERROR_FILE)) => {
+                if fleet::is_host() {
+                    return Err(anyhow!(
+                        "Passphrase of {} can't be asked for on inventory hosts. Use ssh-agent or a key without passphrase",
+                        key.display()
+                    ));
+                }
+                let passphrase = Secret::new(rpassword::prompt_password(format!(
+                    "Passphrase for {}: ",
+                    key.display()
+                ))?);
+                session.userauth_pubkey_file(username, None, key, Some(passphrase.expose()))
+            }
+            r => r,
+        };
+        match result {
+            Ok(_) => return Ok(true),
+            Err(e) => errors.push(format!("{} ({})", key.display(), e.message())),
+        }
+    }
+    Err(anyhow!("{} not accepted", errors.join(", ")))
+}
+
+fn auth_password(
+    session: &Session,
+    username: &str,
+    hostname: &str,
+    source: &PasswordSource,
+) -> Result<Secret, anyhow::Error> {
+    let password = source.read(&format!("{}@{}'s password: ", username, hostname))?;
+    session.userauth_password(username, password.expose())?;
+    Ok(password)
+}
+
+impl Host {
+    /// Runs command as root. Root logins run it as is. Otherwise it goes through sudo and
+    /// password is written to its stdin only when sudo asks for it, so it never shows up in
+    /// command lines. Returns None if command timed out.
+    pub fn sudo(&self, command: &str, timeout: Duration) -> Result<Option<Output>, anyhow::Error> {
+        // Inside sudo, so time spent typing the password doesn't count
+        let command = timed(command, timeout);
+        self.exec(
+            &format!(
+                "[ \"$(id -u)\" -eq 0 ] && exec {}; exec sudo -S -p '{}' {}",
+                command, SUDO_PROMPT, command
+            ),
+            timeout,
+        )
+    }
+
+    /// Runs command as login user. Returns None if command timed out.
+    pub fn run(&self, command: &str, timeout: Duration) -> Result<Option<Output>, anyhow::Error> {
+        self.exec(&format!("exec {}", timed(command, timeout)), timeout)
+    }
+
+    fn exec(&self, command: &str, timeout: Duration) -> Result<Option<Output>, anyhow::Error> {
+        let mut channel = self.session.channel_session()?;
+        channel.exec(command)?;
+
+        let Some((stdout, stderr, started)) =
+            self.read_sudo(&mut channel, timeout + TIMEOUT_GRACE)?
+        else {
+            let _ = channel.close();
+            return Ok(None);
+        };
+        channel.wait_close()?;
+
+        // Command can exit with 124 itself, but only timeout(1) does so once timeout passed
+        let status = channel.exit_status()?;
+        if status == TIMEOUT_STATUS && started.elapsed() >= timeout {
+            return Ok(None);
+        }
+        Ok(Some(Output {
+            stdout: String::from_utf8_lossy(&stdout).to_string(),
+            stderr: String::from_utf8_lossy(&stderr).replace(SUDO_PROMPT, ""),
+            status,
+        }))
+    }
+
+    /// Login password, or password read from init.password_source the first time sudo needs it.
+    fn password(&self) -> Result<Secret, anyhow::Error> {
+        let mut password = self.password.lock().unwrap();
+        if password.is_none() {
+            *password = Some(self.source.read(&format!(
+                "[sudo] password for {}@{}: ",
+                self.username, self.hostname
+            ))?);
+        }
+        Ok(password.clone().unwrap_or_default())
+    }
+
+    /// Reads stdout and stderr of channel until command ends, answering sudo prompt. Returns
+    /// None if command doesn't end within timeout of starting or of getting password.
+    fn read_sudo(
+        &self,
+        channel: &mut Channel,
+        timeout: Duration,
+    ) -> Result<Option<Streams>, anyhow::Error> {
+        let _non_blocking = NonBlocking::new(&self.session);
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let mut buf = [0; 4096];
+        let mut prompted = false;
+        let mut started = Instant::now();
+
+        loop {
+            let mut read = false;
+            for (stream, out) in [
+                (channel.stream(0), &mut stdout),
+                (channel.stderr(), &mut stderr),
+            ] {
+                let mut stream = stream;
+                match stream.read(&mut buf) {
+                    Ok(n) => {
+                        out.extend_from_slice(&buf[..n]);
+                        read |= n > 0;
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            let prompts = String::from_utf8_lossy(&stderr)
+                .matches(SUDO_PROMPT)
+                .count();
+            if prompts > 1 {
+                return Err(anyhow!("sudo did not accept the password"));
+            }
+            if prompts == 1 && !prompted {
+                prompted = true;
+                let password = self.password()?;
+                write_all(channel, password.expose().as_bytes())?;
+                write_all(channel, b"\n")?;
+                started = Instant::now();
+            }
+
+            if channel.eof() && !read {
+                return Ok(Some((stdout, stderr, started)));
+            }
+            if started.elapsed() >= timeout {
+                return Ok(None);
+            }
+            if !read {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+/// write_all for non-blocking channel or socket.
+fn write_all(writer: &mut impl Write, mut data: &[u8]) -> Result<(), anyhow::Error> {
+    while !data.is_empty() {
+        match writer.write(data) {
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    loop {
+        match writer.flush() {
+            Ok(_) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Command wrapped in timeout(1), which stops it on host once timeout passes.
+fn timed(command: &str, timeout: Duration) -> String {
+    format!(
+        "timeout {} sh -c {}",
+        timeout.as_secs().max(1),
+        shell_quote(command)
+    )
+}
+
+/// Single-quotes value for sh.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
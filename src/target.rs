@@ -0,0 +1,347 @@
+use std::{
+    borrow::Cow,
+    fmt::{self, Display},
+    fs::{self, File},
+    io::{self, Read, Write},
+    os::{fd::AsRawFd, unix::process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
+use schemars::{JsonSchema, Schema, SchemaGenerator};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    config::Init,
+    ssh::{self, Host, POLL_INTERVAL},
+};
+
+/// Directory of named network namespaces made by ip netns add.
+static NETNS_DIR: &str = "/run/netns";
+/// Time commands get on target, unless they need longer
+pub static COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time to compile program on target
+pub static BUILD_TIMEOUT: Duration = Duration::from_secs(300);
+/// Time to install packages on target
+pub static INSTALL_TIMEOUT: Duration = Duration::from_secs(900);
+
+/// Result of command run on target.
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    pub status: i32,
+}
+
+/// Kind of target chosen by init.target. When it is not set, hostname decides.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TargetKind {
+    Local,
+    Ssh,
+    Netns(String),
+}
+
+impl TargetKind {
+    pub fn of(init: &Init) -> TargetKind {
+        match init.target.as_ref() {
+            Some(t) => t.clone(),
+            None if is_local_host(init.hostname.as_deref()) => TargetKind::Local,
+            None => TargetKind::Ssh,
+        }
+    }
+
+    /// Commands of local and namespace targets run in this process, so it has to be root.
+    pub fn is_local(&self) -> bool {
+        *self != TargetKind::Ssh
+    }
+}
+
+impl FromStr for TargetKind {
+    type Err = anyhow::Error;
+
+    /// Kind is read in any case, namespace name as is.
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        let target = target.trim();
+        match target.split_once(':') {
+            Some((kind, name))
+                if kind.eq_ignore_ascii_case("netns")
+                    && !name.trim().is_empty()
+                    && !name.contains('/') =>
+            {
+                Ok(TargetKind::Netns(name.trim().to_string()))
+            }
+            None if target.eq_ignore_ascii_case("local") => Ok(TargetKind::Local),
+            None if target.eq_ignore_ascii_case("ssh") => Ok(TargetKind::Ssh),
+            _ => Err(anyhow!(
+                "unsupported target \"{}\". Expected one of: local, ssh, netns:<name>",
+                target
+            )),
+        }
+    }
+}
+
+impl Display for TargetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetKind::Local => write!(f, "local"),
+            TargetKind::Ssh => write!(f, "ssh"),
+            TargetKind::Netns(name) => write!(f, "netns:{}", name),
+        }
+    }
+}
+
+impl Serialize for TargetKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for TargetKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl JsonSchema for TargetKind {
+    fn schema_name() -> Cow<'static, str> {
+        "TargetKind".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        schemars::json_schema!({
+            "type": "string",
+            "anyOf": [
+                { "enum": ["local", "ssh", "LOCAL", "SSH"] },
+                { "pattern": "^(netns|NETNS):[^/]+$" }
+            ]
+        })
+    }
+}
+
+/// Where commands run: this machine, SSH host or network namespace on this machine. Commands
+/// inspecting or changing the system go through target, so all of them share one code path.
+pub enum Target {
+    Local,
+    Ssh(Arc<Host>),
+    /// Only network namespace is entered, so programs pinned to /sys/fs/bpf stay visible from
+    /// the host.
+    Netns(String),
+}
+
+impl Target {
+    /// Target of init. Local and namespace targets need root, so hpx restarts itself with sudo.
+    /// SSH targets are logged into.
+    pub fn connect(init: &Init, prefix: impl Display) -> Result<Target, anyhow::Error> {
+        let target = match TargetKind::of(init) {
+            TargetKind::Local => {
+                escalate(&prefix);
+                Target::Local
+            }
+            TargetKind::Netns(name) => {
+                if !netns_path(&name).exists() {
+                    return Err(anyhow!(
+                        "Network namespace \"{}\" does not exist. Create it with: ip netns add {}",
+                        name,
+                        name
+                    ));
+                }
+                escalate(&prefix);
+                Target::Netns(name)
+            }
+            TargetKind::Ssh => {
+                if init.hostname.is_none() {
+                    return Err(anyhow!("Target ssh needs init.hostname"));
+                }
+                Target::Ssh(ssh::connect(init, &prefix)?)
+            }
+        };
+
+        if !matches!(target, Target::Local) {
+            println!("{}: Connected to {}\n", prefix, target);
+        }
+        Ok(target)
+    }
+
+    /// Runs shell command as root and fails unless it exits with 0. Error tells command, exit
+    /// status and what command printed.
+    pub fn exec(&self, command: &str, timeout: Duration) -> Result<Output, anyhow::Error> {
+        let output = self.run(command, timeout)?;
+        if output.status == 0 {
+            return Ok(output);
+        }
+
+        // bpftool -j prints errors as JSON to stdout
+        let message = match output.stderr.trim() {
+            "" => output.stdout.trim(),
+            stderr => stderr,
+        };
+        Err(anyhow!(
+            "\"{}\" failed on {} with exit status {}{}",
+            command,
+            self,
+            output.status,
+            if message.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", message)
+            }
+        ))
+    }
+
+    /// Runs shell command as root. Exit status is left to caller. Command is stopped and error
+    /// returned once timeout passes.
+    pub fn run(&self, command: &str, timeout: Duration) -> Result<Output, anyhow::Error> {
+        let output = match self {
+            Target::Ssh(host) => host.sudo(command, timeout)?,
+            Target::Local | Target::Netns(_) => self.spawn(command, timeout)?,
+        };
+        output.with_context(|| {
+            format!(
+                "\"{}\" timed out on {} after {}s",
+                command,
+                self,
+                timeout.as_secs()
+            )
+        })
+    }
+
+    /// Runs command on this machine, in namespace of target. Returns None if it timed out.
+    fn spawn(&self, command: &str, timeout: Duration) -> Result<Option<Output>, anyhow::Error> {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", command])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Own process group, so everything command started is killed on timeout
+            .process_group(0);
+
+        let ns = match self {
+            Target::Netns(name) => Some(
+                File::open(netns_path(name))
+                    .with_context(|| format!("Failed to open network namespace {}", name))?,
+            ),
+            _ => None,
+        };
+        if let Some(ns) = ns.as_ref() {
+            let fd = ns.as_raw_fd();
+            // Runs in forked child right before exec
+            unsafe {
+                cmd.pre_exec(move || match libc::setns(fd, libc::CLONE_NEWNET) {
+                    0 => Ok(()),
+                    _ => Err(io::Error::last_os_error()),
+                });
+            }
+        }
+
+        let child = cmd
+            .spawn()
+            .with_context(|| format!("Failed to run \"{}\"", command))?;
+        wait(child, timeout).with_context(|| format!("Failed to run \"{}\"", command))
+    }
+
+    /// Makes empty file in /tmp that only the user writing files can access. Its name is random,
+    /// so other users can not put a file or symlink there beforehand.
+    pub fn temp_file(&self, prefix: &str) -> Result<String, anyhow::Error> {
+        let command = format!("mktemp /tmp/{}.XXXXXXXX", prefix);
+        let output = match self {
+            // write copies files over SSH as login user, so file has to be theirs
+            Target::Ssh(host) => host
+                .run(&command, COMMAND_TIMEOUT)?
+                .filter(|output| output.status == 0),
+            Target::Local | Target::Netns(_) => Some(self.exec(&command, COMMAND_TIMEOUT)?),
+        };
+        match output.as_ref().map(|output| output.stdout.trim()) {
+            Some(path) if !path.is_empty() => Ok(path.to_string()),
+            _ => Err(anyhow!("Failed to create temporary file on {}", self)),
+        }
+    }
+
+    /// Writes file on target.
+    pub fn write(&self, path: &str, contents: &[u8]) -> Result<(), anyhow::Error> {
+        match self {
+            Target::Ssh(host) => {
+                let mut channel =
+                    host.session
+                        .scp_send(Path::new(path), 0o644, contents.len() as u64, None)?;
+                channel.write_all(contents)?;
+                channel.send_eof()?;
+                channel.wait_eof()?;
+                channel.close()?;
+                channel.wait_close()?;
+            }
+            Target::Local | Target::Netns(_) => fs::write(path, contents)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Local => write!(f, "this machine"),
+            Target::Ssh(host) => write!(f, "{}", host.hostname),
+            Target::Netns(name) => write!(f, "network namespace {}", name),
+        }
+    }
+}
+
+/// Hostname that means this machine.
+pub fn is_local_host(hostname: Option<&str>) -> bool {
+    matches!(hostname, None | Some("localhost") | Some("127.0.0.1"))
+}
+
+fn netns_path(name: &str) -> PathBuf {
+    Path::new(NETNS_DIR).join(name)
+}
+
+/// Waits for child, reading its output. Kills its process group once timeout passes and returns
+/// None then.
+fn wait(mut child: Child, timeout: Duration) -> Result<Option<Output>, anyhow::Error> {
+    // Read in threads, so child doesn't block on full pipe while we wait
+    let stdout = read_in_thread(child.stdout.take());
+    let stderr = read_in_thread(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            unsafe { libc::kill(-(child.id() as i32), libc::SIGKILL) };
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    Ok(Some(Output {
+        stdout: String::from_utf8_lossy(&stdout.join().unwrap_or_default()).to_string(),
+        stderr: String::from_utf8_lossy(&stderr.join().unwrap_or_default()).to_string(),
+        status: status.code().unwrap_or(-1),
+    }))
+}
+
+fn read_in_thread(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut data = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut data);
+        }
+        data
+    })
+}
+
+/// Restarts hpx with sudo unless it is root already.
+fn escalate(prefix: impl Display) {
+    if let sudo::RunningAs::User = sudo::check() {
+        println!("{}: Requesting sudo privileges", prefix);
+        let _ = sudo::with_env(&["HOME"]);
+    }
+}
@@ -1,40 +1,31 @@
 use std::io;
-use std::net::TcpStream;
-use std::sync::Mutex;
-use std::{fs::File, io::Write, path::Path, process::Command};
+use std::io::Write;
 
 use anyhow::{anyhow, Context};
 use crossterm::style::Stylize;
-use ssh2::Session;
 
 use crate::cli::Unload;
-use crate::config::{DEFAULT_NAME, DEFAULT_NET_IFACE};
-use crate::{Config, SSH_PASS, WORKING_DIR};
+use crate::config::{DEFAULT_NAME, DEFAULT_NET_IFACE, DEFAULT_XDP_MODE};
+use crate::fleet;
+use crate::load;
+use crate::ssh::shell_quote;
+use crate::target::{Target, COMMAND_TIMEOUT};
+use crate::Config;
 
 pub fn unload(options: &mut Unload, config: Config) -> Result<(), anyhow::Error> {
-    let hostname = config.init.as_ref().unwrap().hostname.as_ref();
-    let port = config.init.as_ref().unwrap().port.as_ref();
-
     let config_iface = config.init.as_ref().unwrap().iface.as_ref();
-
-    if hostname.is_none()
-        || *hostname.as_ref().unwrap() == "localhost"
-        || *hostname.as_ref().unwrap() == "127.0.0.1"
-    {
-        match sudo::check() {
-            sudo::RunningAs::Root => (),
-            sudo::RunningAs::User => {
-                println!("{}: Requesting sudo privileges", "Unload".red().bold());
-                let _ = sudo::with_env(&["HOME"]);
-            }
-            sudo::RunningAs::Suid => todo!(),
-        }
-    }
+    let target = Target::connect(config.init.as_ref().unwrap(), "Unload".red().bold())?;
 
     if config_iface.is_some()
         && !options.iface.is_empty()
         && *config_iface.unwrap() != options.iface
     {
+        if fleet::is_host() {
+            return Err(anyhow!(
+                "Network interface {} differs from config and can't be confirmed on inventory hosts. Set iface of the host in inventory instead",
+                options.iface
+            ));
+        }
         let mut action = String::new();
         print!(
             "{}: Network interface differs from config. Are you sure you want to proceed? [Y/n] ",
@@ -62,153 +53,48 @@ pub fn unload(options: &mut Unload, config: Config) -> Result<(), anyhow::Error>
         );
         options.iface = String::from(DEFAULT_NET_IFACE);
     }
+    options
+        .xdp_flags
+        .get_or_insert(config.init.as_ref().unwrap().get_xdp_mode());
 
-    if hostname.is_none()
-        || *hostname.as_ref().unwrap() == "localhost"
-        || *hostname.as_ref().unwrap() == "127.0.0.1"
-    {
-        unload_local(options, config)?;
-    } else if hostname.is_some() {
-        let tcp = TcpStream::connect(format!(
-            "{}:{}",
-            hostname.unwrap(),
-            port.unwrap_or(&22)
-        ))
-        .unwrap();
-        let mut session = Session::new().unwrap();
-        session.set_tcp_stream(tcp);
-        session.handshake().unwrap();
-
-        let mut username: String = String::new();
-        if config.init.as_ref().unwrap().username.is_none() {
-            print!("Username: ");
-            io::stdout().flush()?;
-            io::stdin().read_line(&mut username)?;
-        } else {
-            username = config
-                .init
-                .as_ref()
-                .unwrap()
-                .username
-                .as_ref()
-                .unwrap()
-                .to_string();
-            println!("{}: Using username \"{}\"", "Unload".red().bold(), &username);
-        }
-
-        let password: String;
-        unsafe {
-            let pass = (*SSH_PASS.get()).lock().unwrap();
-            if !pass.is_empty() {
-                password = (*pass).clone();
-            } else {
-                password = rpassword::prompt_password("Password: ")?;
-            }
-        }
-
-        session.userauth_password(username.trim(), password.trim())?;
-
-        unsafe {
-            let pass = (*SSH_PASS.get()).lock().unwrap();
-            if pass.is_empty() {
-                let new = SSH_PASS.get().as_mut().unwrap();
-                *new = Mutex::new(password.clone());
-            }
-        }
-
-        println!(
-            "{}: Connected to {}\n",
-            "Unload".red().bold(),
-            hostname.unwrap()
-        );
-
-        unload_remote(options, config, &mut session, &password)?;
-    }
-    Ok(())
-}
-
-fn unload_local(options: &mut Unload, config: Config) -> Result<(), anyhow::Error> {
-    let name = config.init.as_ref().unwrap().name.as_ref();
-    let xdp_flag = match options.xdp_flags.as_ref() {
-        "generic" => "xdpgeneric",
-        "native" => "xdpdrv",
-        "offloaded" => "xdpoffload",
-        _ => "xdpgeneric",
-    };
-
-    Command::new("bpftool")
-        .arg("net")
-        .arg("detach")
-        .arg(xdp_flag)
-        .arg("dev")
-        .arg(&options.iface)
-        .output()?;
-
-    Command::new("rm")
-        .arg(format!("/sys/fs/bpf/{}", &name.unwrap_or(&DEFAULT_NAME.to_string())).as_str())
-        .output()?;
-
-    let p = format!(
-        "{}/data",
-        WORKING_DIR
-            .to_str()
-            .with_context(|| "Failed to parse HOME directory")?,
-    );
-    let path = Path::new(&p);
-
-    if !path.exists() {
-        return Err(anyhow!("No programs loaded"));
-    }
-
-    let mut loaded_progs;
-    let p = format!(
-        "{}/data/progs.json",
-        WORKING_DIR
-            .to_str()
-            .with_context(|| "Failed to parse HOME directory")?,
-    );
-    let path = Path::new(&p);
-
-    loaded_progs = File::create(path)?;
-    let json_data = serde_json::to_string("{}")?;
-    loaded_progs.write_all(json_data.as_bytes())?;
-
-    Ok(())
+    unload_target(options, config, &target)
 }
 
-fn unload_remote(
+fn unload_target(
     options: &mut Unload,
     config: Config,
-    session: &mut Session,
-    password: &str,
+    target: &Target,
 ) -> Result<(), anyhow::Error> {
-    let name = config.init.as_ref().unwrap().name.as_ref().unwrap();
-    let xdp_flag = match options.xdp_flags.as_ref() {
-        "generic" => "xdpgeneric",
-        "native" => "xdpdrv",
-        "offloaded" => "xdpoffload",
-        _ => "xdpgeneric",
-    };
+    let name = config
+        .init
+        .as_ref()
+        .unwrap()
+        .name
+        .as_deref()
+        .unwrap_or(DEFAULT_NAME);
+    let xdp_flag = options.xdp_flags.unwrap_or(DEFAULT_XDP_MODE).bpftool_flag();
 
     println!("{}: Detaching program...", "Unload".red().bold());
-    let mut channel = session.channel_session()?;
-    channel.exec(
-        format!(
-            "echo {} | sudo -S bpftool net detach {} dev {}",
-            password, xdp_flag, options.iface
+    target
+        .exec(
+            &format!(
+                "bpftool net detach {} dev {}",
+                xdp_flag,
+                shell_quote(&options.iface)
+            ),
+            COMMAND_TIMEOUT,
         )
-        .as_str(),
-    )?;
+        .with_context(|| format!("Failed to detach program from {}", options.iface))?;
 
     println!("{}: Unloading the program...", "Unload".red().bold());
-    channel = session.channel_session()?;
-    channel.exec(
-        format!(
-            "echo {} | sudo -S rm /sys/fs/bpf/{}",
-            password, name
+    target
+        .exec(
+            &format!("rm {}", shell_quote(&format!("/sys/fs/bpf/{}", name))),
+            COMMAND_TIMEOUT,
         )
-        .as_str(),
-    )?;
+        .with_context(|| format!("Failed to unload program {}", name))?;
+
+    load::forget_loaded(name)?;
 
     Ok(())
 }
@@ -0,0 +1,452 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    net::Ipv4Addr,
+    path::Path,
+};
+
+use anyhow::anyhow;
+use crossterm::style::Stylize;
+
+use crate::{
+    config::{Config, Init, List, ListAction, ProgType, Schedule, DEFAULT_NET_IFACE},
+    geo::{self, GeoEntry},
+    schedule, ssh,
+    target::TargetKind,
+};
+
+// Kernel truncates program names to 15 characters. Loader looks programs up by name.
+static MAX_NAME_LEN: usize = 15;
+
+/// Single problem in config. Path points to offending field, f.e. init.graylist.action
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.as_str().bold(), self.message)
+    }
+}
+
+struct Issues(Vec<Issue>);
+
+// List name, its data entries and its settings
+type ListData<'a> = (&'a str, Option<&'a Vec<String>>, Option<&'a dyn List>);
+
+impl Issues {
+    fn push(&mut self, path: impl Into<String>, message: impl Into<String>) {
+        self.0.push(Issue {
+            path: path.into(),
+            message: message.into(),
+        });
+    }
+}
+
+/// Collects every problem found in config. Empty if config is valid.
+pub fn validate(config: &Config) -> Vec<Issue> {
+    let mut issues = Issues(Vec::new());
+
+    if config.programs.as_ref().is_none_or(|p| p.is_empty()) {
+        validate_program(config, &mut issues);
+        return issues.0;
+    }
+
+    let programs = match config.programs() {
+        Ok(p) => p,
+        Err(e) => {
+            issues.push("programs", format!("{:#}", e));
+            return issues.0;
+        }
+    };
+
+    // Program name and (host, interface) pairs already taken, with index of program
+    let mut names: HashMap<&str, usize> = HashMap::new();
+    let mut ifaces: HashMap<(&str, &str), usize> = HashMap::new();
+    for (i, p) in programs.iter().enumerate() {
+        let mut program = Issues(Vec::new());
+        validate_program(p, &mut program);
+        for issue in program.0 {
+            issues.push(format!("programs[{}].{}", i, issue.path), issue.message);
+        }
+
+        if let Some(other) = names.insert(p.name(), i) {
+            issues.push(
+                format!("programs[{}].init.name", i),
+                format!("\"{}\" is already used by programs[{}]", p.name(), other),
+            );
+        }
+
+        let init = match p.init.as_ref() {
+            Some(i) => i,
+            None => continue,
+        };
+        let host = init.hostname.as_deref().unwrap_or("localhost");
+        let iface = init.iface.as_deref().unwrap_or(DEFAULT_NET_IFACE);
+        if let Some(other) = ifaces.insert((host, iface), i) {
+            issues.push(
+                format!("programs[{}].init.iface", i),
+                format!(
+                    "{} on {} is already used by programs[{}]. Only one XDP program can be attached to an interface",
+                    iface, host, other
+                ),
+            );
+        }
+    }
+
+    issues.0
+}
+
+fn validate_program(config: &Config, issues: &mut Issues) {
+    let init = match config.init.as_ref() {
+        Some(i) => i,
+        None => {
+            issues.push("init", "section is missing");
+            return;
+        }
+    };
+
+    validate_init(init, issues);
+    validate_data(config, init, issues);
+}
+
+/// Fails with all problems listed if config is invalid.
+pub fn check(config: &Config) -> Result<(), anyhow::Error> {
+    let issues = validate(config);
+    if issues.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "Invalid configuration:\n{}",
+        issues
+            .iter()
+            .map(|i| format!("  {}", i))
+            .collect::<Vec<String>>()
+            .join("\n")
+    ))
+}
+
+/// hpx config validate
+pub fn config_validate(config: &Config) -> Result<(), anyhow::Error> {
+    let issues = validate(config);
+    if issues.is_empty() {
+        println!("{}: Configuration is valid", "Validate".blue().bold());
+        return Ok(());
+    }
+
+    for i in &issues {
+        println!("{}: {}", "Validate".blue().bold(), i);
+    }
+    Err(anyhow!(
+        "Found {} problem(s) in configuration",
+        issues.len()
+    ))
+}
+
+fn validate_init(init: &Init, issues: &mut Issues) {
+    if let Some(name) = init.name.as_ref() {
+        let name = name.replace(" ", "");
+        if name.is_empty()
+            || name.starts_with(|c: char| c.is_ascii_digit())
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            issues.push(
+                "init.name",
+                format!(
+                    "\"{}\" is not a valid program name. Use letters, digits and _",
+                    name
+                ),
+            );
+        } else if name.len() > MAX_NAME_LEN {
+            issues.push(
+                "init.name",
+                format!("\"{}\" is longer than {} characters", name, MAX_NAME_LEN),
+            );
+        }
+    }
+
+    if TargetKind::of(init) == TargetKind::Ssh && init.hostname.is_none() {
+        issues.push("init.target", "ssh target needs init.hostname");
+    }
+    if init.port == Some(0) {
+        issues.push("init.port", "must be between 1 and 65535");
+    }
+    if init.ssh_auth.as_ref().is_some_and(|a| a.is_empty()) {
+        issues.push("init.ssh_auth", "must list at least one method");
+    }
+    if let Some(f) = init.host_key_fingerprint.as_ref() {
+        if !f.trim().starts_with("SHA256:") {
+            issues.push(
+                "init.host_key_fingerprint",
+                "must be a SHA256 fingerprint as printed by ssh-keygen -lf, f.e. SHA256:...",
+            );
+        }
+    }
+    if let Some(f) = init.identity_file.as_ref() {
+        if !ssh::expand_home(f).exists() {
+            issues.push("init.identity_file", format!("{} does not exist", f));
+        }
+    }
+    for (i, hop) in init.proxy_jump.iter().flatten().enumerate() {
+        let path = format!("init.proxy_jump[{}]", i);
+        match hop.host() {
+            Ok(h) => {
+                if h.port == Some(0) {
+                    issues.push(&path, "port must be between 1 and 65535");
+                }
+                if h.ssh_auth.as_ref().is_some_and(|a| a.is_empty()) {
+                    issues.push(&path, "ssh_auth must list at least one method");
+                }
+                if h.host_key_fingerprint
+                    .as_ref()
+                    .is_some_and(|f| !f.trim().starts_with("SHA256:"))
+                {
+                    issues.push(&path, "host_key_fingerprint must be a SHA256 fingerprint");
+                }
+                if let Some(f) = h.identity_file.as_ref() {
+                    if !ssh::expand_home(f).exists() {
+                        issues.push(&path, format!("{} does not exist", f));
+                    }
+                }
+            }
+            Err(e) => issues.push(&path, e.to_string()),
+        }
+    }
+    if init.proxy_jump.is_some() && TargetKind::of(init).is_local() {
+        issues.push("init.proxy_jump", "only used by ssh target");
+    }
+
+    let prog_type = init.get_prog_type();
+
+    if let Some(w) = init.whitelist.as_ref() {
+        validate_list(
+            "init.whitelist",
+            w,
+            &[ListAction::Allow, ListAction::Deny],
+            issues,
+        );
+    }
+    if let Some(b) = init.blacklist.as_ref() {
+        validate_list(
+            "init.blacklist",
+            b,
+            &[ListAction::Allow, ListAction::Deny],
+            issues,
+        );
+    }
+    if let Some(g) = init.graylist.as_ref() {
+        validate_list("init.graylist", g, ListAction::ALL, issues);
+        if g.frequency == Some(0) {
+            issues.push("init.graylist.frequency", "must be greater than 0");
+        }
+    }
+
+    if let Some(bruteforce) = init.bruteforce.as_ref() {
+        if !bruteforce.is_empty() && prog_type != ProgType::Ip {
+            issues.push("init.bruteforce", "only supported by ip program type");
+        }
+        if !bruteforce.is_empty() && !init.blacklist_enabled() {
+            issues.push(
+                "init.bruteforce",
+                "needs init.blacklist to be enabled, sources are added to it",
+            );
+        }
+        for (i, b) in bruteforce.iter().enumerate() {
+            if b.port == 0 {
+                issues.push(
+                    format!("init.bruteforce[{}].port", i),
+                    "must be between 1 and 65535",
+                );
+            }
+            if b.window == Some(0) {
+                issues.push(
+                    format!("init.bruteforce[{}].window", i),
+                    "must be greater than 0",
+                );
+            }
+        }
+    }
+
+    if let Some(a) = init.amplification.as_ref() {
+        if prog_type != ProgType::Udp {
+            issues.push("init.amplification", "only supported by udp program type");
+        }
+        for (i, p) in a.get_ports().iter().enumerate() {
+            if *p == 0 {
+                issues.push(
+                    format!("init.amplification.ports[{}]", i),
+                    "must be between 1 and 65535",
+                );
+            }
+        }
+        match a.get_action() {
+            ListAction::Deny if !init.blacklist_enabled() => issues.push(
+                "init.amplification.action",
+                "deny needs init.blacklist to be enabled",
+            ),
+            ListAction::Investigate if !init.graylist_enabled() => issues.push(
+                "init.amplification.action",
+                "investigate needs init.graylist to be enabled",
+            ),
+            ListAction::Deny | ListAction::Investigate => (),
+            action => issues.push(
+                "init.amplification.action",
+                format!(
+                    "unsupported action \"{}\". Expected deny or investigate",
+                    action
+                ),
+            ),
+        }
+        if a.window == Some(0) {
+            issues.push("init.amplification.window", "must be greater than 0");
+        }
+    }
+
+    if let Some(a) = init.scheduled_action.as_ref() {
+        validate_schedule("init.scheduled_action.schedule", &a.schedule, issues);
+    }
+
+    if let Some(f) = init.fill_warning {
+        if f == 0 || f > 100 {
+            issues.push("init.fill_warning", "must be between 1 and 100");
+        }
+    }
+
+    for (i, m) in init.mmdb.iter().flatten().enumerate() {
+        if !Path::new(m).exists() {
+            issues.push(
+                format!("init.mmdb[{}]", i),
+                format!("file {} does not exist", m),
+            );
+        }
+    }
+}
+
+fn validate_list(path: &str, list: &impl List, actions: &[ListAction], issues: &mut Issues) {
+    if !actions.contains(&list.get_action()) {
+        issues.push(
+            format!("{}.action", path),
+            format!(
+                "unsupported action \"{}\". Expected one of: {}",
+                list.get_action(),
+                actions
+                    .iter()
+                    .map(|a| a.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            ),
+        );
+    }
+    if list.get_max() == 0 {
+        issues.push(format!("{}.max", path), "must be greater than 0");
+    }
+    if let Some(s) = list.get_schedule() {
+        validate_schedule(&format!("{}.schedule", path), s, issues);
+    }
+}
+
+fn validate_schedule(path: &str, schedule: &Schedule, issues: &mut Issues) {
+    if let Err(e) = schedule::parse_timezone(schedule) {
+        issues.push(format!("{}.timezone", path), e.to_string());
+    }
+    if schedule.windows.is_empty() {
+        issues.push(
+            format!("{}.windows", path),
+            "at least one window is required",
+        );
+    }
+    for (i, w) in schedule.windows.iter().enumerate() {
+        if let Err(e) = schedule::parse_time(&w.start) {
+            issues.push(format!("{}.windows[{}].start", path, i), e.to_string());
+        }
+        if let Err(e) = schedule::parse_time(&w.end) {
+            issues.push(format!("{}.windows[{}].end", path, i), e.to_string());
+        }
+        if let Err(e) = schedule::parse_days(w) {
+            issues.push(format!("{}.windows[{}].days", path, i), e.to_string());
+        }
+    }
+}
+
+fn validate_data(config: &Config, init: &Init, issues: &mut Issues) {
+    let data = match config.data.as_ref() {
+        Some(d) => d,
+        None => return,
+    };
+
+    let lists: [ListData; 3] = [
+        (
+            "whitelist",
+            data.whitelist.as_ref(),
+            init.whitelist.as_ref().map(|l| l as &dyn List),
+        ),
+        (
+            "blacklist",
+            data.blacklist.as_ref(),
+            init.blacklist.as_ref().map(|l| l as &dyn List),
+        ),
+        (
+            "graylist",
+            data.graylist.as_ref(),
+            init.graylist.as_ref().map(|l| l as &dyn List),
+        ),
+    ];
+
+    let mut has_geo = false;
+    for (name, entries, list) in lists {
+        let entries = match entries {
+            Some(e) => e,
+            None => continue,
+        };
+
+        let mut addresses = 0;
+        for (i, e) in entries.iter().enumerate() {
+            let path = format!("data.{}[{}]", name, i);
+            if geo::is_geo_entry(e) {
+                has_geo = true;
+                if name == "graylist" {
+                    issues.push(path, "graylist does not support country/ASN entries");
+                } else if let Err(err) = e.parse::<GeoEntry>() {
+                    issues.push(path, err.to_string());
+                }
+                continue;
+            }
+
+            addresses += 1;
+            if e.trim().parse::<Ipv4Addr>().is_err() {
+                issues.push(path, format!("\"{}\" is not a valid IPv4 address", e));
+            }
+        }
+
+        if let Some(l) = list {
+            if addresses > l.get_max() as usize {
+                issues.push(
+                    format!("init.{}.max", name),
+                    format!(
+                        "{} is below number of addresses in data.{} ({})",
+                        l.get_max(),
+                        name,
+                        addresses
+                    ),
+                );
+            }
+        }
+    }
+
+    if has_geo && init.mmdb.as_ref().is_none_or(|m| m.is_empty()) {
+        issues.push("init.mmdb", "required by country/ASN entries in data");
+    }
+
+    let whitelisted: HashSet<&str> = data.whitelist.iter().flatten().map(|e| e.trim()).collect();
+    for (i, e) in data.blacklist.iter().flatten().enumerate() {
+        if whitelisted.contains(e.trim()) {
+            issues.push(
+                format!("data.blacklist[{}]", i),
+                format!("{} is also in data.whitelist", e.trim()),
+            );
+        }
+    }
+}